@@ -4,7 +4,7 @@
 //! Orphan ruleを回避するためにnewtypeパターンを使用。
 
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -41,16 +41,32 @@ impl IntoResponse for ApiError {
         let status = match &inner {
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::ContentBlocked { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::Database(e) => {
                 error!("Database error: {:?}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
             }
             AppError::ExternalApi(e) => {
                 error!("External API error: {:?}", e);
-                StatusCode::BAD_GATEWAY
+                match e {
+                    OpenAIError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+                    OpenAIError::QuotaExceeded(_) => StatusCode::SERVICE_UNAVAILABLE,
+                    OpenAIError::InvalidRequest { .. }
+                    | OpenAIError::ContextLengthExceeded(_) => StatusCode::BAD_REQUEST,
+                    OpenAIError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                    OpenAIError::Refusal(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                    _ => StatusCode::BAD_GATEWAY,
+                }
             }
         };
 
+        // レート制限時は上流の待機時間を Retry-After（秒、切り上げ）として伝える
+        let retry_after = match &inner {
+            AppError::ExternalApi(e) => e.retry_after(),
+            _ => None,
+        }
+        .map(|d| d.as_secs() + u64::from(d.subsec_nanos() > 0));
+
         // 構造化されたエラーレスポンス
         let body = serde_json::json!({
             "error": {
@@ -59,6 +75,12 @@ impl IntoResponse for ApiError {
             }
         });
 
-        (status, Json(body)).into_response()
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
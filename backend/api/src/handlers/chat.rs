@@ -1,8 +1,10 @@
 use axum::{extract::State, Json};
 use tracing::info;
 
-use backend_core::models::{ChatRequest, ChatResponse};
+use backend_core::models::{ChatRequest, ChatResponse, Message};
+use backend_core::moderation::ModerationStage;
 use crate::error::ApiError;
+use crate::handlers::costs::{moderate, record_cost};
 use crate::handlers::AppState;
 
 /// POST /chat ハンドラー
@@ -12,11 +14,42 @@ pub async fn chat(
 ) -> Result<Json<ChatResponse>, ApiError> {
     info!("Chat request received");
 
-    let response = state.openai.chat(request).await?;
+    // サンプリング・生成のパラメーターを検証
+    request.generation.validate()?;
+
+    // モデルを検証（未指定ならデフォルト）
+    let model = state.llm.resolve_model(request.model.as_deref())?;
+
+    // コンテキストウィンドウを超える入力は、モデレーションを含めて API を呼ばずに拒否
+    let messages = [Message {
+        role: "user".to_string(),
+        content: request.message.clone(),
+    }];
+    state
+        .tokenizer
+        .check_context_window(&model, &messages, request.system_prompt.as_deref())?;
+
+    // モデレーションでブロックした入力は API を呼ばずに拒否
+    moderate(
+        &state,
+        None,
+        ModerationStage::Input,
+        &request.message.text(),
+    )
+    .await?;
+
+    let request = ChatRequest {
+        model: Some(model),
+        ..request
+    };
+
+    let mut response = state.llm.chat(request).await?;
+    response.cost_usd = record_cost(&state, None, &response).await;
+    moderate(&state, None, ModerationStage::Output, &response.response).await?;
 
     info!(
-        "Chat response sent (tokens: {})",
-        response.usage.total_tokens
+        "Chat response sent (tokens: {}, cached: {}, cost: {:?})",
+        response.usage.total_tokens, response.cached, response.cost_usd
     );
     Ok(Json(response))
 }
@@ -0,0 +1,173 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{Days, Utc};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use backend_core::models::{
+    ChatResponse, CostSummary, DailyCostQuery, DailyCostResponse, ModerationFlags, SessionCost,
+    Usage,
+};
+use backend_core::moderation::ModerationStage;
+use backend_core::AppError;
+use crate::error::ApiError;
+use crate::handlers::AppState;
+
+/// 日ごとの料金の期間のデフォルト（日数）
+const DEFAULT_DAYS: u64 = 30;
+/// 日ごとの料金の期間の上限（日数）
+const MAX_DAYS: i64 = 366;
+
+/// レスポンスの料金を計算して使用量と一緒に記録し、料金を返す
+///
+/// 応答キャッシュから返したレスポンスは API を呼んでいないため、記録せずに 0 を返す。
+/// 記録の失敗はログに記録するだけで、チャットには影響しない。
+pub(crate) async fn record_cost(
+    state: &AppState,
+    session_id: Option<Uuid>,
+    response: &ChatResponse,
+) -> Option<f64> {
+    if response.cached {
+        return Some(0.0);
+    }
+
+    record_usage(state, session_id, &response.model, &response.usage).await
+}
+
+/// API の呼び出し（チャット・埋め込み・モデレーション）の料金を計算して使用量と一緒に記録し、料金を返す
+///
+/// 記録の失敗はログに記録するだけで、呼び出し元には影響しない。
+pub(crate) async fn record_usage(
+    state: &AppState,
+    session_id: Option<Uuid>,
+    model: &str,
+    usage: &Usage,
+) -> Option<f64> {
+    let cost = state.prices.cost(model, usage);
+    if cost.is_none() {
+        warn!("No price for model {}, cost is not counted", model);
+    }
+
+    if let Err(e) = state
+        .session_repo
+        .record_usage(session_id, model, usage, cost)
+        .await
+    {
+        warn!("Failed to record usage: {}", e);
+    }
+
+    cost
+}
+
+/// テキストをモデレーションで確認し、Moderations API を呼んだ場合は呼び出しを使用量に記録する
+///
+/// Moderations API はトークン使用量を返さないため、トークン数 0 の呼び出しとして記録する。
+pub(crate) async fn moderate(
+    state: &AppState,
+    session_id: Option<Uuid>,
+    stage: ModerationStage,
+    text: &str,
+) -> Result<Option<ModerationFlags>, AppError> {
+    let mut called = None;
+    let result = moderate_unrecorded(state, stage, text, &mut called).await;
+    record_moderation(state, session_id, called).await;
+    result
+}
+
+/// テキストをモデレーションで確認する（使用量は記録しない）
+///
+/// Moderations API を呼んだ場合はそのモデルを `called` に残す。
+/// ストリーミングの途中の確認のように、複数の呼び出しを `record_moderation` でまとめて記録するときに使う。
+pub(crate) async fn moderate_unrecorded<'a>(
+    state: &'a AppState,
+    stage: ModerationStage,
+    text: &str,
+    called: &mut Option<&'a str>,
+) -> Result<Option<ModerationFlags>, AppError> {
+    let result = state.moderator.check(state.llm.as_ref(), stage, text).await;
+
+    // API の呼び出しに失敗した場合は数えない（ブロックした場合は数える）
+    if let Some(model) = state.moderator.api_model(text)
+        && !matches!(result, Err(AppError::ExternalApi(_)))
+    {
+        *called = Some(model);
+    }
+
+    result
+}
+
+/// `moderate_unrecorded` で呼んだ Moderations API を1件の使用量として記録する
+pub(crate) async fn record_moderation(
+    state: &AppState,
+    session_id: Option<Uuid>,
+    called: Option<&str>,
+) {
+    if let Some(model) = called {
+        record_usage(state, session_id, model, &Usage::default()).await;
+    }
+}
+
+/// GET /sessions/{id}/cost - セッションの使用量と料金（モデルごとの内訳付き）
+pub async fn get_session_cost(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionCost>, ApiError> {
+    info!("Getting session cost: {}", id);
+
+    state
+        .session_repo
+        .get_session(id)
+        .await?
+        .ok_or_else(|| ApiError::from(AppError::NotFound("Session".to_string())))?;
+
+    let models = state.session_repo.get_session_costs(id).await?;
+    let mut total = CostSummary::default();
+    for model in &models {
+        total.add(&model.summary);
+    }
+
+    Ok(Json(SessionCost {
+        session_id: id,
+        total,
+        models,
+    }))
+}
+
+/// GET /costs/daily?from=&to= - 全体の日ごとの使用量と料金（日付は UTC）
+///
+/// セッション外の呼び出し（`POST /chat`）や削除したセッションの呼び出しも含む。
+pub async fn get_daily_costs(
+    State(state): State<AppState>,
+    Query(query): Query<DailyCostQuery>,
+) -> Result<Json<DailyCostResponse>, ApiError> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Days::new(DEFAULT_DAYS - 1));
+
+    if from > to {
+        return Err(AppError::Validation("from must not be after to".to_string()).into());
+    }
+    if (to - from).num_days() >= MAX_DAYS {
+        return Err(
+            AppError::Validation(format!("The range must be at most {} days", MAX_DAYS)).into(),
+        );
+    }
+
+    info!("Getting daily costs: {} - {}", from, to);
+
+    let days = state.session_repo.get_daily_costs(from, to).await?;
+    let mut total = CostSummary::default();
+    for day in &days {
+        total.add(&day.summary);
+    }
+
+    Ok(Json(DailyCostResponse {
+        from,
+        to,
+        days,
+        total,
+    }))
+}
@@ -1,9 +1,19 @@
 // HTTPハンドラー（コントローラー相当）
 
 pub mod chat;
+pub mod costs;
 pub mod health;
+pub mod multipart;
+pub mod search;
 pub mod session;
+pub mod ws;
 
 pub use chat::chat;
+pub use costs::{get_daily_costs, get_session_cost};
 pub use health::health_check;
-pub use session::{create_session, delete_session, get_session, session_chat, AppState};
+pub use search::search;
+pub use session::{
+    create_session, delete_session, get_session, session_chat, session_chat_stream,
+    update_message, update_session, AppState,
+};
+pub use ws::session_ws;
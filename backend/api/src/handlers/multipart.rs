@@ -0,0 +1,159 @@
+use axum::{
+    extract::{multipart::Field, FromRequest, Multipart, Request},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use backend_core::models::{
+    ContentPart, GenerationOptions, MessageContent, ReasoningOptions, SessionChatRequest,
+};
+use backend_core::AppError;
+use crate::error::ApiError;
+
+/// セッション内チャットの入力（JSON または multipart/form-data）
+///
+/// multipart の場合、`message` フィールドをテキスト、ファイル名付きのフィールドを
+/// 画像（`image/*`）またはファイルとして1つのメッセージにまとめる。
+/// 推論の設定は `reasoning_effort` / `reasoning_summary` フィールドで指定する。
+/// `bypass_cache` フィールドが `true` なら応答キャッシュを読まない。
+/// サンプリング・生成のパラメーターは JSON と同じ名前のフィールドで指定する
+/// （`metadata` は JSON オブジェクトの文字列）。
+pub struct ChatInput(pub SessionChatRequest);
+
+/// multipart で受け付けるサンプリング・生成のパラメーター（`GenerationOptions` のフィールド）
+const GENERATION_FIELDS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "max_output_tokens",
+    "truncation",
+    "parallel_tool_calls",
+    "store",
+    "metadata",
+    "verbosity",
+];
+
+impl<S: Send + Sync> FromRequest<S> for ChatInput {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/form-data"));
+
+        if !is_multipart {
+            let Json(request) = Json::<SessionChatRequest>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(ChatInput(request));
+        }
+
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let mut text = None;
+        let mut files = Vec::new();
+        let mut reasoning = ReasoningOptions::default();
+        let mut bypass_cache = false;
+        let mut generation = Map::new();
+
+        while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+            match field.file_name().map(str::to_string) {
+                Some(filename) => {
+                    let content_type = field
+                        .content_type()
+                        .unwrap_or("application/octet-stream")
+                        .to_string();
+                    let data = field.bytes().await.map_err(invalid)?;
+                    files.push(ContentPart::from_bytes(Some(filename), &content_type, &data));
+                }
+                None if field.name() == Some("message") => {
+                    text = Some(field.text().await.map_err(invalid)?);
+                }
+                None if field.name() == Some("reasoning_effort") => {
+                    reasoning.effort = Some(parse_option(field).await?);
+                }
+                None if field.name() == Some("reasoning_summary") => {
+                    reasoning.summary = Some(parse_option(field).await?);
+                }
+                None if field.name() == Some("bypass_cache") => {
+                    bypass_cache = field.text().await.map_err(invalid)?.trim() == "true";
+                }
+                None if field
+                    .name()
+                    .is_some_and(|name| GENERATION_FIELDS.contains(&name)) =>
+                {
+                    let name = field.name().unwrap_or_default().to_string();
+                    let text = field.text().await.map_err(invalid)?;
+                    // 数値・真偽値・オブジェクトは JSON として、それ以外は文字列として読む
+                    let value = serde_json::from_str(&text).unwrap_or(Value::String(text));
+                    generation.insert(name, value);
+                }
+                // 未知のフィールドは無視する
+                None => {}
+            }
+        }
+
+        // テキストを先頭に置く
+        let parts: Vec<ContentPart> = text
+            .filter(|t| !t.trim().is_empty())
+            .map(|text| ContentPart::InputText { text })
+            .into_iter()
+            .chain(files)
+            .collect();
+
+        if parts.is_empty() {
+            return Err(ApiError::from(AppError::Validation(
+                "Multipart request requires a 'message' field or at least one file".to_string(),
+            ))
+            .into_response());
+        }
+
+        let reasoning = (reasoning.effort.is_some() || reasoning.summary.is_some())
+            .then_some(reasoning);
+
+        let generation: GenerationOptions = serde_json::from_value(Value::Object(generation))
+            .map_err(|e| {
+                ApiError::from(AppError::Validation(format!(
+                    "Invalid generation parameter: {}",
+                    e
+                )))
+                .into_response()
+            })?;
+
+        Ok(ChatInput(SessionChatRequest {
+            message: MessageContent::Parts(parts),
+            reasoning,
+            bypass_cache,
+            generation,
+        }))
+    }
+}
+
+/// 列挙値のフィールド（`"low"` など）をパース
+async fn parse_option<T: DeserializeOwned>(field: Field<'_>) -> Result<T, Response> {
+    let name = field.name().unwrap_or_default().to_string();
+    let value = field.text().await.map_err(invalid)?;
+
+    serde_json::from_value(Value::String(value.clone())).map_err(|_| {
+        ApiError::from(AppError::Validation(format!(
+            "Invalid value for '{}': {}",
+            name, value
+        )))
+        .into_response()
+    })
+}
+
+/// multipart の読み取りエラーをバリデーションエラーに変換
+fn invalid(err: axum::extract::multipart::MultipartError) -> Response {
+    ApiError::from(AppError::Validation(format!(
+        "Invalid multipart request: {}",
+        err.body_text()
+    )))
+    .into_response()
+}
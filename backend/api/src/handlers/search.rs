@@ -0,0 +1,51 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use tracing::info;
+
+use backend_core::models::{SearchQuery, SearchResponse};
+use backend_core::AppError;
+use crate::error::ApiError;
+use crate::handlers::costs::record_usage;
+use crate::handlers::AppState;
+
+/// 返す件数のデフォルト
+const DEFAULT_LIMIT: usize = 10;
+/// 返す件数の上限
+const MAX_LIMIT: usize = 50;
+
+/// GET /search?q= - 全セッションのメッセージを意味で検索
+///
+/// クエリを埋め込み、保存済みのメッセージの埋め込みとのコサイン類似度が高い順に返す。
+pub async fn search(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let model = state.embedding_model.as_deref().ok_or_else(|| {
+        AppError::Validation("Semantic search is disabled (EMBEDDING_MODEL is not set)".to_string())
+    })?;
+
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(AppError::Validation("q must not be empty".to_string()).into());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    info!("Searching messages: {}", q);
+
+    let embeddings = state.llm.embed(model, &[q.to_string()]).await?;
+    record_usage(&state, None, model, &embeddings.usage).await;
+    let embedding = embeddings.vectors.into_iter().next().unwrap_or_default();
+    let results = state
+        .session_repo
+        .search_messages(model, &embedding, limit)
+        .await?;
+
+    info!("Search completed: {} results", results.len());
+
+    Ok(Json(SearchResponse {
+        query: q.to_string(),
+        results,
+    }))
+}
@@ -1,23 +1,52 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
 use axum::{
     extract::{Path, State},
     http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
     Json,
 };
-use tracing::info;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use backend_core::{AppError, OpenAIService, SessionRepository};
+use backend_core::history::{self, HistoryInput};
+use backend_core::{AppError, LlmProvider, Moderator, SessionRepository, Tokenizer};
+use backend_core::moderation::ModerationStage;
+use backend_core::pricing::PriceTable;
+use backend_core::db::NewMessage;
 use backend_core::models::{
-    CreateSessionRequest, CreateSessionResponse, Message, SessionChatRequest, SessionChatResponse,
-    SessionWithMessages,
+    ChatMessage, ChatResponse, ChatStreamEvent, CreateSessionRequest, CreateSessionResponse,
+    ChatOptions, HistoryMode, HistoryStrategy, Message, MessageContent, ModerationFlags,
+    PromptCacheStats, Session, SessionChatRequest, SessionChatResponse, SessionChatStreamEvent,
+    SessionSummary, SessionWithMessages, UpdateMessageRequest, UpdateSessionRequest,
 };
+use backend_core::services::{ChatStream, OpenAIError};
 use crate::error::ApiError;
+use crate::handlers::costs::{
+    moderate, moderate_unrecorded, record_cost, record_moderation, record_usage,
+};
+use crate::handlers::multipart::ChatInput;
 
 /// アプリケーション共有状態
 #[derive(Clone)]
 pub struct AppState {
-    pub openai: OpenAIService,
+    /// LLM プロバイダー（設定で Responses API / Chat Completions 互換 API を切り替える）
+    pub llm: Arc<dyn LlmProvider>,
     pub session_repo: SessionRepository,
+    /// 送信前のトークン数の確認に使用
+    pub tokenizer: Tokenizer,
+    /// メッセージの埋め込みに使うモデル（None なら埋め込み・意味検索を行わない）
+    pub embedding_model: Option<String>,
+    /// 入力・出力のモデレーション（デフォルトは無効）
+    pub moderator: Moderator,
+    /// 料金の計算に使うモデルごとの単価
+    pub prices: PriceTable,
 }
 
 /// POST /sessions - 新規セッション作成
@@ -27,9 +56,20 @@ pub async fn create_session(
 ) -> Result<Json<CreateSessionResponse>, ApiError> {
     info!("Creating new session");
 
+    // モデルを検証し、解決後のモデルを保存する（以降のターンで使い続ける）
+    let model = state.llm.resolve_model(request.model.as_deref())?;
+    history::validate_strategy(&request.history_strategy)?;
+    request.generation.validate()?;
+
     let session = state
         .session_repo
-        .create_session(request.system_prompt)
+        .create_session(
+            request.system_prompt,
+            Some(model),
+            request.history_mode,
+            &request.history_strategy,
+            &request.generation,
+        )
         .await?;
 
     info!("Session created: {}", session.id);
@@ -37,10 +77,65 @@ pub async fn create_session(
     Ok(Json(CreateSessionResponse {
         id: session.id,
         system_prompt: session.system_prompt,
+        model: session.model,
+        history_mode: session.history_mode,
+        history_strategy: session.history_strategy.0,
+        generation: session.generation.0,
         created_at: session.created_at,
     }))
 }
 
+/// PATCH /sessions/{id} - セッション設定の更新
+pub async fn update_session(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<UpdateSessionRequest>,
+) -> Result<Json<Session>, ApiError> {
+    info!("Updating session: {}", id);
+
+    let not_found = || ApiError::from(AppError::NotFound("Session".to_string()));
+
+    // 一部だけ更新しないよう、先にすべて検証する
+    if let Some(strategy) = &request.history_strategy {
+        history::validate_strategy(strategy)?;
+    }
+    if let Some(generation) = &request.generation {
+        generation.validate()?;
+    }
+
+    let session = if request.history_strategy.is_none() && request.generation.is_none() {
+        state.session_repo.get_session(id).await?
+    } else {
+        state
+            .session_repo
+            .update_session(
+                id,
+                request.history_strategy.as_ref(),
+                request.generation.as_ref(),
+            )
+            .await?
+    };
+
+    Ok(Json(session.ok_or_else(not_found)?))
+}
+
+/// PATCH /sessions/{id}/messages/{message_id} - メッセージのピン留め
+pub async fn update_message(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<UpdateMessageRequest>,
+) -> Result<Json<ChatMessage>, ApiError> {
+    info!("Updating message: {} in session {}", message_id, id);
+
+    let message = state
+        .session_repo
+        .set_message_pinned(id, message_id, request.pinned)
+        .await?
+        .ok_or_else(|| ApiError::from(AppError::NotFound("Message".to_string())))?;
+
+    Ok(Json(message))
+}
+
 /// GET /sessions/{id} - セッション情報取得（履歴付き）
 pub async fn get_session(
     State(state): State<AppState>,
@@ -55,62 +150,102 @@ pub async fn get_session(
         .ok_or_else(|| ApiError::from(AppError::NotFound("Session".to_string())))?;
 
     let messages = state.session_repo.get_messages(id).await?;
+    let summary = state.session_repo.get_latest_summary(id).await?;
+    let prompt_cache = PromptCacheStats::from_messages(&messages);
 
-    Ok(Json(SessionWithMessages { session, messages }))
+    Ok(Json(SessionWithMessages {
+        session,
+        messages,
+        summary,
+        prompt_cache,
+    }))
 }
 
 /// POST /sessions/{id}/chat - セッション内チャット
+///
+/// JSON のほか、画像・ファイルを添付する multipart/form-data も受け付ける。
 pub async fn session_chat(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
-    Json(request): Json<SessionChatRequest>,
+    ChatInput(request): ChatInput,
 ) -> Result<Json<SessionChatResponse>, ApiError> {
-    info!("Session chat: {} - message: {}", id, &request.message);
-
-    // セッションを取得
-    let session = state
-        .session_repo
-        .get_session(id)
-        .await?
-        .ok_or_else(|| ApiError::from(AppError::NotFound("Session".to_string())))?;
+    info!("Session chat: {} - message: {}", id, describe(&request.message));
 
-    // 過去のメッセージを取得
-    let history = state.session_repo.get_messages(id).await?;
+    // セッションと履歴を読み込み、OpenAI Responses API を呼び出す
+    let turn = prepare_turn(&state, id, &request).await?;
+    let context_message_ids = turn.context_message_ids.clone();
+    let input_moderation = turn.moderation.clone();
+    let model = turn.options.model.clone();
+    let started = Instant::now();
+    let result = turn
+        .call(|messages, options| state.llm.chat_with_history(messages, options))
+        .await;
+    let latency = started.elapsed();
 
-    // OpenAI API用のメッセージを構築（システムプロンプトはinstructionsで渡す）
-    let mut messages: Vec<Message> = Vec::new();
-
-    // 履歴を追加
-    for msg in &history {
-        messages.push(Message {
-            role: msg.role.clone(),
-            content: msg.content.clone(),
-        });
-    }
-
-    // ユーザーメッセージを追加
-    messages.push(Message {
-        role: "user".to_string(),
-        content: request.message.clone(),
-    });
+    // プロバイダーのエラーはターンを "failed" で保存してから返す
+    let mut response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            let error = e.to_string();
+            let reply = NewMessage {
+                status: "failed",
+                context_message_ids: &context_message_ids,
+                model: model.as_deref(),
+                latency: Some(latency),
+                error: Some(&error),
+                ..NewMessage::default()
+            };
+            save_failed_turn(
+                &state,
+                id,
+                &request.message,
+                input_moderation.as_ref(),
+                &reply,
+            )
+            .await;
+            return Err(e.into());
+        }
+    };
+    response.cost_usd = record_cost(&state, Some(id), &response).await;
 
-    // OpenAI Responses API呼び出し（システムプロンプトはinstructionsパラメータで渡す）
-    let response = state
-        .openai
-        .chat_with_history(messages, session.system_prompt.clone())
-        .await?;
+    // 返答を確認（ブロックした場合はターンを保存しない）
+    let output_moderation = moderate(
+        &state,
+        Some(id),
+        ModerationStage::Output,
+        &response.response,
+    )
+    .await?;
 
     // ユーザーメッセージをDBに保存
-    state
+    let user_message = state
         .session_repo
-        .add_message(id, "user", &request.message)
+        .add_user_message(id, &request.message, input_moderation.as_ref())
         .await?;
 
-    // アシスタントの返答をDBに保存
-    state
+    // アシスタントの返答をDBに保存（推論の要約・送った履歴・使用量・所要時間も含める）
+    // 応答キャッシュから返した返答の response_id は別の会話のものなので、続きの生成に使わないよう保存しない
+    let response_id = (!response.cached).then_some(response.response_id.as_str());
+    let assistant_message = state
         .session_repo
-        .add_message(id, "assistant", &response.response)
+        .add_assistant_message(
+            id,
+            &NewMessage {
+                content: &response.response,
+                status: "completed",
+                reasoning_summary: &response.reasoning_summary,
+                response_id,
+                context_message_ids: &context_message_ids,
+                moderation: output_moderation.as_ref(),
+                model: Some(&response.model),
+                usage: Some(&response.usage),
+                cost_usd: response.cost_usd,
+                latency: Some(latency),
+                error: None,
+            },
+        )
         .await?;
+    index_messages(&state, vec![user_message, assistant_message]);
 
     // 更新後のメッセージ数を取得
     let updated_messages = state.session_repo.get_messages(id).await?;
@@ -126,9 +261,600 @@ pub async fn session_chat(
         model: response.model,
         session_id: id,
         message_count: updated_messages.len(),
+        reasoning_summary: response.reasoning_summary,
+        cost_usd: response.cost_usd,
     }))
 }
 
+/// POST /sessions/{id}/chat/stream - セッション内チャット（SSEストリーミング）
+///
+/// 生成は別タスクで行い、チャネル経由でクライアントへ送る。
+/// 完了後にユーザー・アシスタントのメッセージを保存する。
+/// 途中でクライアントが切断した場合は、途中までの返答を "incomplete" として保存する。
+pub async fn session_chat_stream(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    ChatInput(request): ChatInput,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    info!(
+        "Session chat stream: {} - message: {}",
+        id,
+        describe(&request.message)
+    );
+
+    // ストリーム開始前のエラー（認証エラーなど）は通常のエラーレスポンスで返す
+    let turn = open_chat_stream(&state, id, &request).await?;
+
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let outcome = run_turn(
+            &state,
+            id,
+            &request.message,
+            turn,
+            &tx,
+            |text| SessionChatStreamEvent::Delta { text },
+            tx.closed(),
+        )
+        .await;
+
+        let event = match outcome {
+            TurnOutcome::Completed {
+                response,
+                message_count,
+            } => SessionChatStreamEvent::Completed(SessionChatResponse {
+                response: response.response,
+                model: response.model,
+                session_id: id,
+                message_count,
+                reasoning_summary: response.reasoning_summary,
+                cost_usd: response.cost_usd,
+            }),
+            TurnOutcome::Cancelled { .. } => {
+                warn!("Client disconnected during session chat stream: {}", id);
+                return;
+            }
+            TurnOutcome::Failed(err) => {
+                let (code, message) = stream_error(err);
+                SessionChatStreamEvent::Error { code, message }
+            }
+        };
+        let _ = tx.send(event).await;
+    });
+
+    let events = ReceiverStream::new(rx).map(|event: SessionChatStreamEvent| {
+        Ok(Event::default()
+            .event(event.event_name())
+            .json_data(&event)
+            .unwrap_or_default())
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// ストリーミングの1ターンの結果
+pub(crate) enum TurnOutcome {
+    /// 生成完了（メッセージ保存済み）
+    Completed {
+        response: ChatResponse,
+        message_count: usize,
+    },
+    /// 生成途中でキャンセル・切断された（途中までの返答を "incomplete" で保存済み）
+    Cancelled { message_count: usize },
+    /// エラー（プロバイダーのエラーは送った分までの返答を "failed" で保存済み。ブロックした返答は保存しない）
+    Failed(AppError),
+}
+
+/// ストリーミング中のターン
+pub(crate) struct OpenTurn {
+    stream: ChatStream,
+    /// 使用するモデル（中断した返答に記録する）
+    model: Option<String>,
+    /// ストリーミングを開始した時刻
+    started: Instant,
+    /// 送った履歴のメッセージID
+    context_message_ids: Vec<Uuid>,
+    /// ユーザーメッセージのモデレーションの結果
+    moderation: Option<ModerationFlags>,
+}
+
+/// セッションの履歴を読み込み、ストリーミングを開始する
+pub(crate) async fn open_chat_stream(
+    state: &AppState,
+    id: Uuid,
+    request: &SessionChatRequest,
+) -> Result<OpenTurn, AppError> {
+    let turn = prepare_turn(state, id, request).await?;
+    let context_message_ids = turn.context_message_ids.clone();
+    let moderation = turn.moderation.clone();
+    let model = turn.options.model.clone();
+    let started = Instant::now();
+    let result = turn
+        .call(|messages, options| state.llm.chat_with_history_stream(messages, options))
+        .await;
+
+    // ストリーム開始前のプロバイダーのエラーも、ターンを "failed" で保存してから返す
+    let stream = match result {
+        Ok(stream) => stream,
+        Err(e) => {
+            let error = e.to_string();
+            let reply = NewMessage {
+                status: "failed",
+                context_message_ids: &context_message_ids,
+                model: model.as_deref(),
+                latency: Some(started.elapsed()),
+                error: Some(&error),
+                ..NewMessage::default()
+            };
+            save_failed_turn(state, id, &request.message, moderation.as_ref(), &reply).await;
+            return Err(e.into());
+        }
+    };
+
+    Ok(OpenTurn {
+        stream,
+        model,
+        started,
+        context_message_ids,
+        moderation,
+    })
+}
+
+/// 1ターン分の OpenAI への入力
+struct TurnInput {
+    /// 選んだ履歴と新しいユーザーメッセージ
+    messages: Vec<Message>,
+    options: ChatOptions,
+    /// 会話を続ける前のレスポンスのID（chained モードで前の返答のIDがある場合のみ）
+    previous_response_id: Option<String>,
+    /// 送る履歴のメッセージID（返答と一緒に保存する）
+    context_message_ids: Vec<Uuid>,
+    /// ユーザーメッセージのモデレーションの結果（ユーザーメッセージと一緒に保存する）
+    moderation: Option<ModerationFlags>,
+}
+
+impl TurnInput {
+    /// OpenAI を呼び出す
+    ///
+    /// 前のレスポンスのIDがあれば新しいユーザーメッセージだけを送る。
+    /// サーバー側で前のレスポンスが見つからなければ、全履歴を送り直す。
+    async fn call<T, F, Fut>(self, call: F) -> Result<T, OpenAIError>
+    where
+        F: Fn(Vec<Message>, ChatOptions) -> Fut,
+        Fut: Future<Output = Result<T, OpenAIError>>,
+    {
+        let Some(previous_response_id) = self.previous_response_id else {
+            return call(self.messages, self.options).await;
+        };
+
+        let latest = self.messages.last().cloned().into_iter().collect();
+        let options = ChatOptions {
+            previous_response_id: Some(previous_response_id.clone()),
+            ..self.options.clone()
+        };
+
+        match call(latest, options).await {
+            Err(e) if e.is_previous_response_not_found() => {
+                warn!(
+                    "Previous response {} not found, replaying full history: {}",
+                    previous_response_id, e
+                );
+                call(self.messages, self.options).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// セッションと履歴を読み込み、1ターン分の入力を組み立てる
+async fn prepare_turn(
+    state: &AppState,
+    id: Uuid,
+    request: &SessionChatRequest,
+) -> Result<TurnInput, AppError> {
+    // サンプリング・生成のパラメーターを検証
+    request.generation.validate()?;
+
+    // セッションを取得
+    let session = state
+        .session_repo
+        .get_session(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Session".to_string()))?;
+
+    // セッションのモデルを使用（許可リストから外れていればエラー）
+    let model = state.llm.resolve_model(session.model.as_deref())?;
+
+    // 新しいメッセージだけでコンテキストウィンドウを超える場合は、
+    // 要約・モデレーションを含めて API を呼ばずに拒否
+    let new_message = build_messages(&[], &request.message);
+    state
+        .tokenizer
+        .check_context_window(&model, &new_message, session.system_prompt.as_deref())?;
+
+    // 過去のメッセージを取得（エラーで終わった返答は送らない）
+    let mut history = state.session_repo.get_messages(id).await?;
+    history.retain(|msg| msg.status != "failed");
+
+    // summarize では最新の要約を読み込み、上限を超えていれば古いターンを要約に積み重ねる
+    let strategy = &session.history_strategy.0;
+    let mut summary = match strategy {
+        HistoryStrategy::Summarize { .. } => state.session_repo.get_latest_summary(id).await?,
+        _ => None,
+    };
+    let mut summarized = summary
+        .as_ref()
+        .map_or(0, |s| history::summarized_count(&history, s.last_message_id));
+
+    let summary_text = summary.as_ref().map(|s| s.content.as_str());
+    let instructions = history::with_summary(session.system_prompt.as_deref(), summary_text);
+    let input = HistoryInput {
+        new_message: &request.message,
+        instructions: instructions.as_deref(),
+        model: &model,
+        tokenizer: &state.tokenizer,
+    };
+    let fold = history::messages_to_summarize(strategy, &history, summarized, &input);
+    if fold > 0 {
+        let messages = &history[summarized..summarized + fold];
+        match compact(state, id, &model, summary.as_ref(), messages, summarized + fold).await {
+            Ok(compacted) => {
+                summary = Some(compacted);
+                summarized += fold;
+            }
+            // 要約に失敗しても、これまでの要約と履歴で続ける
+            Err(e) => warn!("Failed to summarize session {}: {:?}", id, e),
+        }
+    }
+
+    let summary_text = summary.as_ref().map(|s| s.content.as_str());
+    let instructions = history::with_summary(session.system_prompt.as_deref(), summary_text);
+    let input = HistoryInput {
+        instructions: instructions.as_deref(),
+        ..input
+    };
+
+    // セッションの選び方で送る履歴を選ぶ
+    let selected = history::select_history(strategy, &history, summarized, &input);
+    let context_message_ids = selected.iter().map(|msg| msg.id).collect();
+
+    // OpenAI API用のメッセージを構築（システムプロンプトはinstructionsで渡す）
+    let messages = build_messages(&selected, &request.message);
+
+    // chained モードでもサーバー側で全履歴を使うため、送る履歴全体で確認する
+    state
+        .tokenizer
+        .check_context_window(&model, &messages, instructions.as_deref())?;
+
+    // 入力を確認（ブロックした場合は API を呼ばず、メッセージも保存しない）
+    let moderation = moderate(
+        state,
+        Some(id),
+        ModerationStage::Input,
+        &request.message.text(),
+    )
+    .await?;
+
+    // instructions は前のレスポンスから引き継がれないため、chained モードでも毎回渡す
+    // （prompt_cache_key は受け付けるプロバイダーにだけ渡す）
+    let options = ChatOptions {
+        instructions,
+        model: Some(model),
+        reasoning: request.reasoning.clone(),
+        bypass_cache: request.bypass_cache,
+        prompt_cache_key: state
+            .llm
+            .supports_prompt_cache_key()
+            .then(|| session.prompt_cache_key()),
+        generation: session.generation.merge(&request.generation),
+        ..ChatOptions::default()
+    };
+
+    // 直前の返答が正常に完了していれば、その続きとして生成できる
+    // （previous_response_id に対応していないプロバイダーでは常に全履歴を送る。
+    // サーバー側の会話は全履歴を含むため、履歴を選ぶ場合も選んだ履歴を送る）
+    let chainable =
+        state.llm.supports_previous_response_id() && *strategy == HistoryStrategy::Full;
+    let previous_response_id = match session.history_mode {
+        HistoryMode::Chained if chainable => history
+            .last()
+            .filter(|msg| msg.role == "assistant" && msg.status == "completed")
+            .and_then(|msg| msg.response_id.clone()),
+        HistoryMode::Chained | HistoryMode::Replay => None,
+    };
+
+    Ok(TurnInput {
+        messages,
+        options,
+        previous_response_id,
+        context_message_ids,
+        moderation,
+    })
+}
+
+/// 前の要約と `messages` から新しい要約を生成して保存する
+///
+/// `message_count` は新しい要約に含めるメッセージ数（セッションの先頭から）。
+async fn compact(
+    state: &AppState,
+    id: Uuid,
+    model: &str,
+    previous: Option<&SessionSummary>,
+    messages: &[ChatMessage],
+    message_count: usize,
+) -> Result<SessionSummary, AppError> {
+    let previous = previous.map(|s| s.content.as_str());
+    let response = history::summarize(state.llm.as_ref(), model, previous, messages).await?;
+    record_cost(state, Some(id), &response).await;
+    let last_message_id = messages.last().map(|msg| msg.id).unwrap_or_default();
+
+    info!(
+        "Session summarized: {} - messages: {}",
+        id, message_count
+    );
+
+    Ok(state
+        .session_repo
+        .add_summary(id, &response.response, last_message_id, message_count as i32)
+        .await?)
+}
+
+/// モデレーション有効時に、差分をためてから確認して送る単位（バイト数）
+const MODERATION_CHUNK_BYTES: usize = 200;
+/// ストリーミングの途中の確認で、新しい差分の前に含める確認済みのテキスト（バイト数）
+///
+/// 差分の境目をまたぐ語句も確認できるようにする。
+const MODERATION_OVERLAP_BYTES: usize = 64;
+
+/// ストリームの差分を `tx` へ転送し、終了時にメッセージを保存する
+///
+/// `cancelled` が完了した時点で生成を打ち切り、クライアントに送った分までの返答を保存する。
+/// モデレーション有効時は、差分を `MODERATION_CHUNK_BYTES` ごとにためて、
+/// 新しい差分（直前の `MODERATION_OVERLAP_BYTES` を含む）を確認してから送り、最後に返答全体を確認する
+/// （ブロックした返答はクライアントに届かない）。
+/// 1ターンの出力の確認で呼んだ Moderations API は、まとめて1件の使用量として記録する。
+pub(crate) async fn run_turn<E>(
+    state: &AppState,
+    id: Uuid,
+    user_message: &MessageContent,
+    turn: OpenTurn,
+    tx: &mpsc::Sender<E>,
+    delta: impl Fn(String) -> E,
+    cancelled: impl Future<Output = ()>,
+) -> TurnOutcome {
+    tokio::pin!(cancelled);
+    let OpenTurn {
+        mut stream,
+        model,
+        started,
+        context_message_ids,
+        moderation,
+    } = turn;
+    let moderated = state.moderator.is_enabled();
+    let mut moderation_calls = None;
+    let mut partial = String::new();
+    // クライアントに送った長さ（バイト数）
+    let mut sent = 0;
+
+    let outcome = loop {
+        let event = tokio::select! {
+            event = stream.next() => event,
+            _ = &mut cancelled => {
+                let reply = NewMessage {
+                    content: &partial[..sent],
+                    status: "incomplete",
+                    context_message_ids: &context_message_ids,
+                    model: model.as_deref(),
+                    latency: Some(started.elapsed()),
+                    error: Some("Cancelled before completion"),
+                    ..NewMessage::default()
+                };
+                let saved = save_turn(state, id, user_message, moderation.as_ref(), &reply).await;
+                break match saved {
+                    Ok(message_count) => TurnOutcome::Cancelled { message_count },
+                    Err(e) => TurnOutcome::Failed(AppError::Database(e)),
+                };
+            }
+        };
+
+        match event {
+            Some(Ok(ChatStreamEvent::Delta { text })) => {
+                partial.push_str(&text);
+                if moderated {
+                    if partial.len() - sent < MODERATION_CHUNK_BYTES {
+                        continue;
+                    }
+                    // ブロックした場合は残りを送らず、エラーで終えて保存しない
+                    let mut window = sent.saturating_sub(MODERATION_OVERLAP_BYTES);
+                    while !partial.is_char_boundary(window) {
+                        window -= 1;
+                    }
+                    let text = &partial[window..];
+                    let checked = moderate_unrecorded(
+                        state,
+                        ModerationStage::Output,
+                        text,
+                        &mut moderation_calls,
+                    )
+                    .await;
+                    if let Err(e) = checked {
+                        break TurnOutcome::Failed(e);
+                    }
+                }
+                // 送信失敗（切断）は次のループで cancelled として処理される
+                let _ = tx.send(delta(partial[sent..].to_string())).await;
+                sent = partial.len();
+            }
+            Some(Ok(ChatStreamEvent::Completed(mut response))) => {
+                let latency = started.elapsed();
+                response.cost_usd = record_cost(state, Some(id), &response).await;
+
+                // 返答全体を確認し、まだ送っていない差分を送る（ブロックした場合はエラーで終えて保存しない）
+                let output_moderation = match moderate_unrecorded(
+                    state,
+                    ModerationStage::Output,
+                    &response.response,
+                    &mut moderation_calls,
+                )
+                .await
+                {
+                    Ok(flags) => flags,
+                    Err(e) => break TurnOutcome::Failed(e),
+                };
+                if sent < partial.len() {
+                    let _ = tx.send(delta(partial[sent..].to_string())).await;
+                }
+                let reply = NewMessage {
+                    content: &response.response,
+                    status: "completed",
+                    reasoning_summary: &response.reasoning_summary,
+                    response_id: Some(&response.response_id),
+                    context_message_ids: &context_message_ids,
+                    moderation: output_moderation.as_ref(),
+                    model: Some(&response.model),
+                    usage: Some(&response.usage),
+                    cost_usd: response.cost_usd,
+                    latency: Some(latency),
+                    error: None,
+                };
+                let saved = save_turn(state, id, user_message, moderation.as_ref(), &reply).await;
+                break match saved {
+                    Ok(message_count) => {
+                        info!(
+                            "Session chat stream completed: {} - messages: {}",
+                            id, message_count
+                        );
+                        TurnOutcome::Completed {
+                            response,
+                            message_count,
+                        }
+                    }
+                    Err(e) => TurnOutcome::Failed(AppError::Database(e)),
+                };
+            }
+            // プロバイダーのエラーは、クライアントに送った分までの返答を "failed" で保存する
+            Some(Err(_)) | None => {
+                let e = match event {
+                    Some(Err(e)) => e,
+                    _ => OpenAIError::StreamError("stream ended before completion".to_string()),
+                };
+                let error = e.to_string();
+                let reply = NewMessage {
+                    content: &partial[..sent],
+                    status: "failed",
+                    context_message_ids: &context_message_ids,
+                    model: model.as_deref(),
+                    latency: Some(started.elapsed()),
+                    error: Some(&error),
+                    ..NewMessage::default()
+                };
+                save_failed_turn(state, id, user_message, moderation.as_ref(), &reply).await;
+                break TurnOutcome::Failed(AppError::ExternalApi(e));
+            }
+        }
+    };
+
+    record_moderation(state, Some(id), moderation_calls).await;
+    outcome
+}
+
+/// ユーザーメッセージ（モデレーションの結果付き）とアシスタントの返答を保存し、保存後のメッセージ数を返す
+pub(crate) async fn save_turn(
+    state: &AppState,
+    id: Uuid,
+    user_message: &MessageContent,
+    moderation: Option<&ModerationFlags>,
+    reply: &NewMessage<'_>,
+) -> Result<usize, sqlx::Error> {
+    let user_message = state
+        .session_repo
+        .add_user_message(id, user_message, moderation)
+        .await?;
+    let assistant_message = state.session_repo.add_assistant_message(id, reply).await?;
+    index_messages(state, vec![user_message, assistant_message]);
+
+    Ok(state.session_repo.get_messages(id).await?.len())
+}
+
+/// プロバイダーのエラーで終わったターンを保存する（保存の失敗はログに記録するだけで、元のエラーを返す）
+async fn save_failed_turn(
+    state: &AppState,
+    id: Uuid,
+    user_message: &MessageContent,
+    moderation: Option<&ModerationFlags>,
+    reply: &NewMessage<'_>,
+) {
+    if let Err(e) = save_turn(state, id, user_message, moderation, reply).await {
+        error!("Failed to save failed turn for session {}: {}", id, e);
+    }
+}
+
+/// 保存したメッセージの埋め込みをバックグラウンドで保存する（`EMBEDDING_MODEL` 設定時のみ）
+///
+/// 埋め込みの失敗はログに記録するだけで、チャットには影響しない。
+fn index_messages(state: &AppState, messages: Vec<ChatMessage>) {
+    let Some(model) = state.embedding_model.clone() else {
+        return;
+    };
+    let messages: Vec<ChatMessage> = messages
+        .into_iter()
+        .filter(|msg| !msg.content.trim().is_empty())
+        .collect();
+    if messages.is_empty() {
+        return;
+    }
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let inputs: Vec<String> = messages.iter().map(|msg| msg.content.clone()).collect();
+        let embeddings = match state.llm.embed(&model, &inputs).await {
+            Ok(embeddings) => embeddings,
+            Err(e) => {
+                warn!("Failed to embed messages: {}", e);
+                return;
+            }
+        };
+        let session_id = messages.first().map(|msg| msg.session_id);
+        record_usage(&state, session_id, &model, &embeddings.usage).await;
+
+        for (msg, embedding) in messages.iter().zip(embeddings.vectors) {
+            if let Err(e) = state
+                .session_repo
+                .add_embedding(msg.id, &model, &embedding)
+                .await
+            {
+                warn!("Failed to save embedding for message {}: {}", msg.id, e);
+            }
+        }
+    });
+}
+
+/// ストリーム開始後のエラーをログに記録し、クライアント向けのコードとメッセージに変換
+pub(crate) fn stream_error(err: AppError) -> (String, String) {
+    error!("Streaming error: {:?}", err);
+    (err.code().to_string(), err.user_message())
+}
+
+/// 選んだ履歴と新しいユーザーメッセージから OpenAI API 用のメッセージを構築
+fn build_messages(history: &[&ChatMessage], user_message: &MessageContent) -> Vec<Message> {
+    let mut messages: Vec<Message> = history.iter().map(|msg| msg.to_message()).collect();
+
+    messages.push(Message {
+        role: "user".to_string(),
+        content: user_message.clone(),
+    });
+
+    messages
+}
+
+/// ログ用にメッセージを要約（添付の数を付ける）
+fn describe(message: &MessageContent) -> String {
+    match message.parts() {
+        Some(parts) => format!("{} (parts: {})", message.text(), parts.len()),
+        None => message.text(),
+    }
+}
+
 /// DELETE /sessions/{id} - セッション削除
 pub async fn delete_session(
     State(state): State<AppState>,
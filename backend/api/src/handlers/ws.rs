@@ -0,0 +1,202 @@
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use backend_core::models::{
+    SessionChatRequest, SessionChatResponse, WsClientMessage, WsServerMessage,
+};
+use backend_core::AppError;
+use crate::error::ApiError;
+use crate::handlers::session::{open_chat_stream, run_turn, stream_error, TurnOutcome};
+use crate::handlers::AppState;
+
+/// 生成中のターン（キャンセル用の送信側とタスク）
+///
+/// キャンセル後もタスクが途中までの返答を保存し終えるまで残し、次のターンと重ならないようにする。
+struct Generation {
+    /// キャンセル済みなら None
+    cancel: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+/// GET /sessions/{id}/ws - セッション内チャット（WebSocket）
+///
+/// 1つの接続で複数ターンを扱う。同時に生成できるのは1ターンのみ。
+pub async fn session_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    // 存在しないセッションはアップグレード前に404を返す
+    state
+        .session_repo
+        .get_session(id)
+        .await?
+        .ok_or_else(|| ApiError::from(AppError::NotFound("Session".to_string())))?;
+
+    info!("WebSocket connected: {}", id);
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, id)))
+}
+
+/// WebSocket 接続を処理
+async fn handle_socket(socket: WebSocket, state: AppState, id: Uuid) {
+    let (mut sink, mut incoming) = socket.split();
+
+    // 送信はすべてチャネル経由で書き込みタスクに集約する
+    let (tx, mut rx) = mpsc::channel::<WsServerMessage>(32);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let Ok(text) = serde_json::to_string(&message) else {
+                continue;
+            };
+            if sink.send(WsMessage::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut generation: Option<Generation> = None;
+
+    while let Some(Ok(frame)) = incoming.next().await {
+        let text = match frame {
+            WsMessage::Text(text) => text,
+            WsMessage::Close(_) => break,
+            _ => continue,
+        };
+
+        let message: WsClientMessage = match serde_json::from_str(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                let err = AppError::Validation(format!("Invalid message: {}", e));
+                let _ = tx.send(error_frame(err)).await;
+                continue;
+            }
+        };
+
+        // 完了済みのターンを片付ける
+        if generation.as_ref().is_some_and(|g| g.task.is_finished()) {
+            generation = None;
+        }
+
+        match message {
+            WsClientMessage::Chat(request) => {
+                if generation.as_ref().is_some_and(|g| g.cancel.is_some()) {
+                    let err = AppError::Validation("Generation already in progress".to_string());
+                    let _ = tx.send(error_frame(err)).await;
+                    continue;
+                }
+
+                // キャンセル済みのターンは保存が終わるまで待ってから次のターンを始める
+                if let Some(g) = generation.take() {
+                    let _ = g.task.await;
+                }
+
+                let (cancel, cancel_rx) = oneshot::channel();
+                let task =
+                    tokio::spawn(generate(state.clone(), id, request, tx.clone(), cancel_rx));
+                generation = Some(Generation {
+                    cancel: Some(cancel),
+                    task,
+                });
+            }
+            WsClientMessage::Cancel => match generation.as_mut().map(|g| g.cancel.take()) {
+                Some(Some(cancel)) => {
+                    // 確認応答（Cancelled）は生成タスクが保存後に送信する
+                    let _ = cancel.send(());
+                }
+                Some(None) => {
+                    let err = AppError::Validation("Generation already cancelled".to_string());
+                    let _ = tx.send(error_frame(err)).await;
+                }
+                None => {
+                    let err = AppError::Validation("No generation in progress".to_string());
+                    let _ = tx.send(error_frame(err)).await;
+                }
+            },
+        }
+    }
+
+    // 切断時は生成中のターンをキャンセル扱いにする（送信側のdropで通知される）
+    drop(generation);
+    drop(tx);
+    let _ = writer.await;
+
+    info!("WebSocket disconnected: {}", id);
+}
+
+/// 1ターン分の生成を行い、結果をフレームとして送信
+async fn generate(
+    state: AppState,
+    id: Uuid,
+    request: SessionChatRequest,
+    tx: mpsc::Sender<WsServerMessage>,
+    cancel: oneshot::Receiver<()>,
+) {
+    let turn = match open_chat_stream(&state, id, &request).await {
+        Ok(turn) => turn,
+        Err(err) => {
+            let _ = tx.send(error_frame(err)).await;
+            return;
+        }
+    };
+
+    let outcome = run_turn(
+        &state,
+        id,
+        &request.message,
+        turn,
+        &tx,
+        |text| WsServerMessage::Delta { text },
+        async {
+            let _ = cancel.await;
+        },
+    )
+    .await;
+
+    match outcome {
+        TurnOutcome::Completed {
+            response,
+            message_count,
+        } => {
+            let _ = tx.send(WsServerMessage::Usage(response.usage)).await;
+            let _ = tx
+                .send(WsServerMessage::Completed(SessionChatResponse {
+                    response: response.response,
+                    model: response.model,
+                    session_id: id,
+                    message_count,
+                    reasoning_summary: response.reasoning_summary,
+                    cost_usd: response.cost_usd,
+                }))
+                .await;
+        }
+        TurnOutcome::Cancelled { message_count } => {
+            warn!("Generation cancelled: {}", id);
+            let _ = tx
+                .send(WsServerMessage::Cancelled {
+                    session_id: id,
+                    message_count,
+                })
+                .await;
+        }
+        TurnOutcome::Failed(err) => {
+            let _ = tx.send(error_frame(err)).await;
+        }
+    }
+}
+
+/// エラーフレームを作成
+fn error_frame(err: AppError) -> WsServerMessage {
+    let (code, message) = stream_error(err);
+    WsServerMessage::Error { code, message }
+}
@@ -6,12 +6,16 @@ pub mod error;
 pub mod handlers;
 
 use axum::{
-    routing::{delete, get, post},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, patch, post},
     Router,
 };
 use handlers::AppState;
 use tower_http::cors::{Any, CorsLayer};
 
+/// リクエストボディの上限（画像・PDF の添付を想定）
+const MAX_BODY_BYTES: usize = 20 * 1024 * 1024;
+
 /// アプリケーションのルーターを構築
 /// テストから利用可能にするために公開
 pub fn create_app(state: AppState) -> Router {
@@ -24,10 +28,21 @@ pub fn create_app(state: AppState) -> Router {
         .route("/", get(root))
         .route("/health", get(handlers::health_check))
         .route("/chat", post(handlers::chat))
+        .route("/search", get(handlers::search))
+        .route("/costs/daily", get(handlers::get_daily_costs))
         .route("/sessions", post(handlers::create_session))
         .route("/sessions/{id}", get(handlers::get_session))
         .route("/sessions/{id}", delete(handlers::delete_session))
+        .route("/sessions/{id}", patch(handlers::update_session))
+        .route(
+            "/sessions/{id}/messages/{message_id}",
+            patch(handlers::update_message),
+        )
+        .route("/sessions/{id}/cost", get(handlers::get_session_cost))
         .route("/sessions/{id}/chat", post(handlers::session_chat))
+        .route("/sessions/{id}/chat/stream", post(handlers::session_chat_stream))
+        .route("/sessions/{id}/ws", get(handlers::session_ws))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
         .layer(cors)
         .with_state(state)
 }
@@ -1,5 +1,7 @@
 use api::{create_app, handlers::AppState};
-use backend_core::{Config, OpenAIService, SessionRepository};
+use backend_core::db::vector;
+use backend_core::services::{cache, provider};
+use backend_core::{Config, Moderator, SessionRepository, Tokenizer};
 use sqlx::postgres::PgPoolOptions;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -35,14 +37,32 @@ async fn main() {
 
     info!("Migrations completed");
 
+    // pgvector の場合は拡張・列・HNSW インデックスを用意する
+    vector::setup(&pool, config.embedding_index)
+        .await
+        .expect("Failed to set up embedding index");
+
     // サービスとリポジトリを初期化
-    let openai_service = OpenAIService::new(config.openai_api_key.clone());
-    let session_repo = SessionRepository::new(pool);
+    let llm = cache::from_config(provider::from_config(&config), &config, pool.clone());
+    let session_repo = SessionRepository::new(pool).with_embedding_index(config.embedding_index);
+    let tokenizer = Tokenizer::from_config(&config).expect("Failed to load tokenizer");
+    let embedding_model = config.embedding_model.clone();
+    let moderator = Moderator::from_config(&config).expect("Failed to load moderation rules");
+    let prices = config.model_prices.clone();
+
+    info!("LLM provider: {}", llm.name());
+    info!("Response cache: {}", config.response_cache);
+    info!("Embedding index: {}", config.embedding_index);
+    info!("Tokenizer: {}", config.tokenizer);
 
     // アプリケーション状態
     let app_state = AppState {
-        openai: openai_service,
+        llm,
         session_repo,
+        tokenizer,
+        embedding_model,
+        moderator,
+        prices,
     };
 
     // ルーター設定
@@ -58,10 +78,17 @@ async fn main() {
     info!("  GET    /                  - Hello message");
     info!("  GET    /health            - Health check");
     info!("  POST   /chat              - Chat with OpenAI (single)");
+    info!("  GET    /search?q=         - Semantic search over messages");
+    info!("  GET    /costs/daily       - Token usage and cost per day");
     info!("  POST   /sessions          - Create new session");
     info!("  GET    /sessions/{{id}}     - Get session with messages");
     info!("  DELETE /sessions/{{id}}     - Delete session");
+    info!("  PATCH  /sessions/{{id}}     - Update session settings");
+    info!("  GET    /sessions/{{id}}/cost - Token usage and cost of a session");
+    info!("  PATCH  /sessions/{{id}}/messages/{{message_id}} - Pin or unpin a message");
     info!("  POST   /sessions/{{id}}/chat - Chat within session");
+    info!("  POST   /sessions/{{id}}/chat/stream - Chat within session (SSE)");
+    info!("  GET    /sessions/{{id}}/ws   - Chat within session (WebSocket)");
 
     axum::serve(listener, app).await.unwrap();
 }
@@ -6,16 +6,46 @@
 //! cargo test -p api
 //! ```
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    extract::State,
+    http::{header, Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
 };
+use futures::{SinkExt, StreamExt};
 use http_body_util::BodyExt;
 use api::{create_app, handlers::AppState};
-use backend_core::{OpenAIService, SessionRepository};
+use backend_core::db::{vector, EmbeddingIndex, PostgresCache};
+use backend_core::models::{
+    ChatOptions, ChatRequest, GenerationOptions, HistoryMode, HistoryStrategy, Message,
+    MessageContent, PromptCacheStats, Usage,
+};
+use backend_core::schema::{schema_name, strict_schema};
+use backend_core::services::{
+    CachedProvider, LlmProvider, LlmProviderExt, MemoryCache, OpenAIError, RetryPolicy,
+};
+use backend_core::history;
+use backend_core::moderation::{CategoryThresholds, RuleSet};
+use backend_core::pricing::{Price, PriceTable};
+use backend_core::testing::{MockProvider, MockReply, MOCK_EMBEDDING_DIMENSIONS};
+use backend_core::tokenizer::{self, Bpe, Encoding};
+use backend_core::{
+    ChatCompletionsService, JsonSchema, Moderator, OpenAIService, SessionRepository, Tokenizer,
+    Tool, ToolError, ToolRegistry,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sqlx::postgres::PgPoolOptions;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
 use tower::ServiceExt;
+use uuid::Uuid;
 
 /// テスト用のデータベースURLを取得
 fn get_test_database_url() -> String {
@@ -49,19 +79,71 @@ async fn create_test_state() -> Option<AppState> {
         return None;
     }
 
-    // ダミーのOpenAI APIキー（テストでは実際のAPIは呼ばない）
-    let api_key =
-        std::env::var("OPENAI_API_KEY").unwrap_or_else(|_| "test-api-key".to_string());
-
-    let openai_service = OpenAIService::new(api_key);
+    // 実際のAPIは呼ばない（応答が必要なテストはモックを差し替える）
+    let llm = Arc::new(MockProvider::new());
     let session_repo = SessionRepository::new(pool);
 
     Some(AppState {
-        openai: openai_service,
+        llm,
         session_repo,
+        tokenizer: Tokenizer::new(),
+        embedding_model: None,
+        moderator: Moderator::default(),
+        prices: PriceTable::default(),
     })
 }
 
+/// OpenAI Responses API のモックサーバーを起動し、ベースURLを返す
+///
+/// `stream: true` のリクエストにはSSEで、それ以外はJSONで固定の応答を返す。
+async fn spawn_mock_openai() -> String {
+    async fn responses(Json(body): Json<Value>) -> Response {
+        let response = json!({
+            "id": "resp_mock",
+            "model": body["model"],
+            "output": [{
+                "type": "message",
+                "content": [{"type": "output_text", "text": "Mock reply"}]
+            }],
+            "usage": {"input_tokens": 10, "output_tokens": 2, "total_tokens": 12}
+        });
+
+        if body["stream"] == true {
+            let events = [
+                json!({"type": "response.created"}),
+                json!({"type": "response.output_text.delta", "delta": "Mock "}),
+                json!({"type": "response.output_text.delta", "delta": "reply"}),
+                json!({"type": "response.completed", "response": response}),
+            ];
+            let body: String = events
+                .iter()
+                .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+                .collect();
+            ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+        } else {
+            Json(response).into_response()
+        }
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/v1/responses", post(responses));
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    format!("http://{}/v1", addr)
+}
+
+/// モックのOpenAIサーバーに接続したAppStateを作成
+async fn create_mock_state() -> Option<AppState> {
+    let mut state = create_test_state().await?;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(spawn_mock_openai().await),
+    );
+    Some(state)
+}
+
 // ============================================
 // ヘルスチェックテスト（DBなしでも動作）
 // ============================================
@@ -181,6 +263,58 @@ async fn test_create_session_without_system_prompt() {
     assert!(json["system_prompt"].is_null());
 }
 
+#[tokio::test]
+async fn test_create_session_model() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let default_model = state.llm.default_model().to_string();
+
+    // 未指定ならデフォルトモデルが保存される
+    let app = create_app(state.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions")
+                .header("content-type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(json["model"], default_model.as_str());
+
+    // 許可リストにないモデルは400
+    let app = create_app(state);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"model": "not-allowed-model"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(json["error"]["code"], "VALIDATION_ERROR");
+}
+
 #[tokio::test]
 async fn test_get_session_not_found() {
     let state = match create_test_state().await {
@@ -234,83 +368,3508 @@ async fn test_delete_session_not_found() {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn test_session_chat_stream_not_found() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let app = create_app(state);
+
+    // ストリーム開始前のエラーは通常のJSONエラーレスポンスで返る
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions/00000000-0000-0000-0000-000000000000/chat/stream")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(json["error"]["code"], "NOT_FOUND");
+}
+
 // ============================================
-// セッションCRUDフローテスト
+// セッション内チャットテスト（モックのOpenAIサーバーを使用）
 // ============================================
 
 #[tokio::test]
-async fn test_session_crud_flow() {
-    let state = match create_test_state().await {
+async fn test_session_chat_with_mock_upstream() {
+    let state = match create_mock_state().await {
         Some(s) => s,
         None => return,
     };
 
-    // 1. セッション作成
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
     let app = create_app(state.clone());
     let response = app
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/sessions")
+                .uri(format!("/sessions/{}/chat", session.id))
                 .header("content-type", "application/json")
-                .body(Body::from(
-                    json!({"system_prompt": "CRUD test session"}).to_string(),
-                ))
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
                 .unwrap(),
         )
         .await
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::OK);
+
     let body = response.into_body().collect().await.unwrap().to_bytes();
-    let create_json: Value = serde_json::from_slice(&body).unwrap();
-    let session_id = create_json["id"].as_str().unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
 
-    // 2. セッション取得
-    let app = create_app(state.clone());
+    assert_eq!(json["response"], "Mock reply");
+    assert_eq!(json["model"], state.llm.default_model());
+    assert_eq!(json["message_count"], 2);
+}
+
+#[tokio::test]
+async fn test_session_chat_multipart_upload() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 受け取ったリクエストを記録するモックサーバー
+    async fn recording(
+        State(requests): State<Arc<Mutex<Vec<Value>>>>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        requests.lock().unwrap().push(body);
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [{"text": "A cat"}]}],
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(recording))
+        .with_state(requests.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state);
+
+    // テキストと画像・PDFを multipart で送信
+    let boundary = "test-boundary";
+    let body = format!(
+        "--{b}\r\n\
+         Content-Disposition: form-data; name=\"message\"\r\n\r\n\
+         What is in this image?\r\n\
+         --{b}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"cat.png\"\r\n\
+         Content-Type: image/png\r\n\r\n\
+         PNGDATA\r\n\
+         --{b}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"doc.pdf\"\r\n\
+         Content-Type: application/pdf\r\n\r\n\
+         PDFDATA\r\n\
+         --{b}--\r\n",
+        b = boundary
+    );
     let response = app
+        .clone()
         .oneshot(
             Request::builder()
-                .uri(format!("/sessions/{}", session_id))
-                .body(Body::empty())
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", boundary),
+                )
+                .body(Body::from(body))
                 .unwrap(),
         )
         .await
         .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let expected_parts = json!([
+        {"type": "input_text", "text": "What is in this image?"},
+        {"type": "input_image", "image_url": "data:image/png;base64,UE5HREFUQQ==", "detail": "auto"},
+        {"type": "input_file", "filename": "doc.pdf", "file_data": "data:application/pdf;base64,UERGREFUQQ=="}
+    ]);
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0]["input"][0]["content"], expected_parts);
+    }
 
+    // パーツは保存され、次のターンの履歴にも含まれる
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Thanks"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
     assert_eq!(response.status(), StatusCode::OK);
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1]["input"][0]["content"], expected_parts);
+        assert_eq!(requests[1]["input"][2]["content"], "Thanks");
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", session.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
     let body = response.into_body().collect().await.unwrap().to_bytes();
-    let get_json: Value = serde_json::from_slice(&body).unwrap();
-    assert_eq!(get_json["session"]["id"], session_id);
-    assert_eq!(get_json["session"]["system_prompt"], "CRUD test session");
-    assert!(get_json["messages"].as_array().unwrap().is_empty());
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["messages"][0]["content"], "What is in this image?");
+    assert_eq!(json["messages"][0]["parts"], expected_parts);
+    assert!(json["messages"][1]["parts"].is_null());
+}
+
+#[tokio::test]
+async fn test_session_chat_chained_history() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // リクエストを記録し、2番目のレスポンスだけ期限切れとして扱うモックサーバー
+    async fn chained(
+        State(requests): State<Arc<Mutex<Vec<Value>>>>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        let mut requests = requests.lock().unwrap();
+        requests.push(body.clone());
+
+        if body["previous_response_id"] == "resp_2" {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": {
+                    "message": "Previous response with id 'resp_2' not found.",
+                    "type": "invalid_request_error",
+                    "param": "previous_response_id",
+                    "code": "previous_response_not_found"
+                }})),
+            )
+                .into_response();
+        }
+
+        Json(json!({
+            "id": format!("resp_{}", requests.len()),
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [{"text": "ok"}]}],
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(chained))
+        .with_state(requests.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let app = create_app(state);
 
-    // 3. セッション削除
-    let app = create_app(state.clone());
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({"system_prompt": "Be brief.", "history_mode": "chained"}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let session: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(session["history_mode"], "chained");
+    let session_id = session["id"].as_str().unwrap().to_string();
+
+    for message in ["first", "second", "third"] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/chat", session_id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": message}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // 1回目は全履歴、2回目は新しいターンのみ、3回目は期限切れのため全履歴を送り直す
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 4);
+    assert!(requests[0].get("previous_response_id").is_none());
+    assert_eq!(requests[0]["input"].as_array().unwrap().len(), 1);
+    assert_eq!(requests[1]["previous_response_id"], "resp_1");
+    assert_eq!(requests[1]["input"], json!([{"type": "message", "role": "user", "content": "second"}]));
+    assert_eq!(requests[1]["instructions"], "Be brief.");
+    assert_eq!(requests[2]["previous_response_id"], "resp_2");
+    assert!(requests[3].get("previous_response_id").is_none());
+    assert_eq!(requests[3]["input"].as_array().unwrap().len(), 5);
+
+    // アシスタントメッセージにはレスポンスIDが保存される
     let response = app
         .oneshot(
             Request::builder()
-                .method("DELETE")
                 .uri(format!("/sessions/{}", session_id))
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let response_ids: Vec<&Value> = json["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|msg| &msg["response_id"])
+        .collect();
+    assert_eq!(
+        response_ids,
+        [&Value::Null, &json!("resp_1"), &Value::Null, &json!("resp_2"), &Value::Null, &json!("resp_4")]
+    );
+}
 
-    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+#[tokio::test]
+async fn test_session_chat_reasoning_summary() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
 
-    // 4. 削除後の取得は404
+    // 推論の設定を確認し、要約付きの reasoning アイテムを返すモックサーバー
+    async fn reasoning(Json(body): Json<Value>) -> Response {
+        assert_eq!(body["reasoning"], json!({"effort": "high", "summary": "auto"}));
+
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": [
+                {
+                    "type": "reasoning",
+                    "summary": [
+                        {"type": "summary_text", "text": "Considering the question"},
+                        {"type": "summary_text", "text": "Choosing an answer"}
+                    ]
+                },
+                {"type": "message", "content": [{"type": "output_text", "text": "42"}]}
+            ],
+            "usage": {
+                "input_tokens": 10,
+                "output_tokens": 50,
+                "total_tokens": 60,
+                "output_tokens_details": {"reasoning_tokens": 48}
+            }
+        }))
+        .into_response()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new().route("/v1/responses", post(reasoning));
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
     let app = create_app(state);
+
+    let request = json!({
+        "message": "What is the answer?",
+        "reasoning": {"effort": "high", "summary": "auto"}
+    });
+    let expected_summary = json!(["Considering the question", "Choosing an answer"]);
+
+    // 単発チャットは推論トークン数も返す
     let response = app
+        .clone()
         .oneshot(
             Request::builder()
-                .uri(format!("/sessions/{}", session_id))
+                .method("POST")
+                .uri("/chat")
+                .header("content-type", "application/json")
+                .body(Body::from(request.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["reasoning_summary"], expected_summary);
+    assert_eq!(json["usage"]["reasoning_tokens"], 48);
+
+    // セッション内チャットは要約をアシスタントメッセージと一緒に保存する
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(request.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], "42");
+    assert_eq!(json["reasoning_summary"], expected_summary);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", session.id))
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["messages"][0]["reasoning_summary"], json!([]));
+    assert_eq!(json["messages"][1]["reasoning_summary"], expected_summary);
+}
 
-    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+#[tokio::test]
+async fn test_session_chat_stream_with_mock_upstream() {
+    let state = match create_mock_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let app = create_app(state.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat/stream", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+
+    assert!(body.contains("event: delta"));
+    assert!(body.contains("event: completed"));
+    assert!(body.contains(r#""message_count":2"#));
+
+    // 完了後にユーザー・アシスタントのメッセージが保存されている
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1].content, "Mock reply");
+    assert_eq!(messages[1].status, "completed");
+}
+
+#[tokio::test]
+async fn test_session_chat_with_mock_provider() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(MockProvider::new().reply("Hi!").reply("Fine, thanks."));
+    let mut state = state;
+    state.llm = mock.clone();
+    let session = state
+        .session_repo
+        .create_session(
+            Some("Be brief.".to_string()),
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state.clone());
+
+    for (message, expected) in [("Hello", "Hi!"), ("How are you?", "Fine, thanks.")] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/chat", session.id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": message}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["response"], expected);
+    }
+
+    // システムプロンプトは instructions で、2ターン目は前のターンを含む履歴を送る
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|r| !r.stream));
+    assert_eq!(requests[0].options.instructions.as_deref(), Some("Be brief."));
+    assert_eq!(requests[0].options.model.as_deref(), Some(mock.default_model()));
+    let history: Vec<(String, String)> = requests[1]
+        .messages
+        .iter()
+        .map(|m| (m.role.clone(), m.content.text()))
+        .collect();
+    assert_eq!(
+        history,
+        [
+            ("user".to_string(), "Hello".to_string()),
+            ("assistant".to_string(), "Hi!".to_string()),
+            ("user".to_string(), "How are you?".to_string()),
+        ]
+    );
+    assert_eq!(mock.remaining(), 0);
+
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[3].content, "Fine, thanks.");
+    assert_eq!(messages[3].response_id.as_deref(), Some("mock_resp_2"));
+}
+
+#[tokio::test]
+async fn test_session_chat_errors_with_mock_provider() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .fail(OpenAIError::RateLimited {
+                message: "slow down".to_string(),
+                retry_after: None,
+            })
+            .then(MockReply::Stream {
+                deltas: vec!["Par".to_string(), "tial".to_string()],
+                error: Some(OpenAIError::ServerError("upstream crashed".to_string())),
+            })
+            .reply("Hi"),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state.clone());
+
+    let chat = |path: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/sessions/{}/{}", session.id, path))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": "Hello"}).to_string()))
+            .unwrap()
+    };
+
+    // 生成前のエラーはステータスコードで返す
+    let response = app.clone().oneshot(chat("chat")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // ストリーム開始後のエラーは差分の後に error イベントで返す
+    let response = app.clone().oneshot(chat("chat/stream")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains(r#""text":"Par""#));
+    assert!(body.contains("event: error"));
+    assert!(body.contains("UPSTREAM_SERVER_ERROR"));
+    assert!(!body.contains("event: completed"));
+
+    // どちらのエラーでも、ユーザーメッセージと送った分までの返答を "failed" で保存する
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages.len(), 4);
+    assert!(mock.requests()[1].stream);
+
+    let failed = &messages[1];
+    assert_eq!(failed.role, "assistant");
+    assert_eq!(failed.status, "failed");
+    assert_eq!(failed.content, "");
+    assert_eq!(failed.model.as_deref(), Some("gpt-5.2-chat-latest"));
+    assert!(failed.latency_ms.is_some());
+    assert_eq!(failed.error.as_deref(), Some("Rate limited: slow down"));
+    assert!(failed.prompt_tokens.is_none());
+
+    let failed = &messages[3];
+    assert_eq!(failed.status, "failed");
+    assert_eq!(failed.content, "Partial");
+    assert!(failed.latency_ms.is_some());
+    assert_eq!(
+        failed.error.as_deref(),
+        Some("OpenAI server error: upstream crashed")
+    );
+
+    // エラーで終わった返答は次のターンの履歴に含めない
+    let response = app.clone().oneshot(chat("chat")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let roles: Vec<String> = mock.requests()[2]
+        .messages
+        .iter()
+        .map(|m| m.role.clone())
+        .collect();
+    assert_eq!(roles, ["user", "user", "user"]);
+}
+
+#[tokio::test]
+async fn test_session_history_strategies() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("A1")
+            .reply("A2")
+            .reply("A3")
+            .reply("A4")
+            .reply("A5"),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    let app = create_app(state.clone());
+
+    let send = |method: &str, uri: String, body: Value| {
+        let app = app.clone();
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null))
+        }
+    };
+    let sent = |index: usize| -> Vec<String> {
+        mock.requests()[index]
+            .messages
+            .iter()
+            .map(|m| m.content.text())
+            .collect()
+    };
+
+    // 直近1ターンだけを送るセッション
+    let (status, session) = send(
+        "POST",
+        "/sessions".to_string(),
+        json!({"history_strategy": {"type": "last_turns", "turns": 1}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["history_strategy"]["type"], "last_turns");
+    let id = session["id"].as_str().unwrap().to_string();
+
+    for message in ["Q1", "Q2", "Q3"] {
+        let (status, _) = send(
+            "POST",
+            format!("/sessions/{}/chat", id),
+            json!({"message": message}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    assert_eq!(sent(2), ["Q2", "A2", "Q3"]);
+
+    // 送った履歴のIDが返答と一緒に保存される
+    let session_id = Uuid::parse_str(&id).unwrap();
+    let messages = state.session_repo.get_messages(session_id).await.unwrap();
+    assert_eq!(messages[5].context_message_ids, [messages[2].id, messages[3].id]);
+    assert!(messages[1].context_message_ids.is_empty());
+
+    // ピン留めしたメッセージは範囲外でも送る
+    let (status, pinned) = send(
+        "PATCH",
+        format!("/sessions/{}/messages/{}", id, messages[0].id),
+        json!({"pinned": true}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pinned["pinned"], true);
+
+    send("POST", format!("/sessions/{}/chat", id), json!({"message": "Q4"})).await;
+    assert_eq!(sent(3), ["Q1", "Q3", "A3", "Q4"]);
+
+    // トークン数の上限を超える履歴は送らない（ピン留めは残す）
+    let (status, updated) = send(
+        "PATCH",
+        format!("/sessions/{}", id),
+        json!({"history_strategy": {"type": "token_budget", "max_tokens": 1}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["history_strategy"]["max_tokens"], 1);
+
+    send("POST", format!("/sessions/{}/chat", id), json!({"message": "Q5"})).await;
+    assert_eq!(sent(4), ["Q1", "Q5"]);
+
+    // 不正な設定・存在しないメッセージ
+    let (status, _) = send(
+        "PATCH",
+        format!("/sessions/{}", id),
+        json!({"history_strategy": {"type": "last_turns", "turns": 0}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        "PATCH",
+        format!("/sessions/{}/messages/{}", id, Uuid::new_v4()),
+        json!({"pinned": true}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_session_summarize_strategy() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 返答と要約を交互に返す（3ターン目から、直前のターンより前を要約する）
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("A1")
+            .reply("A2")
+            .reply("S1")
+            .reply("A3")
+            .reply("S2")
+            .reply("A4"),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    let app = create_app(state.clone());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({
+                        "system_prompt": "Be brief.",
+                        "history_strategy": {"type": "summarize", "max_tokens": 60, "keep_turns": 1}
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let session: Value = serde_json::from_slice(&body).unwrap();
+    let id = session["id"].as_str().unwrap().to_string();
+
+    // 1メッセージで上限の大半を使う長さ
+    let question = |n: usize| format!("Q{} {}", n, "lorem ".repeat(20));
+    for n in 1..=4 {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/chat", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": question(n)}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let requests = mock.requests();
+    let text = |index: usize| -> Vec<String> {
+        requests[index]
+            .messages
+            .iter()
+            .map(|m| m.content.text())
+            .collect()
+    };
+    let instructions = |index: usize| requests[index].options.instructions.clone().unwrap();
+    assert_eq!(requests.len(), 6);
+
+    // 1ターン目を要約し、要約を instructions に加えて残りを送る
+    assert_eq!(instructions(2), history::SUMMARY_INSTRUCTIONS);
+    assert!(text(2)[0].contains("Q1") && text(2)[0].contains("[assistant] A1"));
+    assert!(!text(2)[0].contains("Q2"));
+    assert!(instructions(3).starts_with("Be brief."));
+    assert!(instructions(3).ends_with("S1"));
+    assert_eq!(text(3), [question(2), "A2".to_string(), question(3)]);
+
+    // 次の要約は前の要約に積み重ねる
+    assert!(text(4)[0].contains("Previous summary:\nS1"));
+    assert!(text(4)[0].contains("Q2") && !text(4)[0].contains("Q3"));
+    assert!(instructions(5).ends_with("S2") && !instructions(5).contains("S1"));
+    assert_eq!(text(5), [question(3), "A3".to_string(), question(4)]);
+
+    // 全履歴と最新の要約を取得できる
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 8);
+    assert_eq!(json["summary"]["content"], "S2");
+    assert_eq!(json["summary"]["message_count"], 4);
+    assert_eq!(json["summary"]["last_message_id"], messages[3]["id"]);
+    assert_eq!(
+        messages[7]["context_message_ids"],
+        json!([messages[4]["id"], messages[5]["id"]])
+    );
+}
+
+#[tokio::test]
+async fn test_semantic_search_over_messages() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 埋め込みが無効なら検索できない
+    let response = create_app(state.clone())
+        .oneshot(Request::builder().uri("/search?q=hello").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("Pasta needs salted boiling water.")
+            .reply("Rust ownership prevents data races."),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    // 他のテストの実行で保存された埋め込みと区別するための語（埋め込みのモデル名にも使う）
+    let tag = Uuid::new_v4().simple().to_string();
+    state.embedding_model = Some(format!("mock-embedding-{}", tag));
+    let app = create_app(state.clone());
+
+    let topics = [
+        format!("{} how do I cook italian pasta dinner", tag),
+        format!("{} explain rust borrow checker ownership", tag),
+    ];
+    let mut session_ids = Vec::new();
+    for topic in &topics {
+        let id = state
+            .session_repo
+            .create_session(
+                None,
+                None,
+                HistoryMode::Replay,
+                &HistoryStrategy::Full,
+                &GenerationOptions::default(),
+            )
+            .await
+            .unwrap()
+            .id;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/chat", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": topic}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        session_ids.push(id.to_string());
+    }
+
+    // 埋め込みはバックグラウンドで保存されるため、揃うまで待つ
+    let uri = format!("/search?q={}+rust+ownership+borrow&limit=3", tag);
+    let mut json = Value::Null;
+    for _ in 0..50 {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        json = serde_json::from_slice(&body).unwrap();
+        let found = json["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|r| r["content"].as_str().unwrap().contains(&tag))
+            .count();
+        if found == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let results = json["results"].as_array().unwrap();
+    assert!(results.len() <= 3);
+    assert_eq!(results[0]["content"], topics[1].as_str());
+    assert_eq!(results[0]["role"], "user");
+    assert_eq!(results[0]["session_id"], session_ids[1].as_str());
+    assert!(results[0]["score"].as_f64().unwrap() > 0.5);
+
+    // 空のクエリは拒否する
+    let response = app
+        .oneshot(Request::builder().uri("/search?q=%20").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_semantic_search_with_pgvector() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // pgvector 拡張がインストールされていない環境では実行しない
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&get_test_database_url())
+        .await
+        .unwrap();
+    let available: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'vector')",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    if !available {
+        eprintln!("Warning: pgvector is not available. Skipping pgvector test.");
+        return;
+    }
+
+    let index = EmbeddingIndex::PgVector {
+        dimensions: MOCK_EMBEDDING_DIMENSIONS,
+    };
+    vector::setup(&pool, index).await.unwrap();
+    // 何度実行してもよく、次元数が異なれば起動を止める
+    vector::setup(&pool, index).await.unwrap();
+    let mismatch = EmbeddingIndex::PgVector {
+        dimensions: MOCK_EMBEDDING_DIMENSIONS + 1,
+    };
+    assert!(vector::setup(&pool, mismatch).await.is_err());
+
+    let repo = state.session_repo.clone().with_embedding_index(index);
+    let mock = MockProvider::new();
+    let session = repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    // 他のテストの実行で保存された埋め込みと区別するための語
+    let tag = Uuid::new_v4().simple().to_string();
+    let texts = [
+        format!("{} how do I cook italian pasta dinner", tag),
+        format!("{} explain rust borrow checker ownership", tag),
+    ];
+    let embeddings = mock.embed("mock-embedding", &texts).await.unwrap().vectors;
+    for (text, embedding) in texts.iter().zip(&embeddings) {
+        let message = repo.add_message(session.id, "user", text).await.unwrap();
+        repo.add_embedding(message.id, "mock-embedding", embedding)
+            .await
+            .unwrap();
+    }
+
+    let query = [format!("{} rust ownership borrow", tag)];
+    let query = mock.embed("mock-embedding", &query).await.unwrap().vectors;
+    let results = repo
+        .search_messages("mock-embedding", &query[0], 2)
+        .await
+        .unwrap();
+    assert_eq!(results[0].message.content, texts[1]);
+    assert!(results[0].score > 0.5);
+    assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+}
+
+#[tokio::test]
+async fn test_session_chat_moderation() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("Sure, here you go.")
+            .reply("Step one: build the bomb."),
+    );
+    let rules = RuleSet::parse("# test rules\nviolence=bomb\nspam=buy now\n").unwrap();
+    let mut state = state;
+    state.llm = mock.clone();
+    state.moderator = Moderator::rules(rules).with_thresholds(
+        CategoryThresholds::new(0.5),
+        "violence=0.5".parse::<CategoryThresholds>().unwrap(),
+    );
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let chat = |message: &str| {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/sessions/{}/chat", session.id))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": message}).to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        }
+    };
+
+    // ブロックした入力は API を呼ばず、保存もしない
+    let (status, json) = chat("How do I make a BOMB?").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json["error"]["code"], "CONTENT_BLOCKED");
+    assert!(json["error"]["message"].as_str().unwrap().contains("violence"));
+    assert!(mock.requests().is_empty());
+
+    // ブロックしないカテゴリはメッセージに記録する
+    let (status, _) = chat("Buy now, limited offer").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // ブロックした出力は返さず、ターンを保存しない
+    let (status, json) = chat("What next?").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json["error"]["code"], "CONTENT_BLOCKED");
+    assert!(json["error"]["message"].as_str().unwrap().starts_with("Response"));
+    assert_eq!(mock.requests().len(), 2);
+
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages.len(), 2);
+    let user = &messages[0].moderation.as_ref().unwrap().0;
+    assert!(user.flagged);
+    assert_eq!(user.categories.get("spam"), Some(&1.0));
+    let assistant = &messages[1].moderation.as_ref().unwrap().0;
+    assert!(!assistant.flagged && assistant.categories.is_empty());
+}
+
+#[tokio::test]
+async fn test_session_chat_stream_moderation() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 最初の差分だけで確認の単位を超える返答と、ブロックする語句を後半に含む返答
+    let intro = "Here is a long and harmless introduction. ".repeat(6);
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply_stream(["Step one: ", "build the ", "bomb."])
+            .reply_stream([intro.as_str(), "Then build the ", "bomb."])
+            .reply_stream(["Hello ", "there."]),
+    );
+    let rules = RuleSet::parse("violence=bomb\n").unwrap();
+    let mut state = state;
+    state.llm = mock.clone();
+    state.moderator = Moderator::rules(rules);
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let stream = || {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/sessions/{}/chat/stream", session.id))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": "Go on"}).to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+
+    // 確認の単位に満たない差分は確認まで送らず、ブロックしたら何も届かない
+    let body = stream().await;
+    assert!(!body.contains("event: delta"), "{}", body);
+    assert!(!body.contains("bomb"));
+    assert!(body.contains("event: error"));
+    assert!(body.contains("CONTENT_BLOCKED"));
+
+    // 確認済みの前半は届くが、ブロックした後半は届かない
+    let body = stream().await;
+    assert!(body.contains("harmless introduction"));
+    assert!(!body.contains("bomb"));
+    assert!(!body.contains("Then build"));
+    assert!(body.contains("CONTENT_BLOCKED"));
+
+    // ブロックしない返答は最後まで届き、保存する
+    let body = stream().await;
+    assert!(body.contains("event: delta"));
+    assert!(body.contains("Hello there."));
+    assert!(body.contains("event: completed"));
+
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1].content, "Hello there.");
+}
+
+#[tokio::test]
+async fn test_session_chat_stream_moderation_checks_new_text_only() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 100 バイトの差分を 20 回返す長い返答
+    let chunks: Vec<String> = (0..20).map(|i| format!("{:<99}.", i)).collect();
+    let mock = Arc::new(MockProvider::new().reply_stream(chunks.iter().map(String::as_str)));
+    let mut state = state;
+    state.llm = mock.clone();
+    state.moderator = Moderator::api("omni-moderation-latest");
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat/stream", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Go on"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("event: completed"));
+
+    // 途中の確認は新しい差分と直前の一部だけを送り、最後に返答全体を確認する
+    let full = chunks.concat();
+    let moderations = mock.moderations();
+    assert_eq!(moderations.first().map(String::as_str), Some("Go on"));
+    assert_eq!(moderations.last(), Some(&full));
+    let windows = &moderations[1..moderations.len() - 1];
+    assert!(!windows.is_empty());
+    assert!(windows.iter().all(|text| text.len() <= 400));
+
+    // 出力の確認は何回呼んでも1件の使用量として記録する
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}/cost", session.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let moderation = json["models"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["model"] == "omni-moderation-latest")
+        .cloned()
+        .unwrap();
+    assert_eq!(moderation["calls"], 2);
+}
+
+#[tokio::test]
+async fn test_response_cache() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&get_test_database_url())
+        .await
+        .unwrap();
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("first")
+            .reply("second")
+            .reply("third")
+            .reply("fourth")
+            .reply("fifth")
+            .reply("sixth"),
+    );
+    let memory = CachedProvider::new(
+        mock.clone(),
+        Arc::new(MemoryCache::new(10)),
+        Duration::from_secs(60),
+    );
+    let mut state = state;
+    state.llm = Arc::new(memory);
+    let app = create_app(state.clone());
+
+    let chat = |app: Router, body: Value| async move {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/chat")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice::<Value>(&body).unwrap()
+    };
+
+    // 同じプロンプトはキャッシュから返す
+    let json = chat(app.clone(), json!({"message": "Hello"})).await;
+    assert_eq!(json["response"], "first");
+    assert_eq!(json["cached"], false);
+    let json = chat(app.clone(), json!({"message": "Hello"})).await;
+    assert_eq!(json["response"], "first");
+    assert_eq!(json["cached"], true);
+    assert_eq!(mock.requests().len(), 1);
+
+    // instructions が違えば別のキー
+    let json = chat(app.clone(), json!({"message": "Hello", "system_prompt": "Be brief."})).await;
+    assert_eq!(json["response"], "second");
+
+    // bypass_cache は API を呼び、結果でキャッシュを置き換える
+    let json = chat(app.clone(), json!({"message": "Hello", "bypass_cache": true})).await;
+    assert_eq!(json["response"], "third");
+    assert_eq!(json["cached"], false);
+    let json = chat(app.clone(), json!({"message": "Hello"})).await;
+    assert_eq!(json["response"], "third");
+    assert_eq!(json["cached"], true);
+    assert_eq!(mock.requests().len(), 3);
+
+    // PostgreSQL のキャッシュは別のプロバイダーからも読める（期限切れは読まない）
+    let message = format!("Cache me {}", Uuid::new_v4());
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(message),
+    }];
+    let postgres = |ttl: Duration| {
+        CachedProvider::new(mock.clone(), Arc::new(PostgresCache::new(pool.clone())), ttl)
+    };
+    let options = ChatOptions::default();
+    let response = postgres(Duration::from_secs(60))
+        .chat_with_history(messages.clone(), options.clone())
+        .await
+        .unwrap();
+    assert_eq!((response.response.as_str(), response.cached), ("fourth", false));
+    let response = postgres(Duration::from_secs(60))
+        .chat_with_history(messages.clone(), options.clone())
+        .await
+        .unwrap();
+    assert_eq!((response.response.as_str(), response.cached), ("fourth", true));
+
+    let expired = postgres(Duration::ZERO);
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(format!("Expire me {}", Uuid::new_v4())),
+    }];
+    let key = expired.cache_key(&messages, &options);
+    for reply in ["fifth", "sixth"] {
+        let response = expired
+            .chat_with_history(messages.clone(), options.clone())
+            .await
+            .unwrap();
+        assert_eq!((response.response.as_str(), response.cached), (reply, false));
+    }
+    let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM response_cache WHERE key = $1")
+        .bind(&key)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(rows, 0);
+    assert_eq!(mock.requests().len(), 6);
+}
+
+#[tokio::test]
+async fn test_response_cache_skips_tools_and_chained_turns() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let usage = Usage {
+        prompt_tokens: 100,
+        completion_tokens: 20,
+        total_tokens: 120,
+        ..Usage::default()
+    };
+    let mock = Arc::new(MockProvider::new().reply("Hi!").with_usage(usage));
+    let mut state = state;
+    state.llm = Arc::new(CachedProvider::new(
+        mock.clone(),
+        Arc::new(MemoryCache::new(10)),
+        Duration::from_secs(60),
+    ));
+    let app = create_app(state.clone());
+
+    // 2つ目のセッションの同じターンはキャッシュから返し、使用量とレスポンスIDを保存しない
+    let mut saved = Vec::new();
+    for _ in 0..2 {
+        let session = state
+            .session_repo
+            .create_session(
+                None,
+                None,
+                HistoryMode::Replay,
+                &HistoryStrategy::Full,
+                &GenerationOptions::default(),
+            )
+            .await
+            .unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/chat", session.id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": "Hello"}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages = state.session_repo.get_messages(session.id).await.unwrap();
+        saved.push(messages[1].clone());
+    }
+    assert_eq!(mock.requests().len(), 1);
+    assert_eq!(saved[0].response_id.as_deref(), Some("mock_resp_1"));
+    assert_eq!(saved[0].prompt_tokens, Some(100));
+    assert_eq!(saved[1].content, "Hi!");
+    assert_eq!(saved[1].response_id, None);
+    assert_eq!(saved[1].prompt_tokens, Some(0));
+
+    // previous_response_id を指定した呼び出しとツールを実行するプロバイダーはキャッシュしない
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text("Hello".to_string()),
+    }];
+    let chained = ChatOptions {
+        previous_response_id: Some("resp_1".to_string()),
+        ..ChatOptions::default()
+    };
+    let mock = Arc::new(MockProvider::new().reply("first").reply("second"));
+    let provider = CachedProvider::new(
+        mock.clone(),
+        Arc::new(MemoryCache::new(10)),
+        Duration::from_secs(60),
+    );
+    for reply in ["first", "second"] {
+        let response = provider
+            .chat_with_history(messages.clone(), chained.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            (response.response.as_str(), response.cached),
+            (reply, false)
+        );
+    }
+
+    let mock = Arc::new(
+        MockProvider::new()
+            .with_tools()
+            .reply("first")
+            .reply("second"),
+    );
+    let provider = CachedProvider::new(
+        mock.clone(),
+        Arc::new(MemoryCache::new(10)),
+        Duration::from_secs(60),
+    );
+    for reply in ["first", "second"] {
+        let response = provider
+            .chat_with_history(messages.clone(), ChatOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            (response.response.as_str(), response.cached),
+            (reply, false)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_cost_accounting() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let usage = Usage {
+        prompt_tokens: 1000,
+        completion_tokens: 500,
+        total_tokens: 1500,
+        reasoning_tokens: 100,
+        cached_tokens: 400,
+    };
+    let mock = Arc::new(
+        MockProvider::new()
+            .with_models(
+                "test-priced".to_string(),
+                vec!["test-unpriced".to_string()],
+            )
+            .reply("first")
+            .with_usage(usage.clone())
+            .reply_stream(["sec", "ond"])
+            .with_usage(usage)
+            .reply("unpriced"),
+    );
+    let price = Price {
+        input: 1.0,
+        cached_input: 0.5,
+        output: 2.0,
+        reasoning: 4.0,
+    };
+    let mut state = state;
+    state.llm = mock.clone();
+    state.prices = PriceTable::new().with("test-priced", price);
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let send = |method: &str, uri: String, body: Option<Value>| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        }
+    };
+
+    // (600 × 1 + 400 × 0.5 + 400 × 2 + 100 × 4) / 1M
+    let expected = 0.002;
+    let (status, body) = send(
+        "POST",
+        format!("/sessions/{}/chat", session.id),
+        Some(json!({"message": "Hello"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!((json["cost_usd"].as_f64().unwrap() - expected).abs() < 1e-12);
+
+    // ストリーミングも完了イベントに料金を含める
+    let (status, body) = send(
+        "POST",
+        format!("/sessions/{}/chat/stream", session.id),
+        Some(json!({"message": "Again"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("\"cost_usd\":0.002"), "{}", body);
+
+    // 単価が不明なモデルは null（セッション外の呼び出しも日ごとの合計に含める）
+    let (status, body) = send(
+        "POST",
+        "/chat".to_string(),
+        Some(json!({"message": "Hi", "model": "test-unpriced"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["cost_usd"].is_null());
+
+    let (status, body) = send("GET", format!("/sessions/{}/cost", session.id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["total"]["calls"], 2);
+    assert_eq!(json["total"]["cached_tokens"], 800);
+    assert_eq!(json["total"]["unpriced_calls"], 0);
+    assert!((json["total"]["cost_usd"].as_f64().unwrap() - 2.0 * expected).abs() < 1e-12);
+    assert_eq!(json["models"][0]["model"], "test-priced");
+
+    // 共有のDBのため、今日の合計は少なくともこのテストの分を含む
+    let today = chrono::Utc::now().date_naive();
+    let uri = format!("/costs/daily?from={}&to={}", today, today);
+    let (status, body) = send("GET", uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["days"][0]["date"], today.to_string());
+    assert!(json["total"]["calls"].as_i64().unwrap() >= 3);
+    assert!(json["total"]["unpriced_calls"].as_i64().unwrap() >= 1);
+    assert!(json["total"]["cost_usd"].as_f64().unwrap() >= 2.0 * expected - 1e-12);
+
+    let uri = format!("/costs/daily?from={}&to=2020-01-01", today);
+    let (status, _) = send("GET", uri, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // 削除したセッションの料金は日ごとの合計に残る
+    state.session_repo.delete_session(session.id).await.unwrap();
+    let (status, _) = send("GET", format!("/sessions/{}/cost", session.id), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_builtin_prices_use_longest_prefix() {
+    let prices = PriceTable::new();
+
+    // 接頭辞が同じ別料金のモデルを区別する
+    assert_eq!(
+        prices.price("o3-2025-04-16"),
+        Some(Price::new(2.0, 0.5, 8.0))
+    );
+    assert_eq!(
+        prices.price("o3-mini-2025-01-31"),
+        Some(Price::new(1.1, 0.55, 4.4))
+    );
+    assert_eq!(prices.price("o3-pro"), Some(Price::new(20.0, 20.0, 80.0)));
+    assert_eq!(
+        prices.price("gpt-5.2-chat-latest"),
+        Some(Price::new(1.75, 0.175, 14.0))
+    );
+    assert_eq!(
+        prices.price("gpt-5.2-pro"),
+        Some(Price::new(21.0, 21.0, 168.0))
+    );
+    assert_eq!(
+        prices.price("gpt-5-mini"),
+        Some(Price::new(0.25, 0.025, 2.0))
+    );
+    assert_eq!(prices.price("llama3"), None);
+
+    // 設定の単価は組み込みの単価より優先する
+    let prices = prices.with("o3", Price::new(1.0, 1.0, 1.0));
+    assert_eq!(prices.price("o3-mini"), Some(Price::new(1.0, 1.0, 1.0)));
+}
+
+#[tokio::test]
+async fn test_cost_accounting_for_embeddings_and_moderation() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(MockProvider::new().reply("Rust ownership"));
+    let mut state = state;
+    state.llm = mock.clone();
+    state.embedding_model = Some("text-embedding-3-small".to_string());
+    state.moderator = Moderator::api("omni-moderation-latest");
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "explain rust ownership"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 埋め込みはバックグラウンドで記録されるため、揃うまで待つ
+    let model_cost = |json: &Value, model: &str| {
+        json["models"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["model"] == model)
+            .cloned()
+    };
+    let mut json = Value::Null;
+    for _ in 0..50 {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/sessions/{}/cost", session.id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        json = serde_json::from_slice(&body).unwrap();
+        if model_cost(&json, "text-embedding-3-small").is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // 入力・出力のモデレーションは無料の呼び出しとして記録する
+    let moderation = model_cost(&json, "omni-moderation-latest").unwrap();
+    assert_eq!(moderation["calls"], 2);
+    assert_eq!(moderation["cost_usd"], 0.0);
+    assert_eq!(moderation["unpriced_calls"], 0);
+
+    // 埋め込みは入力のトークン数（モックでは単語数 3 + 2）で料金を計算する
+    let embedding = model_cost(&json, "text-embedding-3-small").unwrap();
+    assert_eq!(embedding["calls"], 1);
+    assert_eq!(embedding["prompt_tokens"], 5);
+    assert!((embedding["cost_usd"].as_f64().unwrap() - 5.0 * 0.02 / 1_000_000.0).abs() < 1e-15);
+    assert_eq!(json["total"]["calls"], 4);
+    assert_eq!(json["total"]["unpriced_calls"], 0);
+}
+
+#[tokio::test]
+async fn test_session_prompt_cache() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 受け取った prompt_cache_key を記録し、2回目以降はキャッシュしたトークン数を返すモックサーバー
+    type Keys = Arc<Mutex<Vec<Value>>>;
+    async fn responses(State(keys): State<Keys>, Json(body): Json<Value>) -> Response {
+        let calls = {
+            let mut keys = keys.lock().unwrap();
+            keys.push(body["prompt_cache_key"].clone());
+            keys.len()
+        };
+        let cached_tokens = if calls == 1 { 0 } else { 1024 };
+
+        Json(json!({
+            "id": format!("resp_{}", calls),
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [{"type": "output_text", "text": "ok"}]}],
+            "usage": {
+                "input_tokens": 1200,
+                "output_tokens": 5,
+                "total_tokens": 1205,
+                "input_tokens_details": {"cached_tokens": cached_tokens}
+            }
+        }))
+        .into_response()
+    }
+
+    let keys = Keys::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(responses))
+        .with_state(keys.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let app = create_app(state.clone());
+    let generation = GenerationOptions::default();
+    let create = |system_prompt: &str| {
+        state.session_repo.create_session(
+            Some(system_prompt.to_string()),
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &generation,
+        )
+    };
+    let session = create("You are a long, stable system prompt.").await.unwrap();
+    let other = create("A different system prompt.").await.unwrap();
+
+    let chat = |id: Uuid| {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/sessions/{}/chat", id))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": "Hello"}).to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    };
+    chat(session.id).await;
+    chat(session.id).await;
+    chat(other.id).await;
+
+    // 同じセッションでは同じキー、システムプロンプトが違えば別のキー
+    let keys = keys.lock().unwrap().clone();
+    assert!(keys[0].as_str().unwrap().starts_with("session_"));
+    assert_eq!(keys[0], keys[1]);
+    assert_ne!(keys[0], keys[2]);
+    assert_eq!(keys[0], session.prompt_cache_key());
+
+    let request = Request::builder()
+        .uri(format!("/sessions/{}", session.id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["messages"][3]["cached_tokens"], 1024);
+    assert_eq!(json["prompt_cache"]["prompt_tokens"], 2400);
+    assert_eq!(json["prompt_cache"]["cached_tokens"], 1024);
+    let ratio = json["prompt_cache"]["hit_ratio"].as_f64().unwrap();
+    assert!((ratio - 1024.0 / 2400.0).abs() < 1e-9);
+
+    // 使用量を保存した返答がなければ null
+    assert_eq!(PromptCacheStats::from_messages(&[]).hit_ratio, None);
+}
+
+#[tokio::test]
+async fn test_generation_options() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 受け取ったリクエストボディを記録するモックサーバー
+    type Bodies = Arc<Mutex<Vec<Value>>>;
+    async fn responses(State(bodies): State<Bodies>, Json(body): Json<Value>) -> Response {
+        bodies.lock().unwrap().push(body);
+
+        Json(json!({
+            "id": "resp_1",
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [{"type": "output_text", "text": "ok"}]}],
+            "usage": {"input_tokens": 10, "output_tokens": 5, "total_tokens": 15}
+        }))
+        .into_response()
+    }
+
+    let bodies = Bodies::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(responses))
+        .with_state(bodies.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let app = create_app(state);
+    let send = |method: &str, uri: String, body: Value| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+            (status, json)
+        }
+    };
+
+    // 範囲外の値は API を呼ばずに 400
+    let metadata: serde_json::Map<String, Value> =
+        (0..17).map(|i| (format!("k{}", i), json!("v"))).collect();
+    for invalid in [
+        json!({"temperature": 3}),
+        json!({"top_p": 1.5}),
+        json!({"max_output_tokens": 5}),
+        json!({"metadata": metadata}),
+    ] {
+        let mut body = invalid.clone();
+        body["message"] = json!("Hello");
+        let (status, _) = send("POST", "/chat".to_string(), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", invalid);
+
+        let body = json!({"generation": invalid});
+        let (status, _) = send("POST", "/sessions".to_string(), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", invalid);
+    }
+    assert!(bodies.lock().unwrap().is_empty());
+
+    // POST /chat ではそのまま送る（verbosity は text.verbosity）
+    let body = json!({
+        "message": "Hello",
+        "temperature": 0.2,
+        "top_p": 0.9,
+        "max_output_tokens": 256,
+        "truncation": "auto",
+        "parallel_tool_calls": false,
+        "store": false,
+        "metadata": {"user": "alice"},
+        "verbosity": "low"
+    });
+    let (status, _) = send("POST", "/chat".to_string(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = bodies.lock().unwrap()[0].clone();
+    assert_eq!(sent["temperature"], 0.2);
+    assert_eq!(sent["top_p"], 0.9);
+    assert_eq!(sent["max_output_tokens"], 256);
+    assert_eq!(sent["truncation"], "auto");
+    assert_eq!(sent["parallel_tool_calls"], false);
+    assert_eq!(sent["store"], false);
+    assert_eq!(sent["metadata"], json!({"user": "alice"}));
+    assert_eq!(sent["text"]["verbosity"], "low");
+
+    // セッションのデフォルトを保存し、リクエストで指定した項目だけ上書きする
+    let body = json!({"generation": {"temperature": 0.5, "max_output_tokens": 512}});
+    let (status, session) = send("POST", "/sessions".to_string(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["generation"]["temperature"], 0.5);
+    let id = session["id"].as_str().unwrap().to_string();
+
+    let chat = format!("/sessions/{}/chat", id);
+    let (status, _) = send("POST", chat.clone(), json!({"message": "Hi"})).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({"message": "Hi", "temperature": 1.0});
+    let (status, _) = send("POST", chat.clone(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("POST", chat.clone(), json!({"message": "Hi", "top_p": 2})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let sent = bodies.lock().unwrap()[1..].to_vec();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0]["temperature"], 0.5);
+    assert_eq!(sent[0]["max_output_tokens"], 512);
+    assert!(sent[0].get("top_p").is_none());
+    assert!(sent[0].get("text").is_none());
+    assert_eq!(sent[1]["temperature"], 1.0);
+    assert_eq!(sent[1]["max_output_tokens"], 512);
+
+    // PATCH ではデフォルト全体を置き換える（不正な値は 400 で、他の項目も更新しない）
+    let uri = format!("/sessions/{}", id);
+    let body = json!({
+        "history_strategy": {"type": "last_turns", "turns": 2},
+        "generation": {"top_p": 3}
+    });
+    let (status, _) = send("PATCH", uri.clone(), body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, session) = send("PATCH", uri.clone(), json!({"generation": {"top_p": 0.8}})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["generation"], json!({"top_p": 0.8}));
+    assert_eq!(session["history_strategy"]["type"], "full");
+
+    // 両方を指定すると両方を更新し、指定しない項目は変えない
+    let body = json!({
+        "history_strategy": {"type": "last_turns", "turns": 2},
+        "generation": {"top_p": 0.8}
+    });
+    let (status, session) = send("PATCH", uri.clone(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["history_strategy"]["turns"], 2);
+    assert_eq!(session["generation"], json!({"top_p": 0.8}));
+    let body = json!({"history_strategy": {"type": "full"}});
+    let (status, session) = send("PATCH", uri.clone(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["history_strategy"]["type"], "full");
+    assert_eq!(session["generation"], json!({"top_p": 0.8}));
+
+    let missing = format!("/sessions/{}", Uuid::new_v4());
+    let (status, _) = send("PATCH", missing, json!({"generation": {}})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send("POST", chat, json!({"message": "Hi"})).await;
+    assert_eq!(status, StatusCode::OK);
+    let sent = bodies.lock().unwrap().last().unwrap().clone();
+    assert_eq!(sent["top_p"], 0.8);
+    assert!(sent.get("temperature").is_none());
+}
+
+#[tokio::test]
+async fn test_session_message_metadata() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let usage = Usage {
+        prompt_tokens: 120,
+        completion_tokens: 30,
+        total_tokens: 150,
+        reasoning_tokens: 10,
+        cached_tokens: 64,
+    };
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply("Hi there")
+            .with_usage(usage.clone())
+            .with_latency(Duration::from_millis(30))
+            .reply_stream(["Str", "eamed"])
+            .with_usage(usage),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    let app = create_app(state.clone());
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    for path in ["chat", "chat/stream"] {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/sessions/{}/{}", session.id, path))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": "Hello"}).to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.into_body().collect().await.unwrap();
+    }
+
+    let request = Request::builder()
+        .uri(format!("/sessions/{}", session.id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let messages = json["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+
+    // ユーザーメッセージには記録しない
+    assert!(messages[0]["model"].is_null() && messages[0]["prompt_tokens"].is_null());
+
+    for assistant in [&messages[1], &messages[3]] {
+        assert_eq!(assistant["model"], "gpt-5.2-chat-latest");
+        assert!(assistant["response_id"].as_str().unwrap().starts_with("mock_resp_"));
+        assert_eq!(assistant["status"], "completed");
+        assert_eq!(assistant["prompt_tokens"], 120);
+        assert_eq!(assistant["completion_tokens"], 30);
+        assert_eq!(assistant["cached_tokens"], 64);
+        assert_eq!(assistant["reasoning_tokens"], 10);
+        assert!(assistant["cost_usd"].as_f64().unwrap() > 0.0);
+        assert!(assistant["error"].is_null());
+    }
+    assert!(messages[1]["latency_ms"].as_i64().unwrap() >= 30);
+    assert!(messages[3]["latency_ms"].as_i64().is_some());
+}
+
+#[tokio::test]
+async fn test_context_window_exceeded_rejected_before_call() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(MockProvider::new().reply("unused"));
+    let mut state = state;
+    state.llm = mock.clone();
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state.clone());
+
+    // デフォルトモデルのコンテキストウィンドウ（128,000 トークン）を超える入力
+    let message = "word ".repeat(200_000);
+    for uri in [format!("/sessions/{}/chat", session.id), "/chat".to_string()] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": message}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "VALIDATION_ERROR");
+        assert!(json["error"]["message"].as_str().unwrap().contains("context window"));
+    }
+
+    // API は呼ばず、メッセージも保存しない
+    assert!(mock.requests().is_empty());
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert!(messages.is_empty());
+}
+
+#[tokio::test]
+async fn test_context_window_checked_before_moderation() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mock = Arc::new(MockProvider::new().reply("ok"));
+    let mut state = state;
+    state.llm = mock.clone();
+    state.moderator = Moderator::api("omni-moderation-latest");
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Summarize {
+                max_tokens: 1000,
+                keep_turns: 1,
+            },
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state.clone());
+    let send = |uri: String, message: String| {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": message}).to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+
+    // コンテキストウィンドウを超える入力はモデレーション・要約の API も呼ばずに拒否
+    let message = "word ".repeat(200_000);
+    for uri in [format!("/sessions/{}/chat", session.id), "/chat".to_string()] {
+        let status = send(uri, message.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    assert!(mock.moderations().is_empty());
+    assert!(mock.requests().is_empty());
+
+    // 収まる入力はモデレーションを通してから生成する
+    let status = send(format!("/sessions/{}/chat", session.id), "Hello".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mock.moderations(), vec!["Hello".to_string(), "ok".to_string()]);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[test]
+fn test_tokenizer_counts_with_bpe_ranks() {
+    // 単一バイトと結合のランク表（tiktoken 形式: base64 のトークン ランク）
+    // h, e, l, o, " ", ll, he, llo, hello
+    let ranks = "aA== 0\nZQ== 1\nbA== 2\nbw== 3\nIA== 4\nbGw= 5\naGU= 6\nbGxv 7\naGVsbG8= 8";
+    let tokenizer =
+        Tokenizer::new().with_bpe(Encoding::O200kBase, Bpe::from_tiktoken(ranks).unwrap());
+
+    // "hello" は1トークン、" hello" は結合できない " " と "hello" の2トークン
+    assert!(tokenizer.is_exact(Encoding::O200kBase));
+    assert_eq!(tokenizer.count_text(Encoding::O200kBase, "hello hello"), 3);
+
+    // ランク表がないエンコーディングは近似（ASCII 4バイトで1トークン）
+    assert!(!tokenizer.is_exact(Encoding::Cl100kBase));
+    assert_eq!(tokenizer.count_text(Encoding::Cl100kBase, "hello hello"), 4);
+
+    // メッセージごとに3トークン、返答の開始に3トークンが加わる（"user" は結合なしの4トークン）
+    let messages = [Message {
+        role: "user".to_string(),
+        content: MessageContent::Text("hello hello".to_string()),
+    }];
+    let tokens = tokenizer.count_tokens("gpt-4o", &messages, Some("hello"));
+    assert_eq!(tokens, (3 + 1) + (3 + 4 + 3) + 3);
+
+    assert_eq!(tokenizer::context_window("gpt-4o-2024-08-06"), Some(128_000));
+    assert_eq!(tokenizer::context_window("llama3"), None);
+    assert!(tokenizer.check_context_window("llama3", &messages, None).is_ok());
+}
+
+#[test]
+fn test_tokenizer_counts_long_piece() {
+    // a, aa, aaaa, aaaaaaaa（8文字ずつ1トークンに結合される）
+    let ranks = "YQ== 0\nYWE= 1\nYWFhYQ== 2\nYWFhYWFhYWE= 3";
+    let tokenizer =
+        Tokenizer::new().with_bpe(Encoding::O200kBase, Bpe::from_tiktoken(ranks).unwrap());
+
+    // 事前分割で1片になる長い単語も、結合を繰り返して数え切れる
+    let text = "a".repeat(80_000);
+    assert_eq!(tokenizer.count_text(Encoding::O200kBase, &text), 10_000);
+    assert_eq!(tokenizer.count_text(Encoding::O200kBase, &text[..12]), 2);
+}
+
+#[test]
+fn test_tokenizer_requires_rank_files() {
+    let dir = std::env::temp_dir().join(format!("tokenizer-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(Encoding::O200kBase.file_name()), "aGVsbG8= 0\n").unwrap();
+
+    // cl100k_base のランク表がないため読み込めない
+    let error = Tokenizer::from_dir(&dir).err().unwrap();
+    assert!(error.contains("cl100k_base"), "{}", error);
+
+    std::fs::write(dir.join(Encoding::Cl100kBase.file_name()), "aGVsbG8= 0\n").unwrap();
+    let tokenizer = Tokenizer::from_dir(&dir).unwrap();
+    assert!(tokenizer.is_exact(Encoding::O200kBase));
+    assert!(tokenizer.is_exact(Encoding::Cl100kBase));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tokenizer_counts_with_tiktoken_ranks() {
+    // 実際のランク表は TOKENIZER_DIR がある場合のみ確認する
+    let _ = dotenvy::dotenv();
+    let Some(dir) = std::env::var_os("TOKENIZER_DIR") else {
+        eprintln!("Skipping: TOKENIZER_DIR is not set");
+        return;
+    };
+    let tokenizer = Tokenizer::from_dir(std::path::Path::new(&dir)).unwrap();
+
+    assert_eq!(tokenizer.count_text(Encoding::O200kBase, "hello world"), 2);
+    assert_eq!(tokenizer.count_text(Encoding::Cl100kBase, "hello world"), 2);
+    assert_eq!(
+        tokenizer.count_text(Encoding::Cl100kBase, "tiktoken is great!"),
+        6
+    );
+}
+
+#[test]
+fn test_tokenizer_matches_tiktoken_counts() {
+    // tiktoken のランク表から、下の文字列の部分バイト列にあたるトークンだけを抜き出したもの
+    // （事前分割の違いは、結合できるトークンの違いとしてトークン数に表れる）
+    let tokenizer = Tokenizer::new()
+        .with_bpe(
+            Encoding::O200kBase,
+            Bpe::from_tiktoken(include_str!("fixtures/o200k_base.subset.tiktoken")).unwrap(),
+        )
+        .with_bpe(
+            Encoding::Cl100kBase,
+            Bpe::from_tiktoken(include_str!("fixtures/cl100k_base.subset.tiktoken")).unwrap(),
+        );
+
+    // (テキスト, o200k_base のトークン数, cl100k_base のトークン数)。tiktoken で数えた値
+    let cases = [
+        (
+            "HTTPServer handles URLs like https://example.com/path/to/file\n",
+            13,
+            13,
+        ),
+        ("I'm sure they'll say it's FINE, WON'T they?", 12, 15),
+        (
+            "1234567 apples cost $12.50!!\n\n\n  indented   spaces   end",
+            18,
+            18,
+        ),
+        ("日本語のテキストも数えます。", 11, 13),
+        ("camelCaseIdentifier and snake_case_name / a/b/c", 11, 11),
+        ("NASA's iPhone ÉCOLE naïve → café", 11, 10),
+    ];
+    for (text, o200k, cl100k) in cases {
+        let counts = (
+            tokenizer.count_text(Encoding::O200kBase, text),
+            tokenizer.count_text(Encoding::Cl100kBase, text),
+        );
+        assert_eq!(counts, (o200k, cl100k), "{:?}", text);
+    }
+}
+
+#[tokio::test]
+async fn test_session_chat_with_chat_completions_provider() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // Chat Completions 形式で応答し、受け取ったリクエストを記録するモックサーバー
+    async fn completions(
+        State(requests): State<Arc<Mutex<Vec<Value>>>>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        requests.lock().unwrap().push(body.clone());
+        let usage = json!({"prompt_tokens": 8, "completion_tokens": 2, "total_tokens": 10});
+
+        if body["stream"] == true {
+            let chunks = [
+                json!({"id": "chatcmpl-2", "model": "local-model",
+                       "choices": [{"delta": {"role": "assistant", "content": ""}}]}),
+                json!({"id": "chatcmpl-2", "model": "local-model",
+                       "choices": [{"delta": {"content": "Local "}}]}),
+                json!({"id": "chatcmpl-2", "model": "local-model",
+                       "choices": [{"delta": {"content": "stream"}}]}),
+                json!({"id": "chatcmpl-2", "model": "local-model", "choices": [], "usage": usage}),
+            ];
+            let body: String = chunks
+                .iter()
+                .map(|c| format!("data: {}\n\n", c))
+                .chain(["data: [DONE]\n\n".to_string()])
+                .collect();
+            ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+        } else {
+            Json(json!({
+                "id": "chatcmpl-1",
+                "model": "local-model",
+                "choices": [{"message": {"role": "assistant", "content": "Local reply"}}],
+                "usage": usage
+            }))
+            .into_response()
+        }
+    }
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/chat/completions", post(completions))
+        .with_state(requests.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        ChatCompletionsService::new(String::new())
+            .with_base_url(format!("http://{}/v1", addr))
+            .with_models("local-model".to_string(), Vec::new()),
+    );
+    // previous_response_id に対応していないため、chained でも全履歴を送る
+    let session = state
+        .session_repo
+        .create_session(
+            Some("Be brief.".to_string()),
+            Some("local-model".to_string()),
+            HistoryMode::Chained,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+    let app = create_app(state.clone());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], "Local reply");
+    assert_eq!(json["model"], "local-model");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat/stream", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Again"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains(r#""text":"Local ""#));
+    assert!(body.contains("event: completed"));
+    assert!(body.contains(r#""message_count":4"#));
+
+    // Chat Completions API で表せないオプションは黙って捨てずに 400 を返す
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({"message": "Terse", "verbosity": "low"}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["code"], "INVALID_REQUEST");
+    assert!(
+        json["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("verbosity")
+    );
+
+    // システムプロンプトは先頭の system メッセージになり、履歴は毎回全て送る
+    // prompt_cache_key には対応していないため送らない
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].get("prompt_cache_key").is_none());
+    assert_eq!(
+        requests[1]["messages"],
+        json!([
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Hello"},
+            {"role": "assistant", "content": "Local reply"},
+            {"role": "user", "content": "Again"}
+        ])
+    );
+    assert_eq!(requests[1]["stream_options"]["include_usage"], true);
+
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    assert_eq!(messages[3].content, "Local stream");
+    assert_eq!(messages[3].response_id.as_deref(), Some("chatcmpl-2"));
+}
+
+#[tokio::test]
+async fn test_session_chat_retries_transient_errors() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 最初の1回だけ429を返し、以降は成功するモックサーバー
+    async fn flaky(State(calls): State<Arc<AtomicUsize>>) -> Response {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("retry-after-ms", "10")],
+                Json(json!({"error": {"type": "requests", "code": "rate_limit_exceeded"}})),
+            )
+                .into_response();
+        }
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [{"text": "Recovered"}]}],
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(flaky))
+        .with_state(calls.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    let openai = OpenAIService::new(String::new())
+        .with_base_url(format!("http://{}/v1", addr))
+        .with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        });
+    state.llm = Arc::new(openai);
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let app = create_app(state);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/sessions/{}/chat", session.id))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], "Recovered");
+}
+
+#[tokio::test]
+async fn test_retries_only_unprocessed_requests() {
+    // 登録した順にステータスコード（と Retry-After の秒数）を返すモックサーバー
+    type Script = Arc<Mutex<Vec<(StatusCode, Option<&'static str>)>>>;
+    async fn scripted(State(script): State<Script>) -> Response {
+        let (status, retry_after) = script.lock().unwrap().remove(0);
+        if status == StatusCode::OK {
+            return Json(json!({
+                "id": "resp_mock",
+                "model": "mock-model",
+                "output": [{"type": "message", "content": [{"text": "Recovered"}]}],
+                "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+            }))
+            .into_response();
+        }
+        let error = json!({"error": {"message": "upstream failed", "type": "requests"}});
+        match retry_after {
+            Some(secs) => (status, [("retry-after", secs)], Json(error)).into_response(),
+            None => (status, Json(error)).into_response(),
+        }
+    }
+
+    let script = Script::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(scripted))
+        .with_state(script.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let openai = OpenAIService::new(String::new())
+        .with_base_url(format!("http://{}/v1", addr))
+        .with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        });
+
+    // (返すステータスコード, 成功するか, 使われずに残る数)
+    let ok = (StatusCode::OK, None);
+    let cases = [
+        // サーバーが処理した可能性のある 5xx は、二重の生成を避けるため再試行しない
+        (
+            vec![(StatusCode::INTERNAL_SERVER_ERROR, None), ok],
+            false,
+            1,
+        ),
+        (vec![(StatusCode::SERVICE_UNAVAILABLE, None), ok], false, 1),
+        // Retry-After 付きの 503 と 429 は再試行する（長すぎる待機時間は max_delay まで）
+        (
+            vec![(StatusCode::SERVICE_UNAVAILABLE, Some("0")), ok],
+            true,
+            0,
+        ),
+        (
+            vec![(StatusCode::TOO_MANY_REQUESTS, Some("3600")), ok],
+            true,
+            0,
+        ),
+    ];
+    for (responses, succeeds, remaining) in cases {
+        *script.lock().unwrap() = responses;
+        let started = std::time::Instant::now();
+        let result = openai
+            .chat_with_history(Vec::new(), ChatOptions::default())
+            .await;
+
+        assert_eq!(result.is_ok(), succeeds, "{:?}", result.err());
+        assert_eq!(script.lock().unwrap().len(), remaining);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[tokio::test]
+async fn test_chat_times_out_hung_upstream() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // リクエストを受け取ったまま応答しないモックサーバー
+    async fn hang() -> Response {
+        std::future::pending().await
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new().route("/v1/responses", post(hang));
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let openai = OpenAIService::new(String::new())
+        .with_base_url(format!("http://{}/v1", addr))
+        .with_retry_policy(RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            deadline: Some(Duration::from_millis(300)),
+            ..RetryPolicy::default()
+        });
+
+    // 制限時間で打ち切り、Timeout を返す
+    let started = std::time::Instant::now();
+    let result = openai.chat_with_history(Vec::new(), ChatOptions::default()).await;
+    assert!(matches!(result, Err(OpenAIError::Timeout)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let mut state = state;
+    state.llm = Arc::new(openai);
+    let app = create_app(state);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/chat")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"message": "Hello"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["code"], "UPSTREAM_TIMEOUT");
+}
+
+#[tokio::test]
+async fn test_chat_maps_upstream_errors() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // メッセージ本文に応じてエラー種別を切り替えるモックサーバー
+    async fn failing(
+        State(calls): State<Arc<AtomicUsize>>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        calls.fetch_add(1, Ordering::SeqCst);
+        match body["input"][0]["content"].as_str().unwrap_or_default() {
+            "quota" => (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(json!({"error": {
+                    "message": "You exceeded your current quota",
+                    "type": "insufficient_quota",
+                    "code": "insufficient_quota"
+                }})),
+            )
+                .into_response(),
+            "rate" => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("retry-after", "3")],
+                Json(json!({"error": {
+                    "message": "Rate limit reached",
+                    "type": "requests",
+                    "code": "rate_limit_exceeded"
+                }})),
+            )
+                .into_response(),
+            _ => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": {
+                    "message": "Input is too long",
+                    "type": "invalid_request_error",
+                    "code": "context_length_exceeded",
+                    "param": "input"
+                }})),
+            )
+                .into_response(),
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new()
+        .route("/v1/responses", post(failing))
+        .with_state(calls.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    // 待機時間がテストを遅くしないよう、リトライは無効にする（回数は別途確認）
+    let openai = OpenAIService::new(String::new())
+        .with_base_url(format!("http://{}/v1", addr))
+        .with_retry_policy(RetryPolicy::disabled());
+    state.llm = Arc::new(openai);
+    let app = create_app(state);
+
+    let cases = [
+        ("quota", StatusCode::SERVICE_UNAVAILABLE, "QUOTA_EXCEEDED"),
+        ("rate", StatusCode::TOO_MANY_REQUESTS, "RATE_LIMITED"),
+        ("long", StatusCode::BAD_REQUEST, "CONTEXT_LENGTH_EXCEEDED"),
+    ];
+    for (message, status, code) in cases {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/chat")
+                    .header("content-type", "application/json")
+                    .body(Body::from(json!({"message": message}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status, "case: {}", message);
+        if message == "rate" {
+            assert_eq!(response.headers()[header::RETRY_AFTER], "3");
+        } else {
+            assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        }
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], code);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+/// テスト用のツール（2つの数を足す）
+struct AddTool;
+
+#[async_trait::async_trait]
+impl Tool for AddTool {
+    fn name(&self) -> &str {
+        "add"
+    }
+
+    fn description(&self) -> &str {
+        "Add two numbers"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"a": {"type": "number"}, "b": {"type": "number"}},
+            "required": ["a", "b"]
+        })
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<Value, ToolError> {
+        let (Some(a), Some(b)) = (arguments["a"].as_f64(), arguments["b"].as_f64()) else {
+            return Err(ToolError::InvalidArguments("a and b are required".to_string()));
+        };
+        Ok(json!({ "sum": a + b }))
+    }
+}
+
+#[tokio::test]
+async fn test_chat_runs_tool_calls() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // ツール結果がなければ add を要求し、結果を受け取ったらその内容を返すモックサーバー
+    async fn tool_calling(Json(body): Json<Value>) -> Response {
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["name"], "add");
+
+        let input = body["input"].as_array().unwrap();
+        let output = input
+            .iter()
+            .find(|item| item["type"] == "function_call_output")
+            .map(|item| item["output"].as_str().unwrap().to_string());
+
+        let output_item = match output {
+            None => json!({
+                "type": "function_call",
+                "call_id": "call_1",
+                "name": "add",
+                "arguments": "{\"a\": 2, \"b\": 3}"
+            }),
+            Some(output) => {
+                assert_eq!(input[input.len() - 2]["type"], "function_call");
+                assert_eq!(input[input.len() - 2]["call_id"], "call_1");
+                json!({"type": "message", "content": [{"text": output}]})
+            }
+        };
+
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": [output_item],
+            "usage": {"input_tokens": 5, "output_tokens": 1, "total_tokens": 6}
+        }))
+        .into_response()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new().route("/v1/responses", post(tool_calling));
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let openai = OpenAIService::new(String::new())
+        .with_base_url(format!("http://{}/v1", addr))
+        .with_tools(ToolRegistry::new().register(AddTool));
+
+    let request = || {
+        Request::builder()
+            .method("POST")
+            .uri("/chat")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": "What is 2 + 3?"}).to_string()))
+            .unwrap()
+    };
+
+    // ツール結果を付けて再リクエストし、最終的な返答を返す
+    let mut state = state;
+    state.llm = Arc::new(openai.clone());
+    let response = create_app(state.clone()).oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], r#"{"sum":5.0}"#);
+    // 2回分のリクエストの使用量を合計する
+    assert_eq!(json["usage"]["total_tokens"], 12);
+
+    // ステップ数の上限に達したらエラーを返す
+    state.llm = Arc::new(openai.with_max_tool_steps(0));
+    let response = create_app(state).oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["code"], "TOOL_STEP_LIMIT_EXCEEDED");
+}
+
+/// 構造化出力のテスト用の型
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+struct Sentiment {
+    label: Label,
+    score: f64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Label {
+    Positive,
+    Negative,
+}
+
+/// serde の属性を含む構造化出力のテスト用の型
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Review {
+    product_name: String,
+    sentiment: Option<Sentiment>,
+    tags: Vec<Label>,
+    #[serde(default)]
+    stars: u8,
+}
+
+/// 値がスキーマに合うか確認する（`strict_schema` が出力するキーワードのみ対応）
+fn conforms(schema: &Value, value: &Value) -> Result<(), String> {
+    if let Some(options) = schema["anyOf"].as_array() {
+        if options.iter().any(|option| conforms(option, value).is_ok()) {
+            return Ok(());
+        }
+        return Err(format!("{} matches none of {}", value, schema));
+    }
+    if let Some(values) = schema["enum"].as_array()
+        && !values.contains(value)
+    {
+        return Err(format!("{} is not one of {:?}", value, values));
+    }
+
+    // 型の配列（`Option<T>` の `["string", "null"]` など）はいずれかに合えばよい
+    if let Some(types) = schema["type"].as_array() {
+        let mut single = schema.clone();
+        for schema_type in types {
+            single["type"] = schema_type.clone();
+            if conforms(&single, value).is_ok() {
+                return Ok(());
+            }
+        }
+        return Err(format!("{} matches none of {}", value, schema));
+    }
+
+    let matches = match schema["type"].as_str() {
+        Some("string") => value.is_string(),
+        Some("boolean") => value.is_boolean(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("null") => value.is_null(),
+        Some("array") => {
+            let Some(items) = value.as_array() else {
+                return Err(format!("{} is not an array", value));
+            };
+            return items
+                .iter()
+                .try_for_each(|item| conforms(&schema["items"], item));
+        }
+        Some("object") => {
+            let Some(object) = value.as_object() else {
+                return Err(format!("{} is not an object", value));
+            };
+            for name in schema["required"].as_array().into_iter().flatten() {
+                let name = name.as_str().unwrap();
+                if !object.contains_key(name) {
+                    return Err(format!("missing property '{}'", name));
+                }
+            }
+            for (name, property) in object {
+                match schema["properties"].get(name) {
+                    Some(property_schema) => conforms(property_schema, property)?,
+                    None if schema["additionalProperties"] == false => {
+                        return Err(format!("unexpected property '{}'", name));
+                    }
+                    None => {}
+                }
+            }
+            true
+        }
+        other => return Err(format!("unsupported schema type {:?}", other)),
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("{} does not match {}", value, schema))
+    }
+}
+
+/// サンプル値をシリアライズしてスキーマに合うことを確認し、元の値に戻せることを確認する
+fn round_trip<T: JsonSchema + Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(
+    sample: T,
+) {
+    let value = serde_json::to_value(&sample).unwrap();
+    if let Err(e) = conforms(&strict_schema::<T>(), &value) {
+        panic!("{} does not match its schema: {}", schema_name::<T>(), e);
+    }
+    assert_eq!(serde_json::from_value::<T>(value).unwrap(), sample);
+}
+
+#[test]
+fn test_json_schema_round_trips_sample_values() {
+    // 実装済みの全ての型で、シリアライズした値がスキーマに合う
+    round_trip("text".to_string());
+    round_trip(true);
+    round_trip(-8_i8);
+    round_trip(-16_i16);
+    round_trip(-32_i32);
+    round_trip(-64_i64);
+    round_trip(-1_isize);
+    round_trip(8_u8);
+    round_trip(16_u16);
+    round_trip(32_u32);
+    round_trip(u64::MAX);
+    round_trip(1_usize);
+    round_trip(0.5_f32);
+    round_trip(2.5_f64);
+    round_trip(Some(1_i32));
+    round_trip(None::<String>);
+    round_trip(vec![Some(true), None]);
+    round_trip(Vec::<f64>::new());
+    round_trip(vec![Sentiment {
+        label: Label::Positive,
+        score: 0.9,
+    }]);
+    round_trip(Review {
+        product_name: "Rust".to_string(),
+        sentiment: Some(Sentiment {
+            label: Label::Negative,
+            score: 0.1,
+        }),
+        tags: vec![Label::Positive],
+        stars: 5,
+    });
+    round_trip(Review {
+        product_name: String::new(),
+        sentiment: None,
+        tags: Vec::new(),
+        stars: 0,
+    });
+
+    // スキーマは serde の形（名前の変更・省略可能なフィールド）から導出し、Strict モードに合わせる
+    let schema = strict_schema::<Review>();
+    assert_eq!(schema_name::<Review>(), "Review");
+    assert_eq!(schema_name::<Vec<Review>>(), "Array_of_Review");
+    assert_eq!(
+        schema["required"],
+        json!(["productName", "sentiment", "stars", "tags"])
+    );
+    assert_eq!(schema["additionalProperties"], false);
+    assert_eq!(
+        schema["properties"]["sentiment"]["additionalProperties"],
+        false
+    );
+    assert_eq!(
+        schema["properties"]["tags"]["items"]["enum"],
+        json!(["positive", "negative"])
+    );
+    let stars = &schema["properties"]["stars"];
+    assert!(stars.get("format").is_none() && stars.get("default").is_none());
+    assert!(schema.get("$schema").is_none() && schema.get("title").is_none());
+
+    // 型とスキーマが食い違う値は合わない（確認が常に通るわけではない）
+    let schema = strict_schema::<Sentiment>();
+    assert!(conforms(&schema, &json!({"label": "positive", "score": 0.9})).is_ok());
+    assert!(conforms(&schema, &json!({"label": "neutral", "score": 0.9})).is_err());
+    assert!(conforms(&schema, &json!({"label": "positive"})).is_err());
+    assert!(conforms(&schema, &json!({"label": "positive", "score": 0.9, "x": 1})).is_err());
+    assert!(conforms(&strict_schema::<i32>(), &json!(1.5)).is_err());
+    assert!(conforms(&strict_schema::<Option<bool>>(), &json!("true")).is_err());
+    assert!(conforms(&strict_schema::<Vec<u8>>(), &json!(["1"])).is_err());
+}
+
+#[tokio::test]
+async fn test_chat_extracts_all_output_parts() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 複数のメッセージ・パーツ・引用を返す（"refuse" の場合は拒否する）モックサーバー
+    async fn multi_part(Json(body): Json<Value>) -> Response {
+        let output = if body["input"][0]["content"] == "refuse" {
+            json!([{"type": "message", "content": [
+                {"type": "refusal", "refusal": "I can't help with that."}
+            ]}])
+        } else {
+            json!([
+                {"type": "message", "content": [
+                    {"type": "output_text", "text": "Rust は", "annotations": []},
+                    {"type": "output_text", "text": "安全です。", "annotations": [
+                        {"type": "url_citation", "url": "https://www.rust-lang.org",
+                         "title": "Rust", "start_index": 0, "end_index": 4}
+                    ]}
+                ]},
+                {"type": "message", "content": [
+                    {"type": "output_text", "text": "See docs.", "annotations": [
+                        {"type": "file_citation", "file_id": "file_1",
+                         "filename": "guide.pdf", "index": 4},
+                        {"type": "file_path", "file_id": "file_2", "index": 0}
+                    ]}
+                ]}
+            ])
+        };
+
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": output,
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new().route("/v1/responses", post(multi_part));
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+    let app = create_app(state);
+
+    let chat = |message: &str| {
+        Request::builder()
+            .method("POST")
+            .uri("/chat")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"message": message}).to_string()))
+            .unwrap()
+    };
+
+    // テキストは全パーツを連結し、引用の位置は連結後の文字位置になる
+    let response = app.clone().oneshot(chat("What is Rust?")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], "Rust は安全です。\n\nSee docs.");
+    assert_eq!(json["refused"], false);
+    assert_eq!(
+        json["citations"],
+        json!([
+            {"type": "url", "url": "https://www.rust-lang.org", "title": "Rust",
+             "start_index": 6, "end_index": 10},
+            {"type": "file", "file_id": "file_1", "filename": "guide.pdf", "index": 17}
+        ])
+    );
+
+    let response = app.oneshot(chat("refuse")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["response"], "");
+    assert_eq!(json["refused"], true);
+    assert_eq!(json["refusal"], "I can't help with that.");
+    assert!(json.get("citations").is_none());
+}
+
+#[tokio::test]
+async fn test_chat_structured_output() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // スキーマ名に応じて JSON、拒否、スキーマ不一致の出力を返すモックサーバー
+    async fn structured(Json(body): Json<Value>) -> Response {
+        let format = &body["text"]["format"];
+        assert_eq!(format["type"], "json_schema");
+        assert_eq!(format["strict"], true);
+
+        let content = match format["name"].as_str().unwrap() {
+            "Sentiment" => {
+                assert_eq!(format["schema"]["required"], json!(["label", "score"]));
+                json!({"type": "output_text", "text": r#"{"label":"positive","score":0.9}"#})
+            }
+            "refuse" => json!({"type": "refusal", "refusal": "I can't help with that."}),
+            _ => json!({"type": "output_text", "text": "not json"}),
+        };
+
+        Json(json!({
+            "id": "resp_mock",
+            "model": "mock-model",
+            "output": [{"type": "message", "content": [content]}],
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mock = Router::new().route("/v1/responses", post(structured));
+    tokio::spawn(async move {
+        axum::serve(listener, mock).await.unwrap();
+    });
+
+    let mut state = state;
+    state.llm = Arc::new(
+        OpenAIService::new(String::new()).with_base_url(format!("http://{}/v1", addr)),
+    );
+
+    // Rust の型から作ったスキーマで呼び出し、型付きで受け取る
+    let result = state
+        .llm
+        .chat_structured::<Sentiment>(ChatRequest {
+            message: "I love Rust".into(),
+            system_prompt: None,
+            model: None,
+            response_format: None,
+            reasoning: None,
+            bypass_cache: false,
+            generation: GenerationOptions::default(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(result.data.label, Label::Positive);
+    assert_eq!(result.data.score, 0.9);
+
+    // HTTP では response_format で指定し、parsed に結果を返す
+    let app = create_app(state);
+    let format = |name: &str| {
+        json!({
+            "message": "I love Rust",
+            "response_format": {
+                "type": "json_schema",
+                "name": name,
+                "schema": strict_schema::<Sentiment>()
+            }
+        })
+    };
+    let cases = [
+        ("Sentiment", StatusCode::OK, None),
+        ("refuse", StatusCode::UNPROCESSABLE_ENTITY, Some("MODEL_REFUSAL")),
+        ("broken", StatusCode::BAD_GATEWAY, Some("SCHEMA_MISMATCH")),
+    ];
+    for (name, status, code) in cases {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/chat")
+                    .header("content-type", "application/json")
+                    .body(Body::from(format(name).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "case: {}", name);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        match code {
+            None => assert_eq!(json["parsed"], json!({"label": "positive", "score": 0.9})),
+            Some(code) => assert_eq!(json["error"]["code"], code),
+        }
+    }
+}
+
+// ============================================
+// セッションCRUDフローテスト
+// ============================================
+
+#[tokio::test]
+async fn test_session_crud_flow() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 1. セッション作成
+    let app = create_app(state.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/sessions")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({"system_prompt": "CRUD test session"}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let create_json: Value = serde_json::from_slice(&body).unwrap();
+    let session_id = create_json["id"].as_str().unwrap();
+
+    // 2. セッション取得
+    let app = create_app(state.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", session_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let get_json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(get_json["session"]["id"], session_id);
+    assert_eq!(get_json["session"]["system_prompt"], "CRUD test session");
+    assert!(get_json["messages"].as_array().unwrap().is_empty());
+
+    // 3. セッション削除
+    let app = create_app(state.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/sessions/{}", session_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // 4. 削除後の取得は404
+    let app = create_app(state);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", session_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+// ============================================
+// WebSocketテスト（実サーバーを起動して接続）
+// ============================================
+
+#[tokio::test]
+async fn test_session_ws_protocol_errors() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, create_app(state)).await.unwrap();
+    });
+
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/sessions/{}/ws", addr, session.id))
+            .await
+            .unwrap();
+
+    // 生成中でなければキャンセルはエラー
+    socket
+        .send(WsMessage::text(json!({"type": "cancel"}).to_string()))
+        .await
+        .unwrap();
+    let frame: Value = match socket.next().await.unwrap().unwrap() {
+        WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+        other => panic!("unexpected frame: {:?}", other),
+    };
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["code"], "VALIDATION_ERROR");
+
+    // 不正なフレームもエラー
+    socket
+        .send(WsMessage::text(json!({"type": "unknown"}).to_string()))
+        .await
+        .unwrap();
+    let frame: Value = match socket.next().await.unwrap().unwrap() {
+        WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+        other => panic!("unexpected frame: {:?}", other),
+    };
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["code"], "VALIDATION_ERROR");
+}
+
+#[tokio::test]
+async fn test_session_ws_cancel_with_mock_provider() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    // 差分ごとに待機するので、生成途中でキャンセルできる
+    let mock = Arc::new(
+        MockProvider::new()
+            .reply_stream(["one ", "two ", "three ", "four ", "five"])
+            .with_latency(Duration::from_millis(100))
+            .reply("Done."),
+    );
+    let mut state = state;
+    state.llm = mock.clone();
+    let session = state
+        .session_repo
+        .create_session(
+            None,
+            None,
+            HistoryMode::Replay,
+            &HistoryStrategy::Full,
+            &GenerationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_app(state.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/sessions/{}/ws", addr, session.id))
+            .await
+            .unwrap();
+
+    socket
+        .send(WsMessage::text(json!({"type": "chat", "message": "Count"}).to_string()))
+        .await
+        .unwrap();
+
+    // 最初の差分を受け取ったらキャンセル
+    let mut next_frame = async || -> Value {
+        match socket.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    };
+    let frame = next_frame().await;
+    assert_eq!(frame["type"], "delta");
+    assert_eq!(frame["text"], "one ");
+
+    // キャンセル直後の次のターンは、キャンセルしたターンの保存を待ってから始める
+    socket
+        .send(WsMessage::text(json!({"type": "cancel"}).to_string()))
+        .await
+        .unwrap();
+    socket
+        .send(WsMessage::text(json!({"type": "chat", "message": "Again"}).to_string()))
+        .await
+        .unwrap();
+
+    let frame = loop {
+        let frame = match socket.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+            other => panic!("unexpected frame: {:?}", other),
+        };
+        if frame["type"] != "delta" {
+            break frame;
+        }
+    };
+    assert_eq!(frame["type"], "cancelled");
+    assert_eq!(frame["message_count"], 2);
+
+    let frame = loop {
+        let frame = match socket.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+            other => panic!("unexpected frame: {:?}", other),
+        };
+        if frame["type"] == "completed" {
+            break frame;
+        }
+    };
+    assert_eq!(frame["response"], "Done.");
+    assert_eq!(frame["message_count"], 4);
+
+    // 途中までの返答が "incomplete" として保存される
+    let messages = state.session_repo.get_messages(session.id).await.unwrap();
+    let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents[0], "Count");
+    assert_eq!(&contents[2..], ["Again", "Done."]);
+    let history = &mock.requests()[1].messages;
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[1].content.text(), messages[1].content);
+    assert_eq!(messages[1].status, "incomplete");
+    assert!(messages[1].content.starts_with("one "));
+    assert_ne!(messages[1].content, "one two three four five");
+    assert_eq!(messages[1].response_id, None);
+    assert_eq!(messages[1].error.as_deref(), Some("Cancelled before completion"));
+    assert_eq!(messages[1].prompt_tokens, None);
+}
+
+#[tokio::test]
+async fn test_session_ws_not_found() {
+    let state = match create_test_state().await {
+        Some(s) => s,
+        None => return,
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, create_app(state)).await.unwrap();
+    });
+
+    // 存在しないセッションはアップグレードせずに404を返す
+    let result = tokio_tungstenite::connect_async(format!(
+        "ws://{}/sessions/00000000-0000-0000-0000-000000000000/ws",
+        addr
+    ))
+    .await;
+
+    match result {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        other => panic!("expected HTTP 404, got {:?}", other.map(|_| ())),
+    }
 }
@@ -6,7 +6,7 @@ use std::fs;
 use std::path::PathBuf;
 
 /// CLI設定
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub default: DefaultConfig,
@@ -16,31 +16,20 @@ pub struct Config {
 pub struct DefaultConfig {
     #[serde(default = "default_system_prompt")]
     pub system_prompt: String,
-    #[serde(default = "default_model")]
-    pub model: String,
+    /// 未設定ならプロバイダーのデフォルトモデル（OPENAI_MODEL）
+    #[serde(default)]
+    pub model: Option<String>,
 }
 
 fn default_system_prompt() -> String {
     "You are a helpful assistant.".to_string()
 }
 
-fn default_model() -> String {
-    "gpt-4o-mini".to_string()
-}
-
 impl Default for DefaultConfig {
     fn default() -> Self {
         Self {
             system_prompt: default_system_prompt(),
-            model: default_model(),
-        }
-    }
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            default: DefaultConfig::default(),
+            model: None,
         }
     }
 }
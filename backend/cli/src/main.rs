@@ -9,7 +9,7 @@ mod session;
 use clap::{Parser, Subcommand};
 use colored::Colorize;
 
-use backend_core::OpenAIService;
+use backend_core::services::provider;
 
 use crate::config::Config;
 use crate::session::{list_sessions, Session};
@@ -31,6 +31,10 @@ enum Commands {
         #[arg(short, long)]
         system: Option<String>,
 
+        /// Model to use (defaults to the config file's model)
+        #[arg(short, long)]
+        model: Option<String>,
+
         /// Load an existing session
         #[arg(short, long)]
         load: Option<String>,
@@ -44,6 +48,10 @@ enum Commands {
         /// System prompt to use
         #[arg(short, long)]
         system: Option<String>,
+
+        /// Model to use (defaults to the config file's model)
+        #[arg(short, long)]
+        model: Option<String>,
     },
 
     /// Manage saved sessions
@@ -71,32 +79,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let cli = Cli::parse();
 
-    // OpenAIサービス初期化
-    let api_key = std::env::var("OPENAI_API_KEY").map_err(|_| {
-        eprintln!("{}", "Error: OPENAI_API_KEY environment variable not set".red());
+    // LLMプロバイダー初期化（API サーバーと同じ環境変数 LLM_PROVIDER・OPENAI_* を使う）
+    let settings = backend_core::Config::from_env_without_database().unwrap_or_else(|e| {
+        eprintln!("{}", format!("Error: {}", e).red());
         std::process::exit(1);
-    })?;
-    let openai = OpenAIService::new(api_key);
+    });
+    let llm = provider::from_config(&settings);
 
     match cli.command {
-        Commands::Chat { system, load } => {
-            repl::run_repl(&openai, system, load).await?;
+        Commands::Chat {
+            system,
+            model,
+            load,
+        } => {
+            repl::run_repl(llm.as_ref(), system, model, load).await?;
         }
 
-        Commands::Ask { question, system } => {
+        Commands::Ask {
+            question,
+            system,
+            model,
+        } => {
             let config = Config::load();
             let system_prompt = system.or(Some(config.default.system_prompt));
+            // 未指定ならプロバイダーのデフォルトモデル（OPENAI_MODEL）
+            let model = model.or(config.default.model);
 
             let messages = vec![backend_core::models::Message {
                 role: "user".to_string(),
-                content: question,
+                content: question.into(),
             }];
 
-            match openai.chat_with_history(messages, system_prompt).await {
-                Ok(response) => {
-                    println!("{}", response.response);
+            match repl::stream_to_stdout(llm.as_ref(), messages, system_prompt, model).await {
+                Ok(_) => {
+                    println!();
                 }
                 Err(e) => {
+                    println!();
                     eprintln!("{}", format!("Error: {}", e).red());
                     std::process::exit(1);
                 }
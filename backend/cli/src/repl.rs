@@ -1,18 +1,24 @@
 //! インタラクティブREPL
 
+use std::io::{self, Write};
+
 use colored::Colorize;
+use futures::StreamExt;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 
-use backend_core::OpenAIService;
+use backend_core::models::{ChatOptions, ChatResponse, ChatStreamEvent, Message};
+use backend_core::services::OpenAIError;
+use backend_core::LlmProvider;
 
 use crate::config::Config;
 use crate::session::{list_sessions, Session};
 
 /// REPLを実行
 pub async fn run_repl(
-    openai: &OpenAIService,
+    llm: &dyn LlmProvider,
     system_prompt: Option<String>,
+    model: Option<String>,
     load_session: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let config = Config::load();
@@ -21,7 +27,11 @@ pub async fn run_repl(
     // セッション初期化
     let mut session = if let Some(name) = load_session {
         match Session::load(&name) {
-            Ok(s) => {
+            Ok(mut s) => {
+                // --model 指定時は読み込んだセッションのモデルを上書き
+                if model.is_some() {
+                    s.model = model;
+                }
                 println!(
                     "{}",
                     format!("Loaded session: {} ({} messages)", s.name, s.messages.len()).green()
@@ -35,7 +45,10 @@ pub async fn run_repl(
         }
     } else {
         let prompt = system_prompt.unwrap_or(config.default.system_prompt);
-        Session::new(None, prompt)
+        let model = model
+            .or(config.default.model.clone())
+            .unwrap_or_else(|| llm.default_model().to_string());
+        Session::new(None, prompt, model)
     };
 
     // 履歴ファイルパス
@@ -77,12 +90,15 @@ pub async fn run_repl(
                 print!("{}", "Assistant: ".blue().bold());
                 let messages = session.to_api_messages();
                 let instructions = session.system_prompt();
-                match openai.chat_with_history(messages, instructions).await {
+                // モデル未設定の古いセッションは設定ファイル（なければプロバイダー）のデフォルトを使う
+                let model = session.model.clone().or(config.default.model.clone());
+                match stream_to_stdout(llm, messages, instructions, model).await {
                     Ok(response) => {
-                        println!("{}", response.response);
+                        println!();
                         session.add_message("assistant", &response.response);
                     }
                     Err(e) => {
+                        println!();
                         eprintln!("{}", format!("API Error: {}", e).red());
                         // 失敗したメッセージを削除
                         session.messages.pop();
@@ -110,6 +126,40 @@ pub async fn run_repl(
     Ok(())
 }
 
+/// ストリーミングで応答を取得し、差分を逐次標準出力に表示
+///
+/// 表示し終えた応答全体を返す（末尾の改行は出力しない）。
+pub async fn stream_to_stdout(
+    llm: &dyn LlmProvider,
+    messages: Vec<Message>,
+    instructions: Option<String>,
+    model: Option<String>,
+) -> Result<ChatResponse, OpenAIError> {
+    let options = ChatOptions {
+        instructions,
+        model,
+        ..ChatOptions::default()
+    };
+    let mut stream = llm.chat_with_history_stream(messages, options).await?;
+
+    let mut stdout = io::stdout();
+    let _ = stdout.flush();
+
+    while let Some(event) = stream.next().await {
+        match event? {
+            ChatStreamEvent::Delta { text } => {
+                print!("{}", text);
+                let _ = stdout.flush();
+            }
+            ChatStreamEvent::Completed(response) => return Ok(response),
+        }
+    }
+
+    Err(OpenAIError::StreamError(
+        "stream ended before completion".to_string(),
+    ))
+}
+
 enum CommandResult {
     Continue,
     Exit,
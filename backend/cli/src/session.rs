@@ -12,6 +12,9 @@ use crate::config::Config;
 pub struct Session {
     pub name: String,
     pub system_prompt: String,
+    /// 使用するモデル（古いセッションファイルにはないため省略可）
+    #[serde(default)]
+    pub model: Option<String>,
     pub messages: Vec<Message>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -26,11 +29,12 @@ pub struct Message {
 
 impl Session {
     /// 新しいセッションを作成
-    pub fn new(name: Option<String>, system_prompt: String) -> Self {
+    pub fn new(name: Option<String>, system_prompt: String, model: String) -> Self {
         let now = Utc::now();
         Self {
             name: name.unwrap_or_else(|| format!("session-{}", now.format("%Y%m%d-%H%M%S"))),
             system_prompt,
+            model: Some(model),
             messages: Vec::new(),
             created_at: now,
             updated_at: now,
@@ -86,7 +90,7 @@ impl Session {
             .iter()
             .map(|msg| backend_core::models::Message {
                 role: msg.role.clone(),
-                content: msg.content.clone(),
+                content: msg.content.clone().into(),
             })
             .collect()
     }
@@ -112,20 +116,19 @@ pub fn list_sessions() -> Result<Vec<SessionSummary>, std::io::Error> {
     for entry in fs::read_dir(sessions_dir)? {
         let entry = entry?;
         let path = entry.path();
-        if path.extension().map_or(false, |ext| ext == "json") {
-            if let Ok(content) = fs::read_to_string(&path) {
-                if let Ok(session) = serde_json::from_str::<Session>(&content) {
-                    sessions.push(SessionSummary {
-                        name: session.name,
-                        message_count: session.messages.len(),
-                        updated_at: session.updated_at,
-                    });
-                }
-            }
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Ok(content) = fs::read_to_string(&path)
+            && let Ok(session) = serde_json::from_str::<Session>(&content)
+        {
+            sessions.push(SessionSummary {
+                name: session.name,
+                message_count: session.messages.len(),
+                updated_at: session.updated_at,
+            });
         }
     }
 
-    sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
     Ok(sessions)
 }
 
@@ -1,23 +1,193 @@
 use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::db::vector::{EmbeddingIndex, MAX_INDEXED_DIMENSIONS};
+use crate::moderation::{
+    CategoryThresholds, ModerationMode, DEFAULT_BLOCK_THRESHOLD, DEFAULT_FLAG_THRESHOLD,
+    DEFAULT_MODERATION_MODEL,
+};
+use crate::pricing::PriceTable;
+use crate::services::cache::{CacheKind, DEFAULT_CACHE_CAPACITY, DEFAULT_CACHE_TTL};
+use crate::services::{
+    ProviderKind, RetryPolicy, DEFAULT_BASE_URL, DEFAULT_MAX_TOOL_STEPS, DEFAULT_MODEL,
+};
+use crate::tokenizer::TokenizerMode;
 
 /// アプリケーション設定
 #[derive(Clone)]
 pub struct Config {
+    /// 使用する API（Responses API または Chat Completions 互換 API）
+    pub llm_provider: ProviderKind,
+    /// APIキー（ローカルサーバーなど不要な場合は空文字列）
     pub openai_api_key: String,
+    /// API のベースURL
+    pub openai_base_url: String,
+    /// 全リクエストに付与する追加ヘッダー
+    pub openai_extra_headers: HeaderMap,
+    /// モデル未指定時に使用するモデル
+    pub openai_model: String,
+    /// リクエストで指定を許可するモデル
+    pub openai_allowed_models: Vec<String>,
+    /// 一時的な失敗のリトライポリシー
+    pub openai_retry_policy: RetryPolicy,
+    /// ツール呼び出しループの最大ステップ数
+    pub openai_max_tool_steps: usize,
+    /// 応答キャッシュの保存先
+    pub response_cache: CacheKind,
+    /// 応答キャッシュの有効期間
+    pub response_cache_ttl: Duration,
+    /// メモリキャッシュの最大件数
+    pub response_cache_capacity: usize,
+    /// モデルごとの単価（組み込みの単価を上書き・追加する）
+    pub model_prices: PriceTable,
+    /// メッセージの埋め込みに使うモデル（未設定なら埋め込み・意味検索を無効にする）
+    pub embedding_model: Option<String>,
+    /// 埋め込みベクトルの索引（REAL[] の全件走査または pgvector）
+    pub embedding_index: EmbeddingIndex,
+    /// 入力・出力のモデレーションの方式
+    pub moderation: ModerationMode,
+    /// Moderations API のモデル
+    pub moderation_model: String,
+    /// ローカルのルールのファイル（`MODERATION=rules` の場合）
+    pub moderation_rules: Option<PathBuf>,
+    /// メッセージに記録するカテゴリごとのしきい値
+    pub moderation_flag_thresholds: CategoryThresholds,
+    /// ブロックするカテゴリごとのしきい値
+    pub moderation_block_thresholds: CategoryThresholds,
+    /// トークン数の数え方（BPE のランク表または近似値）
+    pub tokenizer: TokenizerMode,
+    /// tiktoken のランク表（`o200k_base.tiktoken` など）を置いたディレクトリ
+    pub tokenizer_dir: Option<PathBuf>,
     pub host: String,
     pub port: u16,
+    /// データベースのURL（`from_env_without_database` で未設定の場合は空文字列）
     pub database_url: String,
 }
 
 impl Config {
     /// 環境変数から設定を読み込む
     pub fn from_env() -> Result<Self, String> {
+        Self::load(true)
+    }
+
+    /// 環境変数から設定を読み込む（`DATABASE_URL` を必須にしない。データベースを使わない CLI 向け）
+    pub fn from_env_without_database() -> Result<Self, String> {
+        Self::load(false)
+    }
+
+    fn load(require_database: bool) -> Result<Self, String> {
         let _ = dotenvy::dotenv();
 
-        let openai_api_key =
-            env::var("OPENAI_API_KEY").map_err(|_| "OPENAI_API_KEY is not set")?;
+        // ベースURLを変更した場合（ローカルサーバーなど）はAPIキーを省略できる
+        let openai_base_url = env::var("OPENAI_BASE_URL").ok();
+
+        let openai_api_key = match env::var("OPENAI_API_KEY") {
+            Ok(key) => key,
+            Err(_) if openai_base_url.is_some() => String::new(),
+            Err(_) => return Err("OPENAI_API_KEY is not set".to_string()),
+        };
+
+        let openai_base_url = openai_base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        // カンマ区切りの name=value（例: "api-key=xxx,X-Custom=yyy"）
+        let openai_extra_headers = match env::var("OPENAI_EXTRA_HEADERS") {
+            Ok(v) => parse_headers(&v)?,
+            Err(_) => HeaderMap::new(),
+        };
+
+        let openai_model = env::var("OPENAI_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+
+        // カンマ区切り（未設定ならデフォルトモデルのみ）
+        let openai_allowed_models = env::var("OPENAI_ALLOWED_MODELS")
+            .map(|v| {
+                v.split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|_| vec![openai_model.clone()]);
+
+        // リトライポリシー（未設定の項目はデフォルト値）
+        let defaults = RetryPolicy::default();
+        let deadline_ms = parse_env(
+            "OPENAI_RETRY_DEADLINE_MS",
+            defaults.deadline.map_or(0, |d| d.as_millis() as u64),
+        )?;
+        let openai_retry_policy = RetryPolicy {
+            max_attempts: parse_env("OPENAI_RETRY_MAX_ATTEMPTS", defaults.max_attempts)?.max(1),
+            base_delay: Duration::from_millis(parse_env(
+                "OPENAI_RETRY_BASE_DELAY_MS",
+                defaults.base_delay.as_millis() as u64,
+            )?),
+            jitter: parse_env("OPENAI_RETRY_JITTER", defaults.jitter)?,
+            // 0 は制限なし
+            deadline: (deadline_ms > 0).then(|| Duration::from_millis(deadline_ms)),
+            ..defaults
+        };
+
+        let openai_max_tool_steps = parse_env("OPENAI_MAX_TOOL_STEPS", DEFAULT_MAX_TOOL_STEPS)?;
+
+        // "responses"（デフォルト）または "chat_completions"
+        let llm_provider = parse_env("LLM_PROVIDER", ProviderKind::default())?;
+
+        // "off"（デフォルト）、"memory" または "postgres"
+        let response_cache = parse_env("RESPONSE_CACHE", CacheKind::default())?;
+        let response_cache_ttl = Duration::from_secs(parse_env(
+            "RESPONSE_CACHE_TTL_SECS",
+            DEFAULT_CACHE_TTL.as_secs(),
+        )?);
+        let response_cache_capacity =
+            parse_env("RESPONSE_CACHE_CAPACITY", DEFAULT_CACHE_CAPACITY)?;
 
-        let database_url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL is not set")?;
+        // カンマ区切りの model=input:cached_input:output[:reasoning]（100万トークンあたりの USD）
+        let model_prices = parse_env("MODEL_PRICES", PriceTable::default())?;
+
+        let embedding_model = env::var("EMBEDDING_MODEL").ok().filter(|m| !m.is_empty());
+
+        // "scan"（デフォルト）または "pgvector"（次元数は EMBEDDING_DIMENSIONS）
+        let embedding_index = match parse_env("EMBEDDING_INDEX", EmbeddingIndex::default())? {
+            EmbeddingIndex::PgVector { dimensions } => {
+                let dimensions = parse_env("EMBEDDING_DIMENSIONS", dimensions)?;
+                if !(1..=MAX_INDEXED_DIMENSIONS).contains(&dimensions) {
+                    return Err(format!(
+                        "EMBEDDING_DIMENSIONS must be between 1 and {}",
+                        MAX_INDEXED_DIMENSIONS
+                    ));
+                }
+                EmbeddingIndex::PgVector { dimensions }
+            }
+            index => index,
+        };
+
+        // "off"（デフォルト）、"api" または "rules"
+        let moderation = parse_env("MODERATION", ModerationMode::default())?;
+        let moderation_model = env::var("MODERATION_MODEL")
+            .unwrap_or_else(|_| DEFAULT_MODERATION_MODEL.to_string());
+        let moderation_rules = env::var("MODERATION_RULES").ok().map(PathBuf::from);
+
+        // カンマ区切りの category=threshold（"*" は指定のないカテゴリ）
+        let moderation_flag_thresholds = parse_env(
+            "MODERATION_FLAG_THRESHOLDS",
+            CategoryThresholds::new(DEFAULT_FLAG_THRESHOLD),
+        )?;
+        let moderation_block_thresholds = parse_env(
+            "MODERATION_BLOCK_THRESHOLDS",
+            CategoryThresholds::new(DEFAULT_BLOCK_THRESHOLD),
+        )?;
+
+        // "estimate"（デフォルト）または "bpe"（ランク表がなければ警告を出す）
+        let tokenizer = parse_env("TOKENIZER", TokenizerMode::default())?;
+        let tokenizer_dir = env::var("TOKENIZER_DIR").ok().map(PathBuf::from);
+
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) if !require_database => String::new(),
+            Err(_) => return Err("DATABASE_URL is not set".to_string()),
+        };
 
         let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
 
@@ -27,7 +197,27 @@ impl Config {
             .map_err(|_| "PORT must be a valid number")?;
 
         Ok(Self {
+            llm_provider,
             openai_api_key,
+            openai_base_url,
+            openai_extra_headers,
+            openai_model,
+            openai_allowed_models,
+            openai_retry_policy,
+            openai_max_tool_steps,
+            response_cache,
+            response_cache_ttl,
+            response_cache_capacity,
+            model_prices,
+            embedding_model,
+            embedding_index,
+            moderation,
+            moderation_model,
+            moderation_rules,
+            moderation_flag_thresholds,
+            moderation_block_thresholds,
+            tokenizer,
+            tokenizer_dir,
             host,
             port,
             database_url,
@@ -39,3 +229,32 @@ impl Config {
         format!("{}:{}", self.host, self.port)
     }
 }
+
+/// 環境変数をパース（未設定ならデフォルト値）
+fn parse_env<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env::var(name) {
+        Ok(v) => v
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} has an invalid value: {}", name, v)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// "name=value,name=value" 形式のヘッダー指定をパース
+fn parse_headers(value: &str) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (name, value) = pair
+            .split_once('=')
+            .ok_or("OPENAI_EXTRA_HEADERS must be comma-separated name=value pairs")?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name in OPENAI_EXTRA_HEADERS: {}", name))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("Invalid header value in OPENAI_EXTRA_HEADERS for {}", name))?;
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
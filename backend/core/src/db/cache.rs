@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::types::Json;
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::models::ChatResponse;
+use crate::services::cache::CacheStore;
+
+/// 応答キャッシュの保存先（`response_cache` テーブル）
+///
+/// 複数のプロセスで共有でき、再起動しても残る。期限切れの行は保存のたびに削除する。
+#[derive(Clone)]
+pub struct PostgresCache {
+    pool: PgPool,
+}
+
+impl PostgresCache {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl CacheStore for PostgresCache {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn get(&self, key: &str) -> Option<ChatResponse> {
+        let result = sqlx::query_scalar::<_, Json<ChatResponse>>(
+            r#"
+            SELECT response FROM response_cache
+            WHERE key = $1 AND expires_at > NOW()
+            "#,
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(response) => response.map(|r| r.0),
+            Err(e) => {
+                warn!("Failed to read response cache: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn put(&self, key: &str, response: &ChatResponse, ttl: Duration) {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO response_cache (key, model, response, expires_at)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+            ON CONFLICT (key)
+            DO UPDATE SET model = $2, response = $3, created_at = NOW(),
+                expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(key)
+        .bind(&response.model)
+        .bind(Json(response))
+        .bind(ttl.as_secs_f64())
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to write response cache: {}", e);
+            return;
+        }
+
+        if let Err(e) = sqlx::query("DELETE FROM response_cache WHERE expires_at <= NOW()")
+            .execute(&self.pool)
+            .await
+        {
+            warn!("Failed to purge expired response cache: {}", e);
+        }
+    }
+}
@@ -0,0 +1,2 @@
+-- メッセージに完了状態を追加（completed: 正常完了, incomplete: 生成途中で中断）
+ALTER TABLE messages ADD COLUMN status TEXT NOT NULL DEFAULT 'completed';
@@ -0,0 +1,2 @@
+-- セッションに使用モデルを追加（NULLの場合はサーバーのデフォルト）
+ALTER TABLE sessions ADD COLUMN model TEXT;
@@ -0,0 +1,2 @@
+-- 画像・ファイルを含むメッセージのパーツ（文字列のみのメッセージは NULL）
+ALTER TABLE messages ADD COLUMN parts JSONB;
@@ -0,0 +1,2 @@
+-- アシスタントメッセージの推論の要約（要約を要求しなかった場合は空配列）
+ALTER TABLE messages ADD COLUMN reasoning_summary TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- アシスタントメッセージに OpenAI 側のレスポンスIDを追加（previous_response_id で会話を続けるのに使う）
+ALTER TABLE messages ADD COLUMN response_id TEXT;
+
+-- セッションに履歴の送り方を追加（replay: 毎回全履歴を送る, chained: 新しいターンと previous_response_id のみ送る）
+ALTER TABLE sessions ADD COLUMN history_mode TEXT NOT NULL DEFAULT 'replay';
@@ -0,0 +1,8 @@
+-- セッションに履歴の選び方を追加（{"type": "full"} は全履歴を送る）
+ALTER TABLE sessions ADD COLUMN history_strategy JSONB NOT NULL DEFAULT '{"type": "full"}';
+
+-- ピン留めしたメッセージは履歴の選び方に関わらず常に送る
+ALTER TABLE messages ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- アシスタントメッセージの生成時に送った履歴のメッセージID（新しいユーザーメッセージは含めない）
+ALTER TABLE messages ADD COLUMN context_message_ids UUID[] NOT NULL DEFAULT '{}';
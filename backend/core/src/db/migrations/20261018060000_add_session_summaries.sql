@@ -0,0 +1,12 @@
+-- セッションの古いターンの要約（summarize 戦略で作成。最新のものを履歴の代わりに送る）
+CREATE TABLE session_summaries (
+    id UUID PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    -- 要約に含めた最後のメッセージと、要約に含めたメッセージ数（セッションの先頭から）
+    last_message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    message_count INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_session_summaries_session_id ON session_summaries(session_id, created_at);
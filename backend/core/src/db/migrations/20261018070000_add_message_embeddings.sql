@@ -0,0 +1,13 @@
+-- メッセージの埋め込みベクトル（EMBEDDING_MODEL を設定した場合のみ保存する）
+-- pgvector に依存しないよう REAL[] で保存し、コサイン類似度は検索時に計算する
+CREATE TABLE message_embeddings (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    -- 埋め込みに使ったモデル（モデルが異なるベクトルは比較しない）
+    model TEXT NOT NULL,
+    embedding REAL[] NOT NULL,
+    -- ベクトルのノルム（類似度の計算用）
+    norm DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_message_embeddings_model ON message_embeddings(model);
@@ -0,0 +1,3 @@
+-- メッセージのモデレーション結果（モデレーション無効時に保存したメッセージは NULL）
+-- {"flagged": true, "categories": {"violence": 0.91}} の形式
+ALTER TABLE messages ADD COLUMN moderation JSONB;
@@ -0,0 +1,12 @@
+-- 応答キャッシュ（RESPONSE_CACHE=postgres の場合のみ使用）
+-- key はモデル・instructions・入力・パラメーターの SHA-256
+CREATE TABLE response_cache (
+    key TEXT PRIMARY KEY,
+    model TEXT NOT NULL,
+    -- ChatResponse の JSON
+    response JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_response_cache_expires_at ON response_cache(expires_at);
@@ -0,0 +1,17 @@
+-- API 呼び出しごとのトークン使用量と料金（セッション・日ごとの集計に使う）
+-- セッションを削除しても集計に残すため、session_id は NULL にする
+CREATE TABLE usage_costs (
+    id UUID PRIMARY KEY,
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    cached_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    reasoning_tokens INTEGER NOT NULL,
+    -- 単価が不明なモデルは NULL
+    cost_usd DOUBLE PRECISION,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_usage_costs_session_id ON usage_costs(session_id);
+CREATE INDEX idx_usage_costs_created_at ON usage_costs(created_at);
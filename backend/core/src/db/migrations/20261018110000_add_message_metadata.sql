@@ -0,0 +1,14 @@
+-- アシスタントメッセージの生成時の情報（ユーザーメッセージ・追加前のメッセージは NULL）
+ALTER TABLE messages
+    ADD COLUMN model TEXT,
+    -- トークン使用量（中断した返答は NULL）
+    ADD COLUMN prompt_tokens INTEGER,
+    ADD COLUMN completion_tokens INTEGER,
+    ADD COLUMN cached_tokens INTEGER,
+    ADD COLUMN reasoning_tokens INTEGER,
+    -- 料金（USD、単価が不明なモデル・中断した返答は NULL）
+    ADD COLUMN cost_usd DOUBLE PRECISION,
+    -- API の呼び出しから返答の完了（中断）までの時間
+    ADD COLUMN latency_ms INTEGER,
+    -- 正常に完了しなかった理由（status が "incomplete" の場合）
+    ADD COLUMN error TEXT;
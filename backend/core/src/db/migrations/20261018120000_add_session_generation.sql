@@ -0,0 +1,3 @@
+-- セッションのサンプリング・生成のパラメーターのデフォルト（GenerationOptions の JSON）
+-- リクエストで指定した項目はこの値を上書きする
+ALTER TABLE sessions ADD COLUMN generation JSONB NOT NULL DEFAULT '{}';
@@ -1,5 +1,9 @@
 // データベース操作
 
+pub mod cache;
 pub mod repository;
+pub mod vector;
 
-pub use repository::SessionRepository;
+pub use cache::PostgresCache;
+pub use repository::{NewMessage, SessionRepository};
+pub use vector::EmbeddingIndex;
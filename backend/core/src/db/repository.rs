@@ -1,33 +1,89 @@
-use crate::models::{ChatMessage, Session};
+use crate::models::{
+    ChatMessage, DailyCost, GenerationOptions, HistoryMode, HistoryStrategy, MessageContent,
+    MessageSearchResult, ModelCost, ModerationFlags, Session, SessionSummary, Usage,
+};
+use crate::db::vector::EmbeddingIndex;
+use chrono::NaiveDate;
+use std::time::Duration;
+use sqlx::types::Json;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+/// 保存するメッセージ
+///
+/// ユーザーメッセージでは完了状態とモデレーションの結果のみを使い、他の項目は空にする。
+#[derive(Debug, Default)]
+pub struct NewMessage<'a> {
+    pub content: &'a str,
+    /// 完了状態（"completed"、"incomplete" または "failed"）
+    pub status: &'a str,
+    /// 推論の要約
+    pub reasoning_summary: &'a [String],
+    /// OpenAI 側のレスポンスID（中断した場合は None）
+    pub response_id: Option<&'a str>,
+    /// 生成時に送った履歴のメッセージID
+    pub context_message_ids: &'a [Uuid],
+    /// モデレーションの結果（モデレーション無効時は None）
+    pub moderation: Option<&'a ModerationFlags>,
+    /// 生成したモデル
+    pub model: Option<&'a str>,
+    /// トークン使用量（中断した場合は None）
+    pub usage: Option<&'a Usage>,
+    /// 料金（USD）
+    pub cost_usd: Option<f64>,
+    /// API の呼び出しから返答の完了（中断）までの時間
+    pub latency: Option<Duration>,
+    /// 正常に完了しなかった理由
+    pub error: Option<&'a str>,
+}
+
 /// セッション・メッセージのDB操作
 #[derive(Clone)]
 pub struct SessionRepository {
     pool: PgPool,
+    /// 埋め込みベクトルの索引（意味検索の方法）
+    embedding_index: EmbeddingIndex,
 }
 
 impl SessionRepository {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            embedding_index: EmbeddingIndex::default(),
+        }
+    }
+
+    /// 埋め込みベクトルの索引を設定（pgvector の場合は先に `vector::setup` を実行しておく）
+    pub fn with_embedding_index(mut self, embedding_index: EmbeddingIndex) -> Self {
+        self.embedding_index = embedding_index;
+        self
     }
 
-    /// 新規セッションを作成
+    /// 新規セッションを作成（`generation` はセッションのサンプリング・生成のパラメーターのデフォルト）
     pub async fn create_session(
         &self,
         system_prompt: Option<String>,
+        model: Option<String>,
+        history_mode: HistoryMode,
+        history_strategy: &HistoryStrategy,
+        generation: &GenerationOptions,
     ) -> Result<Session, sqlx::Error> {
         let id = Uuid::new_v4();
         let session = sqlx::query_as::<_, Session>(
             r#"
-            INSERT INTO sessions (id, system_prompt)
-            VALUES ($1, $2)
-            RETURNING id, system_prompt, created_at, updated_at
+            INSERT INTO sessions
+                (id, system_prompt, model, history_mode, history_strategy, generation)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, system_prompt, model, history_mode, history_strategy, generation,
+                created_at, updated_at
             "#,
         )
         .bind(id)
         .bind(system_prompt)
+        .bind(model)
+        .bind(history_mode)
+        .bind(Json(history_strategy))
+        .bind(Json(generation))
         .fetch_one(&self.pool)
         .await?;
 
@@ -38,7 +94,8 @@ impl SessionRepository {
     pub async fn get_session(&self, id: Uuid) -> Result<Option<Session>, sqlx::Error> {
         let session = sqlx::query_as::<_, Session>(
             r#"
-            SELECT id, system_prompt, created_at, updated_at
+            SELECT id, system_prompt, model, history_mode, history_strategy, generation,
+                created_at, updated_at
             FROM sessions
             WHERE id = $1
             "#,
@@ -50,25 +107,118 @@ impl SessionRepository {
         Ok(session)
     }
 
+    /// セッションの履歴の選び方と、サンプリング・生成のパラメーターのデフォルトを更新
+    ///
+    /// None の項目は変更しない。1つの UPDATE で更新するので、一部だけ更新されることはない。
+    /// セッションが存在しない場合は None。
+    pub async fn update_session(
+        &self,
+        id: Uuid,
+        history_strategy: Option<&HistoryStrategy>,
+        generation: Option<&GenerationOptions>,
+    ) -> Result<Option<Session>, sqlx::Error> {
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions SET
+                history_strategy = COALESCE($2, history_strategy),
+                generation = COALESCE($3, generation),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, system_prompt, model, history_mode, history_strategy, generation,
+                created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(history_strategy.map(Json))
+        .bind(generation.map(Json))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
     /// セッションにメッセージを追加
     pub async fn add_message(
         &self,
         session_id: Uuid,
         role: &str,
         content: &str,
+    ) -> Result<ChatMessage, sqlx::Error> {
+        let message = NewMessage {
+            status: "completed",
+            ..NewMessage::default()
+        };
+        self.insert_message(session_id, role, &MessageContent::from(content), &message)
+            .await
+    }
+
+    /// セッションにユーザーメッセージをモデレーションの結果付きで追加
+    pub async fn add_user_message(
+        &self,
+        session_id: Uuid,
+        content: &MessageContent,
+        moderation: Option<&ModerationFlags>,
+    ) -> Result<ChatMessage, sqlx::Error> {
+        let message = NewMessage {
+            status: "completed",
+            moderation,
+            ..NewMessage::default()
+        };
+        self.insert_message(session_id, "user", content, &message).await
+    }
+
+    /// セッションにアシスタントの返答を推論の要約・レスポンスID・送った履歴付きで追加
+    pub async fn add_assistant_message(
+        &self,
+        session_id: Uuid,
+        message: &NewMessage<'_>,
+    ) -> Result<ChatMessage, sqlx::Error> {
+        let content = MessageContent::from(message.content);
+        self.insert_message(session_id, "assistant", &content, message)
+            .await
+    }
+
+    /// メッセージを保存し、セッションの updated_at を更新
+    ///
+    /// 本文はパーツを含む `content` を保存し、`message` からは本文以外の項目を使う。
+    async fn insert_message(
+        &self,
+        session_id: Uuid,
+        role: &str,
+        content: &MessageContent,
+        message: &NewMessage<'_>,
     ) -> Result<ChatMessage, sqlx::Error> {
         let id = Uuid::new_v4();
         let message = sqlx::query_as::<_, ChatMessage>(
             r#"
-            INSERT INTO messages (id, session_id, role, content)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, session_id, role, content, created_at
+            INSERT INTO messages
+                (id, session_id, role, content, parts, status, reasoning_summary, response_id,
+                context_message_ids, moderation, model, prompt_tokens, completion_tokens,
+                cached_tokens, reasoning_tokens, cost_usd, latency_ms, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+            RETURNING id, session_id, role, content, parts, reasoning_summary, status, response_id,
+                pinned, context_message_ids, moderation, model, prompt_tokens, completion_tokens,
+                cached_tokens, reasoning_tokens, cost_usd, latency_ms, error, created_at
             "#,
         )
         .bind(id)
         .bind(session_id)
         .bind(role)
-        .bind(content)
+        .bind(content.text())
+        .bind(content.parts().map(Json))
+        .bind(message.status)
+        .bind(message.reasoning_summary)
+        .bind(message.response_id)
+        .bind(message.context_message_ids)
+        .bind(message.moderation.map(Json))
+        .bind(message.model)
+        .bind(message.usage.map(|u| u.prompt_tokens as i32))
+        .bind(message.usage.map(|u| u.completion_tokens as i32))
+        .bind(message.usage.map(|u| u.cached_tokens as i32))
+        .bind(message.usage.map(|u| u.reasoning_tokens as i32))
+        .bind(message.cost_usd)
+        .bind(message.latency.map(|l| l.as_millis() as i32))
+        .bind(message.error)
         .fetch_one(&self.pool)
         .await?;
 
@@ -85,7 +235,9 @@ impl SessionRepository {
     pub async fn get_messages(&self, session_id: Uuid) -> Result<Vec<ChatMessage>, sqlx::Error> {
         let messages = sqlx::query_as::<_, ChatMessage>(
             r#"
-            SELECT id, session_id, role, content, created_at
+            SELECT id, session_id, role, content, parts, reasoning_summary, status, response_id,
+                pinned, context_message_ids, moderation, model, prompt_tokens, completion_tokens,
+                cached_tokens, reasoning_tokens, cost_usd, latency_ms, error, created_at
             FROM messages
             WHERE session_id = $1
             ORDER BY created_at ASC
@@ -98,6 +250,260 @@ impl SessionRepository {
         Ok(messages)
     }
 
+    /// メッセージのピン留めを設定（セッションにメッセージが存在しない場合は None）
+    pub async fn set_message_pinned(
+        &self,
+        session_id: Uuid,
+        message_id: Uuid,
+        pinned: bool,
+    ) -> Result<Option<ChatMessage>, sqlx::Error> {
+        let message = sqlx::query_as::<_, ChatMessage>(
+            r#"
+            UPDATE messages SET pinned = $3
+            WHERE session_id = $1 AND id = $2
+            RETURNING id, session_id, role, content, parts, reasoning_summary, status, response_id,
+                pinned, context_message_ids, moderation, model, prompt_tokens, completion_tokens,
+                cached_tokens, reasoning_tokens, cost_usd, latency_ms, error, created_at
+            "#,
+        )
+        .bind(session_id)
+        .bind(message_id)
+        .bind(pinned)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// セッションの最新の要約を取得
+    pub async fn get_latest_summary(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<SessionSummary>, sqlx::Error> {
+        let summary = sqlx::query_as::<_, SessionSummary>(
+            r#"
+            SELECT id, session_id, content, last_message_id, message_count, created_at
+            FROM session_summaries
+            WHERE session_id = $1
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// セッションに要約を追加（以降はこの要約が最新になる）
+    pub async fn add_summary(
+        &self,
+        session_id: Uuid,
+        content: &str,
+        last_message_id: Uuid,
+        message_count: i32,
+    ) -> Result<SessionSummary, sqlx::Error> {
+        let id = Uuid::new_v4();
+        let summary = sqlx::query_as::<_, SessionSummary>(
+            r#"
+            INSERT INTO session_summaries (id, session_id, content, last_message_id, message_count)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, session_id, content, last_message_id, message_count, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(content)
+        .bind(last_message_id)
+        .bind(message_count)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// メッセージの埋め込みベクトルを保存（既にあれば置き換える）
+    ///
+    /// ノルムが 0 のベクトル（空のテキストなど）は類似度を計算できないため保存しない。
+    /// pgvector の場合は `vector` 列にも保存する（次元数が設定と異なればエラー）。
+    pub async fn add_embedding(
+        &self,
+        message_id: Uuid,
+        model: &str,
+        embedding: &[f32],
+    ) -> Result<(), sqlx::Error> {
+        let norm = norm(embedding);
+        if norm == 0.0 {
+            return Ok(());
+        }
+
+        let sql = match self.embedding_index {
+            EmbeddingIndex::Scan => {
+                r#"
+                INSERT INTO message_embeddings (message_id, model, embedding, norm)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (message_id)
+                DO UPDATE SET model = $2, embedding = $3, norm = $4, created_at = NOW()
+                "#
+            }
+            EmbeddingIndex::PgVector { .. } => {
+                r#"
+                INSERT INTO message_embeddings (message_id, model, embedding, norm, embedding_vector)
+                VALUES ($1, $2, $3, $4, $3::REAL[]::vector)
+                ON CONFLICT (message_id)
+                DO UPDATE SET model = $2, embedding = $3, norm = $4,
+                    embedding_vector = $3::REAL[]::vector, created_at = NOW()
+                "#
+            }
+        };
+
+        sqlx::query(sql)
+            .bind(message_id)
+            .bind(model)
+            .bind(embedding)
+            .bind(norm)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// 全セッションから、クエリのベクトルとのコサイン類似度が高いメッセージを取得
+    ///
+    /// 同じモデルで埋め込んだメッセージのみを比較する。
+    /// `scan` では全件の類似度を計算し、pgvector では HNSW インデックスで近似最近傍を探す。
+    pub async fn search_messages(
+        &self,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<MessageSearchResult>, sqlx::Error> {
+        if norm(query) == 0.0 {
+            return Ok(Vec::new());
+        }
+
+        // 類似度の高い順の (message_id, score)（$1: モデル, $2: クエリのベクトル, $3: 件数）
+        let scored = match self.embedding_index {
+            EmbeddingIndex::Scan => {
+                r#"
+                SELECT message_id,
+                    (SELECT SUM(a::DOUBLE PRECISION * b) FROM unnest(embedding, $2) AS t(a, b))
+                        / (norm * sqrt((SELECT SUM(q::DOUBLE PRECISION * q) FROM unnest($2) AS q)))
+                        AS score
+                FROM message_embeddings
+                WHERE model = $1
+                ORDER BY score DESC
+                LIMIT $3
+                "#
+            }
+            // HNSW インデックスを使うため、距離の昇順で絞り込む
+            EmbeddingIndex::PgVector { .. } => {
+                r#"
+                SELECT message_id, 1 - (embedding_vector <=> $2::REAL[]::vector) AS score
+                FROM message_embeddings
+                WHERE model = $1 AND embedding_vector IS NOT NULL
+                ORDER BY embedding_vector <=> $2::REAL[]::vector
+                LIMIT $3
+                "#
+            }
+        };
+
+        let results = sqlx::query_as::<_, MessageSearchResult>(&format!(
+            r#"
+            SELECT m.id, m.session_id, m.role, m.content, m.parts, m.reasoning_summary, m.status,
+                m.response_id, m.pinned, m.context_message_ids, m.moderation, m.model,
+                m.prompt_tokens, m.completion_tokens, m.cached_tokens, m.reasoning_tokens,
+                m.cost_usd, m.latency_ms, m.error, m.created_at, e.score
+            FROM ({}) e
+            JOIN messages m ON m.id = e.message_id
+            ORDER BY e.score DESC
+            "#,
+            scored
+        ))
+        .bind(model)
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(results)
+    }
+
+    /// API 呼び出しのトークン使用量と料金を記録（セッション外の呼び出しは `session_id` が None）
+    pub async fn record_usage(
+        &self,
+        session_id: Option<Uuid>,
+        model: &str,
+        usage: &Usage,
+        cost_usd: Option<f64>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO usage_costs
+                (id, session_id, model, prompt_tokens, cached_tokens, completion_tokens,
+                reasoning_tokens, cost_usd)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(session_id)
+        .bind(model)
+        .bind(usage.prompt_tokens as i32)
+        .bind(usage.cached_tokens as i32)
+        .bind(usage.completion_tokens as i32)
+        .bind(usage.reasoning_tokens as i32)
+        .bind(cost_usd)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// セッションのモデルごとの使用量と料金（モデル名順）
+    pub async fn get_session_costs(&self, session_id: Uuid) -> Result<Vec<ModelCost>, sqlx::Error> {
+        let costs = sqlx::query_as::<_, ModelCost>(&format!(
+            r#"
+            SELECT model, {}
+            FROM usage_costs
+            WHERE session_id = $1
+            GROUP BY model
+            ORDER BY model
+            "#,
+            COST_COLUMNS
+        ))
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(costs)
+    }
+
+    /// 日ごとの使用量と料金（日付は UTC、`from`〜`to` の両端を含む、日付順）
+    pub async fn get_daily_costs(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyCost>, sqlx::Error> {
+        let costs = sqlx::query_as::<_, DailyCost>(&format!(
+            r#"
+            SELECT (created_at AT TIME ZONE 'UTC')::DATE AS date, {}
+            FROM usage_costs
+            WHERE created_at >= $1::DATE::TIMESTAMP AT TIME ZONE 'UTC'
+                AND created_at < ($2::DATE + 1)::TIMESTAMP AT TIME ZONE 'UTC'
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+            COST_COLUMNS
+        ))
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(costs)
+    }
+
     /// セッションを削除（カスケードでメッセージも削除）
     pub async fn delete_session(&self, id: Uuid) -> Result<bool, sqlx::Error> {
         let result = sqlx::query("DELETE FROM sessions WHERE id = $1")
@@ -108,3 +514,23 @@ impl SessionRepository {
         Ok(result.rows_affected() > 0)
     }
 }
+
+/// 使用量と料金の集計列（`CostSummary`）
+const COST_COLUMNS: &str = r#"
+    COUNT(*) AS calls,
+    COALESCE(SUM(prompt_tokens), 0)::BIGINT AS prompt_tokens,
+    COALESCE(SUM(cached_tokens), 0)::BIGINT AS cached_tokens,
+    COALESCE(SUM(completion_tokens), 0)::BIGINT AS completion_tokens,
+    COALESCE(SUM(reasoning_tokens), 0)::BIGINT AS reasoning_tokens,
+    COALESCE(SUM(cost_usd), 0)::DOUBLE PRECISION AS cost_usd,
+    COUNT(*) FILTER (WHERE cost_usd IS NULL) AS unpriced_calls
+"#;
+
+/// ベクトルのノルム
+fn norm(vector: &[f32]) -> f64 {
+    vector
+        .iter()
+        .map(|v| f64::from(*v) * f64::from(*v))
+        .sum::<f64>()
+        .sqrt()
+}
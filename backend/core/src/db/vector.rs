@@ -0,0 +1,110 @@
+//! メッセージの埋め込みベクトルの索引
+//!
+//! デフォルトでは `message_embeddings.embedding`（REAL[]）を全件走査してコサイン類似度を計算する。
+//! pgvector を使う場合は、起動時に `vector(d)` 列と HNSW インデックスを追加し、近似最近傍で検索する。
+
+use std::fmt;
+use std::str::FromStr;
+
+use sqlx::PgPool;
+use tracing::info;
+
+/// pgvector の次元数のデフォルト（text-embedding-3-small）
+pub const DEFAULT_EMBEDDING_DIMENSIONS: usize = 1536;
+
+/// HNSW インデックスを作れる次元数の上限（pgvector の `vector` 型）
+pub const MAX_INDEXED_DIMENSIONS: usize = 2000;
+
+/// 埋め込みベクトルの索引（`EMBEDDING_INDEX`）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmbeddingIndex {
+    /// REAL[] を全件走査する（拡張不要）
+    #[default]
+    Scan,
+    /// pgvector の `vector(dimensions)` 列と HNSW インデックス（コサイン距離）
+    PgVector { dimensions: usize },
+}
+
+impl FromStr for EmbeddingIndex {
+    type Err = String;
+
+    /// 次元数は `DEFAULT_EMBEDDING_DIMENSIONS`（`EMBEDDING_DIMENSIONS` で上書きする）
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scan" => Ok(EmbeddingIndex::Scan),
+            "pgvector" => Ok(EmbeddingIndex::PgVector {
+                dimensions: DEFAULT_EMBEDDING_DIMENSIONS,
+            }),
+            _ => Err(format!(
+                "Unknown embedding index '{}' (expected: scan, pgvector)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EmbeddingIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbeddingIndex::Scan => write!(f, "scan"),
+            EmbeddingIndex::PgVector { dimensions } => write!(f, "pgvector ({})", dimensions),
+        }
+    }
+}
+
+/// 索引に必要な拡張・列・インデックスを作成する（pgvector の場合のみ。何度実行してもよい）
+///
+/// 既存の埋め込みのうち次元数が一致するものは `vector` 列にコピーする。
+/// REAL[] も引き続き保存するため、`scan` に戻しても検索できる。
+pub async fn setup(pool: &PgPool, index: EmbeddingIndex) -> Result<(), sqlx::Error> {
+    let EmbeddingIndex::PgVector { dimensions } = index else {
+        return Ok(());
+    };
+
+    // 次元数は型の一部のため、SQL に埋め込む（設定値の数値のみ）
+    let statements = [
+        "CREATE EXTENSION IF NOT EXISTS vector".to_string(),
+        format!(
+            "ALTER TABLE message_embeddings ADD COLUMN IF NOT EXISTS embedding_vector vector({})",
+            dimensions
+        ),
+        format!(
+            "UPDATE message_embeddings SET embedding_vector = embedding::vector({0})
+            WHERE embedding_vector IS NULL AND cardinality(embedding) = {0}",
+            dimensions
+        ),
+        "CREATE INDEX IF NOT EXISTS idx_message_embeddings_hnsw ON message_embeddings
+            USING hnsw (embedding_vector vector_cosine_ops)"
+            .to_string(),
+    ];
+
+    // 既存の列の次元数が異なる場合は、列を作り直す必要があるため起動を止める
+    let existing: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT format_type(atttypid, atttypmod) FROM pg_attribute
+        WHERE attrelid = 'message_embeddings'::regclass
+            AND attname = 'embedding_vector' AND NOT attisdropped
+        "#,
+    )
+    .fetch_optional(pool)
+    .await?;
+    let expected = format!("vector({})", dimensions);
+    if let Some(existing) = existing.filter(|t| *t != expected) {
+        return Err(sqlx::Error::Configuration(
+            format!(
+                "message_embeddings.embedding_vector is {}, but EMBEDDING_DIMENSIONS is {}",
+                existing, dimensions
+            )
+            .into(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+    for statement in &statements {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    info!("pgvector index ready ({} dimensions)", dimensions);
+    Ok(())
+}
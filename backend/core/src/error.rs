@@ -5,6 +5,7 @@
 
 use thiserror::Error;
 
+use crate::moderation::ModerationStage;
 use crate::services::OpenAIError;
 
 /// アプリケーション全体で使用するエラー型
@@ -22,6 +23,14 @@ pub enum AppError {
     #[error("Database error")]
     Database(#[from] sqlx::Error),
 
+    /// モデレーションでブロックされた入力・出力
+    #[error("Content blocked by moderation ({stage}): {}", categories.join(", "))]
+    ContentBlocked {
+        stage: ModerationStage,
+        /// ブロックのしきい値を超えたカテゴリ
+        categories: Vec<String>,
+    },
+
     /// 外部APIエラー（OpenAI）
     #[error("External API error")]
     ExternalApi(#[from] OpenAIError),
@@ -29,12 +38,27 @@ pub enum AppError {
 
 impl AppError {
     /// エラーコードを取得
+    ///
+    /// クライアントが分岐に使うため、一度公開したコードは変更しない。
     pub fn code(&self) -> &'static str {
         match self {
             AppError::NotFound(_) => "NOT_FOUND",
             AppError::Validation(_) => "VALIDATION_ERROR",
             AppError::Database(_) => "DATABASE_ERROR",
-            AppError::ExternalApi(_) => "EXTERNAL_API_ERROR",
+            AppError::ContentBlocked { .. } => "CONTENT_BLOCKED",
+            AppError::ExternalApi(e) => match e {
+                OpenAIError::RateLimited { .. } => "RATE_LIMITED",
+                OpenAIError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+                OpenAIError::AuthFailed(_) => "UPSTREAM_AUTH_FAILED",
+                OpenAIError::InvalidRequest { .. } => "INVALID_REQUEST",
+                OpenAIError::ContextLengthExceeded(_) => "CONTEXT_LENGTH_EXCEEDED",
+                OpenAIError::ServerError(_) => "UPSTREAM_SERVER_ERROR",
+                OpenAIError::Timeout => "UPSTREAM_TIMEOUT",
+                OpenAIError::ToolStepLimitExceeded(_) => "TOOL_STEP_LIMIT_EXCEEDED",
+                OpenAIError::Refusal(_) => "MODEL_REFUSAL",
+                OpenAIError::SchemaMismatch(_) => "SCHEMA_MISMATCH",
+                _ => "EXTERNAL_API_ERROR",
+            },
         }
     }
 
@@ -44,7 +68,40 @@ impl AppError {
             AppError::NotFound(resource) => format!("{} not found", resource),
             AppError::Validation(msg) => msg.clone(),
             AppError::Database(_) => "Database operation failed".to_string(),
-            AppError::ExternalApi(_) => "External service unavailable".to_string(),
+            AppError::ContentBlocked { stage, categories } => {
+                let content = match stage {
+                    ModerationStage::Input => "Message",
+                    ModerationStage::Output => "Response",
+                };
+                format!(
+                    "{} was blocked by content moderation (categories: {})",
+                    content,
+                    categories.join(", ")
+                )
+            }
+            AppError::ExternalApi(e) => match e {
+                OpenAIError::RateLimited { .. } => {
+                    "Rate limit exceeded, please retry later".to_string()
+                }
+                OpenAIError::QuotaExceeded(_) => "External service quota exceeded".to_string(),
+                // リクエスト内容の誤りはクライアントが修正できるよう詳細を返す
+                OpenAIError::InvalidRequest { message, param } => match param {
+                    Some(param) => format!("{} (param: {})", message, param),
+                    None => message.clone(),
+                },
+                OpenAIError::ContextLengthExceeded(_) => {
+                    "Conversation is too long for the model's context window".to_string()
+                }
+                OpenAIError::Timeout => "External service timed out".to_string(),
+                OpenAIError::ToolStepLimitExceeded(steps) => {
+                    format!("Model did not finish within {} tool call steps", steps)
+                }
+                OpenAIError::Refusal(reason) => format!("Model refused to respond: {}", reason),
+                OpenAIError::SchemaMismatch(_) => {
+                    "Model output did not match the requested format".to_string()
+                }
+                _ => "External service unavailable".to_string(),
+            },
         }
     }
 }
@@ -0,0 +1,235 @@
+//! 送る履歴の選択
+//!
+//! セッションの `HistoryStrategy` に従い、保存された履歴から API に送るメッセージを選ぶ。
+//! ターン（ユーザーメッセージから次のユーザーメッセージの前まで）単位で選び、
+//! ピン留めしたメッセージはどの選び方でも残す。
+//! `summarize` では古いターンを LLM で要約し、要約を instructions に加えて送る。
+
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::{ChatMessage, ChatOptions, ChatResponse, HistoryStrategy, Message, MessageContent};
+use crate::services::{LlmProvider, OpenAIError};
+use crate::tokenizer::Tokenizer;
+
+/// 要約を生成するときの instructions
+pub const SUMMARY_INSTRUCTIONS: &str = "You maintain a running summary of a conversation \
+between a user and an assistant. Merge the previous summary (if any) with the new messages. \
+Keep facts, decisions, names, numbers, user preferences and open questions the assistant will \
+need later. Write concise prose in the language of the conversation and output only the summary.";
+
+/// 履歴を選ぶときの入力（トークン数の計算に使う）
+pub struct HistoryInput<'a> {
+    /// 新しいユーザーメッセージ
+    pub new_message: &'a MessageContent,
+    /// 送る instructions（要約を含む）
+    pub instructions: Option<&'a str>,
+    pub model: &'a str,
+    pub tokenizer: &'a Tokenizer,
+}
+
+impl HistoryInput<'_> {
+    /// 履歴を送らない場合の入力トークン数
+    fn base_tokens(&self) -> usize {
+        let new_message = Message {
+            role: "user".to_string(),
+            content: self.new_message.clone(),
+        };
+        self.tokenizer
+            .count_tokens(self.model, &[new_message], self.instructions)
+    }
+
+    fn message_tokens(&self, message: &ChatMessage) -> usize {
+        self.tokenizer
+            .count_message(self.model, &message.to_message())
+    }
+}
+
+/// 選び方の設定を検証
+pub fn validate_strategy(strategy: &HistoryStrategy) -> Result<(), AppError> {
+    let error = match strategy {
+        HistoryStrategy::LastTurns { turns: 0 } => "last_turns.turns must be at least 1",
+        HistoryStrategy::TokenBudget { max_tokens: 0 } => {
+            "token_budget.max_tokens must be at least 1"
+        }
+        HistoryStrategy::DropMiddle {
+            head_turns: 0,
+            tail_turns: 0,
+        } => "drop_middle must keep at least 1 turn",
+        HistoryStrategy::Summarize { max_tokens: 0, .. } => {
+            "summarize.max_tokens must be at least 1"
+        }
+        _ => return Ok(()),
+    };
+
+    Err(AppError::Validation(error.to_string()))
+}
+
+/// 履歴から送るメッセージを選ぶ（時系列順）
+///
+/// 先頭の `summarized` 件は要約済みとして、ピン留めしたもの以外は送らない。
+/// `token_budget` では、instructions・新しいユーザーメッセージ・ピン留めしたメッセージを先に数え、
+/// 残りに収まる直近のターンを選ぶ（収まらないターンより古いターンは送らない）。
+pub fn select_history<'a>(
+    strategy: &HistoryStrategy,
+    history: &'a [ChatMessage],
+    summarized: usize,
+    input: &HistoryInput<'_>,
+) -> Vec<&'a ChatMessage> {
+    let (covered, history) = history.split_at(summarized.min(history.len()));
+    let turn_of = turn_indices(history);
+    let turns = turn_of.last().map_or(0, |last| last + 1);
+
+    let keep: Vec<bool> = match strategy {
+        HistoryStrategy::Full | HistoryStrategy::Summarize { .. } => vec![true; turns],
+        HistoryStrategy::LastTurns { turns: n } => (0..turns).map(|t| t + n >= turns).collect(),
+        HistoryStrategy::DropMiddle {
+            head_turns,
+            tail_turns,
+        } => (0..turns)
+            .map(|t| t < *head_turns || t + tail_turns >= turns)
+            .collect(),
+        HistoryStrategy::TokenBudget { max_tokens } => {
+            let mut used = input.base_tokens()
+                + covered
+                    .iter()
+                    .chain(history)
+                    .filter(|m| m.pinned)
+                    .map(|m| input.message_tokens(m))
+                    .sum::<usize>();
+            let mut keep = vec![false; turns];
+
+            for turn in (0..turns).rev() {
+                let tokens: usize = history
+                    .iter()
+                    .zip(&turn_of)
+                    .filter(|(msg, t)| **t == turn && !msg.pinned)
+                    .map(|(msg, _)| input.message_tokens(msg))
+                    .sum();
+                if used + tokens > *max_tokens {
+                    break;
+                }
+                used += tokens;
+                keep[turn] = true;
+            }
+
+            keep
+        }
+    };
+
+    let pinned = covered.iter().filter(|msg| msg.pinned);
+    let selected = history
+        .iter()
+        .zip(&turn_of)
+        .filter(|(msg, turn)| msg.pinned || keep[**turn])
+        .map(|(msg, _)| msg);
+
+    pinned.chain(selected).collect()
+}
+
+/// 新たに要約に含めるメッセージ数（要約済みの `summarized` 件の後から数える）
+///
+/// `summarize` で入力が `max_tokens` を超える場合、直近の `keep_turns` ターンより前のメッセージ数を返す。
+/// 要約が不要な場合は 0。
+pub fn messages_to_summarize(
+    strategy: &HistoryStrategy,
+    history: &[ChatMessage],
+    summarized: usize,
+    input: &HistoryInput<'_>,
+) -> usize {
+    let HistoryStrategy::Summarize {
+        max_tokens,
+        keep_turns,
+    } = strategy
+    else {
+        return 0;
+    };
+
+    let history = &history[summarized.min(history.len())..];
+    let tokens = input.base_tokens()
+        + history
+            .iter()
+            .map(|msg| input.message_tokens(msg))
+            .sum::<usize>();
+    if tokens <= *max_tokens {
+        return 0;
+    }
+
+    let turn_of = turn_indices(history);
+    let turns = turn_of.last().map_or(0, |last| last + 1);
+    if turns <= *keep_turns {
+        return 0;
+    }
+
+    turn_of
+        .iter()
+        .position(|turn| turn + keep_turns >= turns)
+        .unwrap_or(history.len())
+}
+
+/// 要約済みのメッセージ数（要約に含めた最後のメッセージまでの件数）
+pub fn summarized_count(history: &[ChatMessage], last_message_id: Uuid) -> usize {
+    history
+        .iter()
+        .position(|msg| msg.id == last_message_id)
+        .map_or(0, |i| i + 1)
+}
+
+/// instructions に要約を加える
+pub fn with_summary(instructions: Option<&str>, summary: Option<&str>) -> Option<String> {
+    match (instructions, summary) {
+        (instructions, None) => instructions.map(str::to_string),
+        (None, Some(summary)) => Some(format!("Summary of the earlier conversation:\n{}", summary)),
+        (Some(instructions), Some(summary)) => Some(format!(
+            "{}\n\nSummary of the earlier conversation:\n{}",
+            instructions, summary
+        )),
+    }
+}
+
+/// 前の要約と新しいメッセージから、積み重ねた要約を生成する
+///
+/// 要約は `response` に入る（トークン使用量を記録できるよう、レスポンス全体を返す）。
+pub async fn summarize(
+    llm: &dyn LlmProvider,
+    model: &str,
+    previous: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<ChatResponse, OpenAIError> {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str(&format!("Previous summary:\n{}\n\n", previous));
+    }
+    transcript.push_str("New messages:\n");
+    for msg in messages {
+        transcript.push_str(&format!("[{}] {}\n", msg.role, msg.content));
+    }
+
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(transcript),
+    }];
+    let options = ChatOptions {
+        instructions: Some(SUMMARY_INSTRUCTIONS.to_string()),
+        model: Some(model.to_string()),
+        ..ChatOptions::default()
+    };
+
+    llm.chat_with_history(messages, options).await
+}
+
+/// 各メッセージが属するターンの番号（ユーザーメッセージごとに次のターンになる）
+fn turn_indices(history: &[ChatMessage]) -> Vec<usize> {
+    let mut turn = 0;
+
+    history
+        .iter()
+        .enumerate()
+        .map(|(i, msg)| {
+            if i > 0 && msg.role == "user" {
+                turn += 1;
+            }
+            turn
+        })
+        .collect()
+}
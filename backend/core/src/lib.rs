@@ -2,18 +2,36 @@
 //!
 //! API/CLIで共有するロジックを提供。
 //! - 設定管理
-//! - OpenAI APIサービス
+//! - LLM プロバイダー（OpenAI Responses API / Chat Completions 互換 API）
 //! - データベース操作
+//! - ツール呼び出し・構造化出力
+//! - トークン数の計算・コンテキストウィンドウの確認
+//! - 送る履歴の選択（直近のターン・トークン数の上限・ピン留めなど）
+//! - 入力・出力のモデレーション
+//! - トークン使用量からの料金の計算
 //! - 共通モデル・エラー型
+//! - テスト用のモック LLM プロバイダー（`testing` フィーチャー）
 
 pub mod config;
 pub mod db;
 pub mod error;
+pub mod history;
 pub mod models;
+pub mod moderation;
+pub mod pricing;
+pub mod schema;
 pub mod services;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tokenizer;
+pub mod tools;
 
 // 主要な型を再エクスポート
 pub use config::Config;
 pub use db::SessionRepository;
 pub use error::AppError;
-pub use services::OpenAIService;
+pub use moderation::Moderator;
+pub use schema::JsonSchema;
+pub use services::{ChatCompletionsService, LlmProvider, OpenAIService};
+pub use tokenizer::Tokenizer;
+pub use tools::{Tool, ToolError, ToolRegistry};
@@ -1,5 +1,11 @@
+use std::collections::BTreeMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
+use crate::error::AppError;
+
 // ========================================
 // API リクエスト/レスポンス（外部向け）
 // ========================================
@@ -7,24 +13,361 @@ use serde::{Deserialize, Serialize};
 /// クライアントからのリクエスト
 #[derive(Deserialize)]
 pub struct ChatRequest {
-    pub message: String,
+    /// 文字列、またはテキスト・画像・ファイルのパーツの配列
+    pub message: MessageContent,
     #[serde(default)] // フィールドがなければデフォルト値（None）を使用
     pub system_prompt: Option<String>,
+    /// 使用するモデル（未指定ならサーバーのデフォルト）
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 出力形式（JSON Schema を指定すると `parsed` に構造化された結果を返す）
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// 推論の設定（推論モデルのみ）
+    #[serde(default)]
+    pub reasoning: Option<ReasoningOptions>,
+    /// 応答キャッシュを読まずに API を呼ぶ（キャッシュ有効時のみ意味がある）
+    #[serde(default)]
+    pub bypass_cache: bool,
+    /// サンプリング・生成のパラメーター（`temperature` などをトップレベルに書く）
+    #[serde(flatten)]
+    pub generation: GenerationOptions,
+}
+
+/// 生成オプション（履歴付きチャットで使用）
+#[derive(Clone, Debug, Default)]
+pub struct ChatOptions {
+    /// システムプロンプト（instructions）
+    pub instructions: Option<String>,
+    /// 使用するモデル（None ならデフォルト）
+    pub model: Option<String>,
+    /// 出力形式
+    pub response_format: Option<ResponseFormat>,
+    /// 推論の設定
+    pub reasoning: Option<ReasoningOptions>,
+    /// 前のレスポンスのID（指定するとサーバー側の会話の続きとして生成する）
+    pub previous_response_id: Option<String>,
+    /// 応答キャッシュを読まずに API を呼ぶ（結果はキャッシュに保存する）
+    pub bypass_cache: bool,
+    /// プロンプトキャッシュのキー（同じキーの呼び出しを同じキャッシュに振り分ける）
+    pub prompt_cache_key: Option<String>,
+    /// サンプリング・生成のパラメーター
+    pub generation: GenerationOptions,
+}
+
+/// サンプリング・生成のパラメーター（未指定の項目はモデルのデフォルト）
+///
+/// セッションのデフォルトとしても保存し、リクエストで指定した項目で上書きする。
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GenerationOptions {
+    /// サンプリングの温度（0〜2。低いほど決定的）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// nucleus sampling の累積確率（0〜1）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 出力トークン数の上限（推論トークンを含む。16以上）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    /// コンテキストウィンドウを超えたときに古い入力を切り詰めるか
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<Truncation>,
+    /// ツールを並列に呼び出せるか
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    /// OpenAI 側にレスポンスを保存するか
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    /// レスポンスに付ける任意のキーと値（最大16組）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<BTreeMap<String, String>>,
+    /// 出力の詳しさ
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verbosity: Option<Verbosity>,
+}
+
+/// metadata の最大の組数
+const MAX_METADATA_PAIRS: usize = 16;
+/// metadata のキーの最大文字数
+const MAX_METADATA_KEY_CHARS: usize = 64;
+/// metadata の値の最大文字数
+const MAX_METADATA_VALUE_CHARS: usize = 512;
+/// max_output_tokens の最小値
+const MIN_MAX_OUTPUT_TOKENS: u32 = 16;
+
+impl GenerationOptions {
+    /// 値の範囲を検証
+    pub fn validate(&self) -> Result<(), AppError> {
+        let invalid = |message: String| Err(AppError::Validation(message));
+
+        if let Some(t) = self.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+            return invalid(format!("temperature must be between 0 and 2: {}", t));
+        }
+        if let Some(p) = self.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+            return invalid(format!("top_p must be between 0 and 1: {}", p));
+        }
+        if let Some(n) = self
+            .max_output_tokens
+            .filter(|n| *n < MIN_MAX_OUTPUT_TOKENS)
+        {
+            return invalid(format!(
+                "max_output_tokens must be at least {}: {}",
+                MIN_MAX_OUTPUT_TOKENS, n
+            ));
+        }
+        if let Some(metadata) = &self.metadata {
+            if metadata.len() > MAX_METADATA_PAIRS {
+                return invalid(format!(
+                    "metadata must have at most {} pairs",
+                    MAX_METADATA_PAIRS
+                ));
+            }
+            for (key, value) in metadata {
+                if key.is_empty() || key.chars().count() > MAX_METADATA_KEY_CHARS {
+                    return invalid(format!(
+                        "metadata keys must be 1 to {} characters: {}",
+                        MAX_METADATA_KEY_CHARS, key
+                    ));
+                }
+                if value.chars().count() > MAX_METADATA_VALUE_CHARS {
+                    return invalid(format!(
+                        "metadata values must be at most {} characters: {}",
+                        MAX_METADATA_VALUE_CHARS, key
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `overrides` で指定した項目を上書きする（metadata は丸ごと置き換える）
+    pub fn merge(&self, overrides: &GenerationOptions) -> GenerationOptions {
+        GenerationOptions {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_output_tokens: overrides.max_output_tokens.or(self.max_output_tokens),
+            truncation: overrides.truncation.or(self.truncation),
+            parallel_tool_calls: overrides.parallel_tool_calls.or(self.parallel_tool_calls),
+            store: overrides.store.or(self.store),
+            metadata: overrides.metadata.clone().or_else(|| self.metadata.clone()),
+            verbosity: overrides.verbosity.or(self.verbosity),
+        }
+    }
+}
+
+/// 入力の切り詰め
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// コンテキストウィンドウを超えたら古い入力を落とす
+    Auto,
+    /// 超えたらエラーにする
+    Disabled,
+}
+
+/// 出力の詳しさ（`text.verbosity`）
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    Low,
+    Medium,
+    High,
+}
+
+/// 推論の設定（Responses API の `reasoning` と同じ形式）
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReasoningOptions {
+    /// 推論にかける労力（未指定ならモデルのデフォルト）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effort: Option<ReasoningEffort>,
+    /// 推論の要約（指定すると `reasoning_summary` に要約を返す）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ReasoningSummary>,
+}
+
+/// 推論の労力
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningEffort {
+    None,
+    Minimal,
+    Low,
+    Medium,
+    High,
+    Xhigh,
+}
+
+/// 推論の要約の詳しさ
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningSummary {
+    Auto,
+    Concise,
+    Detailed,
 }
 
 /// クライアントへのレスポンス
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatResponse {
+    /// 全メッセージのテキストを連結したもの
     pub response: String,
     pub model: String,
+    /// OpenAI 側のレスポンスID（`previous_response_id` で会話を続けるのに使う）
+    pub response_id: String,
     pub usage: Usage,
+    /// JSON 形式を指定した場合のパース結果
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<serde_json::Value>,
+    /// モデルが応答を拒否したか
+    pub refused: bool,
+    /// モデルが応答を拒否した場合の理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    /// 引用（位置は `response` 内の文字単位）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+    /// 推論の要約（`reasoning.summary` を指定した場合）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasoning_summary: Vec<String>,
+    /// 応答キャッシュから返したか
+    #[serde(default)]
+    pub cached: bool,
+    /// 料金（USD、単価が不明なモデルは None。キャッシュから返した場合は 0）
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
 }
 
-#[derive(Serialize)]
+/// 引用（URL・ファイル）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Citation {
+    /// Web ページの引用（`start_index..end_index` の範囲）
+    Url {
+        url: String,
+        title: Option<String>,
+        start_index: usize,
+        end_index: usize,
+    },
+    /// アップロードされたファイルの引用（`index` の位置）
+    File {
+        file_id: String,
+        filename: Option<String>,
+        index: usize,
+    },
+    /// コンテナ内のファイルの引用（`start_index..end_index` の範囲）
+    ContainerFile {
+        container_id: String,
+        file_id: String,
+        filename: Option<String>,
+        start_index: usize,
+        end_index: usize,
+    },
+}
+
+impl Citation {
+    /// 位置を `offset` 文字ずらす（複数パーツを連結したテキスト上の位置にする）
+    pub fn shifted(self, offset: usize) -> Self {
+        match self {
+            Citation::Url {
+                url,
+                title,
+                start_index,
+                end_index,
+            } => Citation::Url {
+                url,
+                title,
+                start_index: start_index + offset,
+                end_index: end_index + offset,
+            },
+            Citation::File {
+                file_id,
+                filename,
+                index,
+            } => Citation::File {
+                file_id,
+                filename,
+                index: index + offset,
+            },
+            Citation::ContainerFile {
+                container_id,
+                file_id,
+                filename,
+                start_index,
+                end_index,
+            } => Citation::ContainerFile {
+                container_id,
+                file_id,
+                filename,
+                start_index: start_index + offset,
+                end_index: end_index + offset,
+            },
+        }
+    }
+}
+
+/// 構造化出力のレスポンス（`OpenAIService::chat_structured` の結果）
+#[derive(Debug, Clone)]
+pub struct StructuredResponse<T> {
+    /// 出力をデシリアライズした値
+    pub data: T,
+    /// 元のレスポンス（生のテキストとトークン使用量）
+    pub response: ChatResponse,
+}
+
+/// 出力形式（Responses API の `text.format` と同じ形式）
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// 自由形式のテキスト（デフォルト）
+    Text,
+    /// 任意の JSON オブジェクト
+    JsonObject,
+    /// JSON Schema に従う JSON（Structured Outputs）
+    JsonSchema {
+        name: String,
+        schema: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        /// true の場合、スキーマへの準拠が保証される
+        #[serde(default = "default_strict")]
+        strict: bool,
+    },
+}
+
+impl ResponseFormat {
+    /// 出力が JSON になる形式か
+    pub fn is_json(&self) -> bool {
+        !matches!(self, ResponseFormat::Text)
+    }
+}
+
+fn default_strict() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    /// completion_tokens のうち推論に使ったトークン数
+    pub reasoning_tokens: u32,
+    /// prompt_tokens のうちプロンプトキャッシュから読んだトークン数
+    #[serde(default)]
+    pub cached_tokens: u32,
+}
+
+/// ストリーミングレスポンスのイベント
+///
+/// `Delta` を0回以上返した後、最後に `Completed` を1回だけ返す。
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatStreamEvent {
+    /// テキストの差分
+    Delta { text: String },
+    /// 生成完了（全文と最終的なトークン使用量）
+    Completed(ChatResponse),
 }
 
 // ========================================
@@ -35,16 +378,206 @@ pub struct Usage {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// メッセージの内容（文字列、またはテキスト・画像・ファイルのパーツの配列）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// テキスト部分（パーツの場合は input_text を改行で連結）
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::InputText { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// パーツ（文字列の場合は None）
+    pub fn parts(&self) -> Option<&[ContentPart]> {
+        match self {
+            MessageContent::Text(_) => None,
+            MessageContent::Parts(parts) => Some(parts),
+        }
+    }
+
+    /// 内容が空か（空文字列、またはパーツなし・空テキストのみ）
+    pub fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.trim().is_empty(),
+            MessageContent::Parts(parts) => parts.iter().all(|part| match part {
+                ContentPart::InputText { text } => text.trim().is_empty(),
+                _ => false,
+            }),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+/// メッセージのパーツ
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    /// テキスト
+    InputText { text: String },
+    /// 画像（URL または `data:image/png;base64,...` 形式のデータURL）
+    InputImage {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image_url: Option<String>,
+        /// アップロード済みファイルのID
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+        /// 解像度（"low"、"high" または "auto"）
+        #[serde(default = "default_image_detail")]
+        detail: String,
+    },
+    /// ファイル（PDF など）
+    InputFile {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        /// `data:application/pdf;base64,...` 形式のデータURL
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_data: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_url: Option<String>,
+        /// アップロード済みファイルのID
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+    },
+}
+
+impl ContentPart {
+    /// アップロードされたファイルからパーツを作成
+    ///
+    /// 画像（`image/*`）は input_image、それ以外は input_file としてデータURLに埋め込む。
+    pub fn from_bytes(filename: Option<String>, content_type: &str, data: &[u8]) -> Self {
+        let data_url = format!("data:{};base64,{}", content_type, BASE64.encode(data));
+
+        if content_type.starts_with("image/") {
+            ContentPart::InputImage {
+                image_url: Some(data_url),
+                file_id: None,
+                detail: default_image_detail(),
+            }
+        } else {
+            ContentPart::InputFile {
+                filename,
+                file_data: Some(data_url),
+                file_url: None,
+                file_id: None,
+            }
+        }
+    }
+}
+
+fn default_image_detail() -> String {
+    "auto".to_string()
+}
+
+/// input配列の要素（メッセージ、またはツール呼び出しとその結果）
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputItem {
+    Message(Message),
+    /// モデルが要求したツール呼び出し（前のレスポンスの output をそのまま返す）
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    /// ツールの実行結果
+    FunctionCallOutput { call_id: String, output: String },
+}
+
+impl From<Message> for InputItem {
+    fn from(message: Message) -> Self {
+        InputItem::Message(message)
+    }
+}
+
+/// ツール定義（tools配列の要素）
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolDefinition {
+    Function {
+        name: String,
+        description: String,
+        /// 引数の JSON Schema
+        parameters: serde_json::Value,
+    },
 }
 
 /// OpenAI Responses API へのリクエスト
 #[derive(Serialize)]
 pub struct OpenAIRequest {
     pub model: String,
-    pub input: Vec<Message>,
+    pub input: Vec<InputItem>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub instructions: Option<String>,
+    /// モデルが呼び出せるツール
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+    /// テキスト出力の設定
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<TextOptions>,
+    /// 推論の設定
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<ReasoningOptions>,
+    /// 前のレスポンスのID（指定した場合、input には新しいターンだけを入れる）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<Truncation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<BTreeMap<String, String>>,
+    /// プロンプトキャッシュのキー
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    /// trueの場合、SSEでイベントを逐次返す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+}
+
+/// テキスト出力の設定（`text`）
+#[derive(Serialize)]
+pub struct TextOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbosity: Option<Verbosity>,
 }
 
 /// OpenAI Responses API からのレスポンス
@@ -56,22 +589,122 @@ pub struct OpenAIResponse {
     pub usage: OpenAIUsage,
 }
 
-/// output配列の要素（type: "message" または "reasoning"）
+/// output配列の要素（type: "message"、"reasoning" または "function_call"）
 #[derive(Deserialize, Debug)]
 pub struct OutputItem {
-    /// アイテムの種類: "message"（公開出力）または "reasoning"（内部思考）
+    /// アイテムの種類: "message"（公開出力）、"reasoning"（内部思考）、"function_call"（ツール呼び出し）
     #[serde(rename = "type")]
     pub item_type: String,
     /// コンテンツ（messageタイプのみ存在）
     #[serde(default)]
     pub content: Vec<ContentItem>,
+    /// ツール呼び出しID（function_callタイプのみ存在）
+    #[serde(default)]
+    pub call_id: Option<String>,
+    /// 呼び出すツール名（function_callタイプのみ存在）
+    #[serde(default)]
+    pub name: Option<String>,
+    /// ツールの引数（JSON文字列、function_callタイプのみ存在）
+    #[serde(default)]
+    pub arguments: Option<String>,
+    /// 推論の要約（reasoningタイプのみ存在）
+    #[serde(default)]
+    pub summary: Vec<SummaryItem>,
 }
 
-/// content配列の要素
+/// summary配列の要素（type: "summary_text"）
+#[derive(Deserialize, Debug)]
+pub struct SummaryItem {
+    #[serde(default)]
+    pub text: String,
+}
+
+/// content配列の要素（type: "output_text" または "refusal"）
 #[derive(Deserialize, Debug)]
 pub struct ContentItem {
+    /// パーツの種類（省略時はテキストとして扱う）
+    #[serde(rename = "type", default)]
+    pub content_type: Option<String>,
     #[serde(default)]
     pub text: Option<String>,
+    /// 拒否の理由（refusalタイプのみ存在）
+    #[serde(default)]
+    pub refusal: Option<String>,
+    /// 引用などの注釈（output_textタイプのみ存在）
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// output_text の注釈（位置はパーツのテキスト内の文字単位）
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Annotation {
+    UrlCitation {
+        url: String,
+        #[serde(default)]
+        title: Option<String>,
+        start_index: usize,
+        end_index: usize,
+    },
+    FileCitation {
+        file_id: String,
+        #[serde(default)]
+        filename: Option<String>,
+        index: usize,
+    },
+    ContainerFileCitation {
+        container_id: String,
+        file_id: String,
+        #[serde(default)]
+        filename: Option<String>,
+        start_index: usize,
+        end_index: usize,
+    },
+    /// 引用以外の注釈（file_path など）
+    #[serde(other)]
+    Other,
+}
+
+impl Annotation {
+    /// 引用に変換（引用以外の注釈は None）
+    pub fn into_citation(self) -> Option<Citation> {
+        match self {
+            Annotation::UrlCitation {
+                url,
+                title,
+                start_index,
+                end_index,
+            } => Some(Citation::Url {
+                url,
+                title,
+                start_index,
+                end_index,
+            }),
+            Annotation::FileCitation {
+                file_id,
+                filename,
+                index,
+            } => Some(Citation::File {
+                file_id,
+                filename,
+                index,
+            }),
+            Annotation::ContainerFileCitation {
+                container_id,
+                file_id,
+                filename,
+                start_index,
+                end_index,
+            } => Some(Citation::ContainerFile {
+                container_id,
+                file_id,
+                filename,
+                start_index,
+                end_index,
+            }),
+            Annotation::Other => None,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -79,4 +712,90 @@ pub struct OpenAIUsage {
     pub input_tokens: u32,
     pub output_tokens: u32,
     pub total_tokens: u32,
+    #[serde(default)]
+    pub input_tokens_details: Option<InputTokensDetails>,
+    #[serde(default)]
+    pub output_tokens_details: Option<OutputTokensDetails>,
+}
+
+/// 入力トークンの内訳
+#[derive(Deserialize, Debug)]
+pub struct InputTokensDetails {
+    #[serde(default)]
+    pub cached_tokens: u32,
+}
+
+/// 出力トークンの内訳
+#[derive(Deserialize, Debug)]
+pub struct OutputTokensDetails {
+    #[serde(default)]
+    pub reasoning_tokens: u32,
+}
+
+// ========================================
+// OpenAI Responses API ストリーミングイベント（内部用）
+// ========================================
+
+/// SSEの各イベント（`data` フィールドのJSON）
+///
+/// 使用しないイベント（`response.created` など）は `Other` にまとめる。
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum OpenAIStreamEvent {
+    /// テキストの差分
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    /// 生成完了（最終的なレスポンス全体を含む）
+    #[serde(rename = "response.completed")]
+    Completed { response: OpenAIResponse },
+    /// 生成失敗
+    #[serde(rename = "response.failed")]
+    Failed { response: OpenAIStreamFailure },
+    /// 途中終了（トークン上限など）
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: OpenAIStreamFailure },
+    /// ストリーム自体のエラー
+    #[serde(rename = "error")]
+    Error {
+        #[serde(flatten)]
+        error: OpenAIErrorDetail,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// `response.failed` / `response.incomplete` に含まれるレスポンス
+#[derive(Deserialize, Debug)]
+pub struct OpenAIStreamFailure {
+    #[serde(default)]
+    pub error: Option<OpenAIErrorDetail>,
+    #[serde(default)]
+    pub incomplete_details: Option<IncompleteDetails>,
+}
+
+/// エラーレスポンスのエンベロープ（`{"error": {...}}`）
+#[derive(Deserialize, Debug)]
+pub struct OpenAIErrorResponse {
+    pub error: OpenAIErrorDetail,
+}
+
+/// エラー詳細
+#[derive(Deserialize, Debug, Default)]
+pub struct OpenAIErrorDetail {
+    #[serde(default)]
+    pub message: String,
+    /// エラー種別（`invalid_request_error` など）
+    #[serde(rename = "type", default)]
+    pub error_type: Option<String>,
+    /// エラーコード（`rate_limit_exceeded`、`context_length_exceeded` など）
+    #[serde(default)]
+    pub code: Option<String>,
+    /// 問題のあるパラメータ名
+    #[serde(default)]
+    pub param: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IncompleteDetails {
+    pub reason: String,
 }
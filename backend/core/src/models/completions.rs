@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+
+use super::chat::{ContentPart, Message, MessageContent, ReasoningEffort, ResponseFormat};
+
+// ========================================
+// OpenAI Chat Completions 互換 API 用の型定義（内部用）
+// ========================================
+
+/// Chat Completions API へのリクエスト
+#[derive(Serialize)]
+pub struct CompletionsRequest {
+    pub model: String,
+    pub messages: Vec<CompletionsMessage>,
+    /// 出力形式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<CompletionsResponseFormat>,
+    /// 推論にかける労力（要約には対応していない）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 出力トークン数の上限（互換サーバーが広く対応している `max_tokens` で送る）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// trueの場合、SSEでチャンクを逐次返す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// ストリーミングの設定（最後のチャンクでトークン使用量を受け取る）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// ストリーミングの設定
+#[derive(Serialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+/// メッセージ（messages配列の要素）
+#[derive(Serialize)]
+pub struct CompletionsMessage {
+    pub role: String,
+    pub content: CompletionsContent,
+}
+
+/// メッセージの内容（文字列、またはパーツの配列）
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum CompletionsContent {
+    Text(String),
+    Parts(Vec<CompletionsContentPart>),
+}
+
+/// メッセージのパーツ
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CompletionsContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    File { file: FileData },
+}
+
+/// 画像（URL またはデータURL）
+#[derive(Serialize)]
+pub struct ImageUrl {
+    pub url: String,
+    pub detail: String,
+}
+
+/// ファイル（データURL またはアップロード済みファイルのID）
+#[derive(Serialize)]
+pub struct FileData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+}
+
+impl From<Message> for CompletionsMessage {
+    fn from(message: Message) -> Self {
+        let content = match message.content {
+            MessageContent::Text(text) => CompletionsContent::Text(text),
+            MessageContent::Parts(parts) => CompletionsContent::Parts(
+                parts
+                    .into_iter()
+                    .filter_map(CompletionsContentPart::from_part)
+                    .collect(),
+            ),
+        };
+
+        CompletionsMessage {
+            role: message.role,
+            content,
+        }
+    }
+}
+
+impl CompletionsContentPart {
+    /// Responses API 形式のパーツから変換
+    ///
+    /// Chat Completions で表せないパーツ（ファイルIDのみの画像、URL 指定のファイル）は None。
+    fn from_part(part: ContentPart) -> Option<Self> {
+        match part {
+            ContentPart::InputText { text } => Some(CompletionsContentPart::Text { text }),
+            ContentPart::InputImage {
+                image_url, detail, ..
+            } => image_url.map(|url| CompletionsContentPart::ImageUrl {
+                image_url: ImageUrl { url, detail },
+            }),
+            ContentPart::InputFile {
+                file_data: None,
+                file_id: None,
+                ..
+            } => None,
+            ContentPart::InputFile {
+                filename,
+                file_data,
+                file_id,
+                ..
+            } => Some(CompletionsContentPart::File {
+                file: FileData {
+                    filename,
+                    file_data,
+                    file_id,
+                },
+            }),
+        }
+    }
+}
+
+/// 出力形式（`response_format`）
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CompletionsResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+/// JSON Schema の出力形式
+#[derive(Serialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub strict: bool,
+}
+
+impl From<ResponseFormat> for CompletionsResponseFormat {
+    fn from(format: ResponseFormat) -> Self {
+        match format {
+            ResponseFormat::Text => CompletionsResponseFormat::Text,
+            ResponseFormat::JsonObject => CompletionsResponseFormat::JsonObject,
+            ResponseFormat::JsonSchema {
+                name,
+                schema,
+                description,
+                strict,
+            } => CompletionsResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name,
+                    schema,
+                    description,
+                    strict,
+                },
+            },
+        }
+    }
+}
+
+/// Chat Completions API からのレスポンス
+#[derive(Deserialize, Debug)]
+pub struct CompletionsResponse {
+    #[serde(default)]
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<CompletionsChoice>,
+    #[serde(default)]
+    pub usage: Option<CompletionsUsage>,
+}
+
+/// choices配列の要素
+#[derive(Deserialize, Debug)]
+pub struct CompletionsChoice {
+    pub message: CompletionsResponseMessage,
+}
+
+/// 生成されたメッセージ
+#[derive(Deserialize, Debug)]
+pub struct CompletionsResponseMessage {
+    #[serde(default)]
+    pub content: Option<String>,
+    /// 拒否の理由
+    #[serde(default)]
+    pub refusal: Option<String>,
+}
+
+/// トークン使用量
+#[derive(Deserialize, Debug, Default)]
+pub struct CompletionsUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    #[serde(default)]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    #[serde(default)]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+/// 入力トークンの内訳
+#[derive(Deserialize, Debug, Default)]
+pub struct PromptTokensDetails {
+    #[serde(default)]
+    pub cached_tokens: u32,
+}
+
+/// 出力トークンの内訳
+#[derive(Deserialize, Debug, Default)]
+pub struct CompletionTokensDetails {
+    #[serde(default)]
+    pub reasoning_tokens: u32,
+}
+
+/// ストリーミングのチャンク（SSE の `data` フィールドのJSON）
+#[derive(Deserialize, Debug)]
+pub struct CompletionsChunk {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<CompletionsChunkChoice>,
+    /// トークン使用量（`include_usage` を指定した場合、最後のチャンクのみ）
+    #[serde(default)]
+    pub usage: Option<CompletionsUsage>,
+}
+
+/// チャンクの choices配列の要素
+#[derive(Deserialize, Debug)]
+pub struct CompletionsChunkChoice {
+    pub delta: CompletionsDelta,
+}
+
+/// メッセージの差分
+#[derive(Deserialize, Debug)]
+pub struct CompletionsDelta {
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub refusal: Option<String>,
+}
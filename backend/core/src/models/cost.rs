@@ -0,0 +1,81 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// トークン使用量と料金の合計
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct CostSummary {
+    /// API の呼び出し回数
+    pub calls: i64,
+    pub prompt_tokens: i64,
+    pub cached_tokens: i64,
+    pub completion_tokens: i64,
+    pub reasoning_tokens: i64,
+    /// 料金の合計（USD、単価が不明なモデルの呼び出しは含まない）
+    pub cost_usd: f64,
+    /// 単価が不明で料金に含めていない呼び出しの回数
+    pub unpriced_calls: i64,
+}
+
+impl CostSummary {
+    /// 別の合計を加算
+    pub fn add(&mut self, other: &CostSummary) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+        self.cost_usd += other.cost_usd;
+        self.unpriced_calls += other.unpriced_calls;
+    }
+}
+
+/// モデルごとの合計
+#[derive(Debug, FromRow, Serialize)]
+pub struct ModelCost {
+    pub model: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub summary: CostSummary,
+}
+
+/// セッションの料金（`GET /sessions/{id}/cost`）
+#[derive(Debug, Serialize)]
+pub struct SessionCost {
+    pub session_id: Uuid,
+    pub total: CostSummary,
+    /// モデルごとの内訳（モデル名順）
+    pub models: Vec<ModelCost>,
+}
+
+/// 日ごとの合計（日付は UTC）
+#[derive(Debug, FromRow, Serialize)]
+pub struct DailyCost {
+    pub date: NaiveDate,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub summary: CostSummary,
+}
+
+/// 日ごとの料金のクエリ（`GET /costs/daily?from=...&to=...`、両端を含む）
+#[derive(Deserialize, Debug)]
+pub struct DailyCostQuery {
+    /// 未指定なら `to` の29日前（30日間）
+    #[serde(default)]
+    pub from: Option<NaiveDate>,
+    /// 未指定なら今日（UTC）
+    #[serde(default)]
+    pub to: Option<NaiveDate>,
+}
+
+/// 日ごとの料金のレスポンス（呼び出しのない日は含まない）
+#[derive(Debug, Serialize)]
+pub struct DailyCostResponse {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// 日付順
+    pub days: Vec<DailyCost>,
+    /// 期間全体の合計
+    pub total: CostSummary,
+}
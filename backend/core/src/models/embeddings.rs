@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use super::Usage;
+
+// ========================================
+// OpenAI Embeddings API 用の型定義（内部用）
+// ========================================
+
+/// Embeddings API へのリクエスト
+#[derive(Serialize)]
+pub struct EmbeddingsRequest<'a> {
+    pub model: &'a str,
+    pub input: &'a [String],
+}
+
+/// Embeddings API からのレスポンス
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingsResponse {
+    pub data: Vec<EmbeddingData>,
+    /// トークン使用量（返さない互換 API では 0）
+    #[serde(default)]
+    pub usage: EmbeddingsUsage,
+}
+
+/// Embeddings API のトークン使用量
+#[derive(Deserialize, Debug, Default)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// data配列の要素（`index` は入力の順番）
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingData {
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+// ========================================
+// プロバイダーの戻り値
+// ========================================
+
+/// 埋め込みベクトル（入力と同じ順番）とトークン使用量
+#[derive(Debug, Clone, Default)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    /// トークン使用量（入力のみ。料金の記録に使う）
+    pub usage: Usage,
+}
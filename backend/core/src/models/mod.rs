@@ -1,11 +1,30 @@
 // データ構造・型定義
 
 pub mod chat;
+pub mod completions;
+pub mod cost;
+pub mod embeddings;
+pub mod moderation;
+pub mod search;
 pub mod session;
+pub mod ws;
 
 // 頻繁に使う型を再エクスポート
-pub use chat::{ChatRequest, ChatResponse, Message, OpenAIRequest, OpenAIResponse, Usage};
+pub use chat::{
+    Annotation, ChatOptions, ChatRequest, ChatResponse, ChatStreamEvent, Citation, ContentPart,
+    GenerationOptions, InputItem, Message, MessageContent, OpenAIErrorDetail, OpenAIErrorResponse,
+    OpenAIRequest, OpenAIResponse, OpenAIStreamEvent, OpenAIUsage, ReasoningEffort,
+    ReasoningOptions, ReasoningSummary, ResponseFormat, StructuredResponse, TextOptions,
+    ToolDefinition, Truncation, Usage, Verbosity,
+};
+pub use embeddings::Embeddings;
+pub use cost::{CostSummary, DailyCost, DailyCostQuery, DailyCostResponse, ModelCost, SessionCost};
+pub use moderation::ModerationFlags;
+pub use search::{MessageSearchResult, SearchQuery, SearchResponse};
 pub use session::{
-    ChatMessage, CreateSessionRequest, CreateSessionResponse, Session, SessionChatRequest,
-    SessionChatResponse, SessionWithMessages,
+    ChatMessage, CreateSessionRequest, CreateSessionResponse, HistoryMode, HistoryStrategy,
+    PromptCacheStats, Session,
+    SessionChatRequest, SessionChatResponse, SessionChatStreamEvent, SessionSummary,
+    SessionWithMessages, UpdateMessageRequest, UpdateSessionRequest,
 };
+pub use ws::{WsClientMessage, WsServerMessage};
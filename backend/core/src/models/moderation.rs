@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// ========================================
+// OpenAI Moderations API 用の型定義（内部用）
+// ========================================
+
+/// Moderations API へのリクエスト
+#[derive(Serialize)]
+pub struct ModerationRequest<'a> {
+    pub model: &'a str,
+    pub input: &'a [String],
+}
+
+/// Moderations API からのレスポンス（`results` は入力と同じ順番）
+#[derive(Deserialize, Debug)]
+pub struct ModerationResponse {
+    pub results: Vec<ModerationResult>,
+}
+
+/// results配列の要素
+#[derive(Deserialize, Debug)]
+pub struct ModerationResult {
+    /// カテゴリごとのスコア（0.0〜1.0）
+    pub category_scores: BTreeMap<String, f64>,
+}
+
+// ========================================
+// DB モデル
+// ========================================
+
+/// メッセージのモデレーション結果（モデレーション有効時に各メッセージに保存する）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModerationFlags {
+    /// 記録のしきい値を超えたカテゴリがあるか
+    pub flagged: bool,
+    /// 記録のしきい値を超えたカテゴリとスコア
+    pub categories: BTreeMap<String, f64>,
+}
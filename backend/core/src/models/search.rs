@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::session::ChatMessage;
+
+/// 意味検索のクエリ（`GET /search?q=...&limit=...`）
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    pub q: String,
+    /// 返す件数（未指定なら 10、最大 50）
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// 意味検索でヒットしたメッセージ
+#[derive(Debug, FromRow, Serialize)]
+pub struct MessageSearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    /// クエリとのコサイン類似度（-1〜1、大きいほど近い）
+    pub score: f64,
+}
+
+/// 意味検索のレスポンス（類似度の高い順）
+#[derive(Serialize, Debug)]
+pub struct SearchResponse {
+    pub query: String,
+    pub results: Vec<MessageSearchResult>,
+}
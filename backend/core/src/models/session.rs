@@ -1,8 +1,13 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use super::chat::{ContentPart, GenerationOptions, Message, MessageContent, ReasoningOptions};
+use super::moderation::ModerationFlags;
+
 // ========================================
 // DB モデル
 // ========================================
@@ -12,20 +17,141 @@ use uuid::Uuid;
 pub struct Session {
     pub id: Uuid,
     pub system_prompt: Option<String>,
+    /// 使用するモデル（NULLの場合はサーバーのデフォルト）
+    pub model: Option<String>,
+    /// 履歴の送り方
+    pub history_mode: HistoryMode,
+    /// 送る履歴の選び方
+    pub history_strategy: Json<HistoryStrategy>,
+    /// サンプリング・生成のパラメーターのデフォルト（リクエストの指定が優先）
+    pub generation: Json<GenerationOptions>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Session {
+    /// プロンプトキャッシュのキー（`prompt_cache_key`）
+    ///
+    /// セッションIDとシステムプロンプトから作るため、同じセッションの呼び出しは同じキーになり、
+    /// 毎回先頭に送る長い instructions がキャッシュされやすくなる。
+    pub fn prompt_cache_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.id.as_bytes());
+        hasher.update(self.system_prompt.as_deref().unwrap_or_default().as_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+
+        format!("session_{}", &digest[..32])
+    }
+}
+
 /// メッセージ（会話履歴の1行）
 #[derive(Debug, FromRow, Serialize, Clone)]
 pub struct ChatMessage {
     pub id: Uuid,
     pub session_id: Uuid,
     pub role: String,
+    /// テキスト部分（パーツ付きのメッセージは input_text を連結したもの）
+    pub content: String,
+    /// 画像・ファイルを含むパーツ（文字列のみのメッセージは NULL）
+    pub parts: Option<Json<Vec<ContentPart>>>,
+    /// 推論の要約（アシスタントメッセージのみ）
+    pub reasoning_summary: Vec<String>,
+    /// 完了状態（"completed"、中断した "incomplete" またはプロバイダーのエラーで終わった "failed"）
+    pub status: String,
+    /// OpenAI 側のレスポンスID（アシスタントメッセージのみ。中断した場合は NULL）
+    pub response_id: Option<String>,
+    /// ピン留め（履歴の選び方に関わらず常に送る）
+    pub pinned: bool,
+    /// 生成時に送った履歴のメッセージID（アシスタントメッセージのみ）
+    pub context_message_ids: Vec<Uuid>,
+    /// モデレーションの結果（モデレーション無効時に保存したメッセージは NULL）
+    pub moderation: Option<Json<ModerationFlags>>,
+    /// 生成したモデル（アシスタントメッセージのみ）
+    pub model: Option<String>,
+    /// トークン使用量（アシスタントメッセージのみ。中断した場合は NULL）
+    pub prompt_tokens: Option<i32>,
+    pub completion_tokens: Option<i32>,
+    /// prompt_tokens のうちプロンプトキャッシュから読んだトークン数
+    pub cached_tokens: Option<i32>,
+    /// completion_tokens のうち推論に使ったトークン数
+    pub reasoning_tokens: Option<i32>,
+    /// 料金（USD。単価が不明なモデル・中断した場合は NULL）
+    pub cost_usd: Option<f64>,
+    /// API の呼び出しから返答の完了（中断）までの時間（ミリ秒）
+    pub latency_ms: Option<i32>,
+    /// 正常に完了しなかった理由（status が "incomplete" または "failed" の場合）
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 履歴の送り方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum HistoryMode {
+    /// 毎回全履歴を送る
+    #[default]
+    Replay,
+    /// 新しいターンだけを送り、前の返答の `previous_response_id` でサーバー側の会話を続ける
+    ///
+    /// 前の返答のIDがない、またはサーバー側で見つからない場合は全履歴を送る。
+    Chained,
+}
+
+/// 送る履歴の選び方
+///
+/// ターンはユーザーメッセージから次のユーザーメッセージの前までを指す。
+/// どの選び方でも、ピン留めしたメッセージは常に送る。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HistoryStrategy {
+    /// 全履歴を送る
+    #[default]
+    Full,
+    /// 直近の `turns` ターンだけを送る
+    LastTurns { turns: usize },
+    /// instructions・新しいメッセージを含めて `max_tokens` に収まる直近のターンを送る
+    TokenBudget { max_tokens: usize },
+    /// 最初の `head_turns` ターンと直近の `tail_turns` ターンを送り、間を省く
+    DropMiddle { head_turns: usize, tail_turns: usize },
+    /// 要約と要約後の履歴を送る
+    ///
+    /// instructions・要約・新しいメッセージを含めて `max_tokens` を超えたら、
+    /// 直近の `keep_turns` ターンより前を LLM で要約し、前の要約に積み重ねる。
+    Summarize { max_tokens: usize, keep_turns: usize },
+}
+
+/// セッションの古いターンの要約
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub session_id: Uuid,
     pub content: String,
+    /// 要約に含めた最後のメッセージ
+    pub last_message_id: Uuid,
+    /// 要約に含めたメッセージ数（セッションの先頭から）
+    pub message_count: i32,
     pub created_at: DateTime<Utc>,
 }
 
+impl ChatMessage {
+    /// OpenAI に送るメッセージの内容（パーツがあればパーツ、なければテキスト）
+    pub fn message_content(&self) -> MessageContent {
+        match &self.parts {
+            Some(Json(parts)) => MessageContent::Parts(parts.clone()),
+            None => MessageContent::Text(self.content.clone()),
+        }
+    }
+
+    /// OpenAI に送るメッセージに変換
+    pub fn to_message(&self) -> Message {
+        Message {
+            role: self.role.clone(),
+            content: self.message_content(),
+        }
+    }
+}
+
 // ========================================
 // API リクエスト/レスポンス
 // ========================================
@@ -35,6 +161,18 @@ pub struct ChatMessage {
 pub struct CreateSessionRequest {
     #[serde(default)]
     pub system_prompt: Option<String>,
+    /// 使用するモデル（未指定ならサーバーのデフォルト）
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 履歴の送り方（未指定なら replay）
+    #[serde(default)]
+    pub history_mode: HistoryMode,
+    /// 送る履歴の選び方（未指定なら全履歴）
+    #[serde(default)]
+    pub history_strategy: HistoryStrategy,
+    /// サンプリング・生成のパラメーターのデフォルト（未指定なら API のデフォルト）
+    #[serde(default)]
+    pub generation: GenerationOptions,
 }
 
 /// セッション作成レスポンス
@@ -42,27 +180,119 @@ pub struct CreateSessionRequest {
 pub struct CreateSessionResponse {
     pub id: Uuid,
     pub system_prompt: Option<String>,
+    pub model: Option<String>,
+    pub history_mode: HistoryMode,
+    pub history_strategy: HistoryStrategy,
+    pub generation: GenerationOptions,
     pub created_at: DateTime<Utc>,
 }
 
-/// セッション内チャットリクエスト
+/// セッション更新リクエスト（指定した項目のみ更新）
+#[derive(Deserialize, Default)]
+pub struct UpdateSessionRequest {
+    #[serde(default)]
+    pub history_strategy: Option<HistoryStrategy>,
+    /// サンプリング・生成のパラメーターのデフォルト（指定した値で置き換える）
+    #[serde(default)]
+    pub generation: Option<GenerationOptions>,
+}
+
+/// メッセージ更新リクエスト
 #[derive(Deserialize)]
+pub struct UpdateMessageRequest {
+    pub pinned: bool,
+}
+
+/// セッション内チャットリクエスト
+#[derive(Deserialize, Debug)]
 pub struct SessionChatRequest {
-    pub message: String,
+    /// 文字列、またはテキスト・画像・ファイルのパーツの配列
+    pub message: MessageContent,
+    /// 推論の設定（推論モデルのみ）
+    #[serde(default)]
+    pub reasoning: Option<ReasoningOptions>,
+    /// 応答キャッシュを読まずに API を呼ぶ（キャッシュ有効時のみ意味がある）
+    #[serde(default)]
+    pub bypass_cache: bool,
+    /// サンプリング・生成のパラメーター（指定した項目だけセッションのデフォルトを上書き）
+    #[serde(flatten)]
+    pub generation: GenerationOptions,
 }
 
 /// セッション内チャットレスポンス
-#[derive(Serialize)]
+#[derive(Serialize, Debug)]
 pub struct SessionChatResponse {
     pub response: String,
     pub model: String,
     pub session_id: Uuid,
     pub message_count: usize,
+    /// 推論の要約（`reasoning.summary` を指定した場合）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasoning_summary: Vec<String>,
+    /// このターンの料金（USD、単価が不明なモデルは None）
+    pub cost_usd: Option<f64>,
+}
+
+/// セッション内ストリーミングチャットのイベント（SSE）
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionChatStreamEvent {
+    /// テキストの差分
+    Delta { text: String },
+    /// 生成完了（メッセージ保存後に送信）
+    Completed(SessionChatResponse),
+    /// エラー（ストリーム開始後に発生したもの）
+    Error { code: String, message: String },
+}
+
+impl SessionChatStreamEvent {
+    /// SSEのイベント名
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            SessionChatStreamEvent::Delta { .. } => "delta",
+            SessionChatStreamEvent::Completed(_) => "completed",
+            SessionChatStreamEvent::Error { .. } => "error",
+        }
+    }
 }
 
 /// セッション情報（履歴付き）
 #[derive(Serialize)]
 pub struct SessionWithMessages {
     pub session: Session,
+    /// 全履歴（要約したメッセージも含む）
     pub messages: Vec<ChatMessage>,
+    /// 最新の要約（summarize 戦略で要約した場合）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<SessionSummary>,
+    /// プロンプトキャッシュの利用状況
+    pub prompt_cache: PromptCacheStats,
+}
+
+/// セッションのプロンプトキャッシュの利用状況（使用量を保存した返答の合計）
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PromptCacheStats {
+    pub prompt_tokens: i64,
+    /// prompt_tokens のうちキャッシュから読んだトークン数
+    pub cached_tokens: i64,
+    /// cached_tokens / prompt_tokens（使用量を保存した返答がなければ None）
+    pub hit_ratio: Option<f64>,
+}
+
+impl PromptCacheStats {
+    /// メッセージに保存した使用量から集計
+    pub fn from_messages(messages: &[ChatMessage]) -> Self {
+        let (prompt_tokens, cached_tokens) = messages
+            .iter()
+            .filter_map(|msg| Some((msg.prompt_tokens?, msg.cached_tokens.unwrap_or(0))))
+            .fold((0i64, 0i64), |(prompt, cached), (p, c)| {
+                (prompt + i64::from(p), cached + i64::from(c))
+            });
+
+        Self {
+            prompt_tokens,
+            cached_tokens,
+            hit_ratio: (prompt_tokens > 0).then(|| cached_tokens as f64 / prompt_tokens as f64),
+        }
+    }
 }
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{SessionChatRequest, SessionChatResponse, Usage};
+
+// ========================================
+// WebSocket プロトコル（/sessions/{id}/ws）
+// ========================================
+
+/// クライアント → サーバーのフレーム
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsClientMessage {
+    /// メッセージを送信して生成を開始（`/chat` と同じ `SessionChatRequest` 形式）
+    Chat(SessionChatRequest),
+    /// 生成中の応答をキャンセル
+    Cancel,
+}
+
+/// サーバー → クライアントのフレーム
+///
+/// 1ターンは `Delta` を0回以上返した後、以下のいずれかで終わる。
+/// - 正常完了: `Usage` → `Completed`
+/// - キャンセル: `Cancelled`
+/// - エラー: `Error`
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsServerMessage {
+    /// テキストの差分
+    Delta { text: String },
+    /// トークン使用量
+    Usage(Usage),
+    /// 生成完了（メッセージ保存後に送信）
+    Completed(SessionChatResponse),
+    /// キャンセル完了（途中までの返答を "incomplete" として保存済み）
+    Cancelled {
+        session_id: Uuid,
+        message_count: usize,
+    },
+    /// エラー
+    Error { code: String, message: String },
+}
@@ -0,0 +1,378 @@
+//! コンテンツのモデレーション
+//!
+//! ユーザーの入力を生成の前に、モデルの出力を生成の後に確認する。
+//! 判定は Moderations API（`LlmProvider::moderate`）またはローカルのルールで行い、
+//! カテゴリごとのしきい値でメッセージに記録（flag）するか、ブロックするかを決める。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::ModerationFlags;
+use crate::services::{LlmProvider, OpenAIError};
+
+/// Moderations API のデフォルトモデル
+pub const DEFAULT_MODERATION_MODEL: &str = "omni-moderation-latest";
+
+/// 記録するしきい値のデフォルト
+pub const DEFAULT_FLAG_THRESHOLD: f64 = 0.5;
+
+/// ブロックするしきい値のデフォルト
+pub const DEFAULT_BLOCK_THRESHOLD: f64 = 0.8;
+
+/// 確認する段階
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationStage {
+    /// ユーザーの入力（生成前）
+    Input,
+    /// モデルの出力（生成後）
+    Output,
+}
+
+impl fmt::Display for ModerationStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationStage::Input => write!(f, "input"),
+            ModerationStage::Output => write!(f, "output"),
+        }
+    }
+}
+
+/// モデレーションの方式（`MODERATION`）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ModerationMode {
+    /// 確認しない
+    #[default]
+    Off,
+    /// Moderations API
+    Api,
+    /// ローカルのルール（`MODERATION_RULES` のファイル）
+    Rules,
+}
+
+impl FromStr for ModerationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ModerationMode::Off),
+            "api" => Ok(ModerationMode::Api),
+            "rules" => Ok(ModerationMode::Rules),
+            _ => Err(format!(
+                "Unknown moderation mode '{}' (expected: off, api, rules)",
+                s
+            )),
+        }
+    }
+}
+
+/// カテゴリごとのしきい値（スコアがしきい値以上なら該当する）
+///
+/// `"*=0.8,violence=0.5,self-harm=0.2"` の形式でパースする。`*` は指定のないカテゴリに使う。
+#[derive(Clone, Debug, PartialEq)]
+pub struct CategoryThresholds {
+    /// 指定のないカテゴリのしきい値（None なら該当させない）
+    default: Option<f64>,
+    categories: BTreeMap<String, f64>,
+}
+
+impl CategoryThresholds {
+    /// 全カテゴリに同じしきい値を使う
+    pub fn new(default: f64) -> Self {
+        Self {
+            default: Some(default),
+            categories: BTreeMap::new(),
+        }
+    }
+
+    /// 指定したカテゴリだけを対象にする
+    pub fn none() -> Self {
+        Self {
+            default: None,
+            categories: BTreeMap::new(),
+        }
+    }
+
+    /// カテゴリのしきい値を設定
+    pub fn with(mut self, category: &str, threshold: f64) -> Self {
+        self.categories.insert(category.to_string(), threshold);
+        self
+    }
+
+    /// カテゴリのしきい値
+    pub fn threshold(&self, category: &str) -> Option<f64> {
+        self.categories.get(category).copied().or(self.default)
+    }
+
+    /// しきい値以上のカテゴリとスコア
+    fn exceeded(&self, scores: &BTreeMap<String, f64>) -> BTreeMap<String, f64> {
+        scores
+            .iter()
+            .filter(|(category, score)| self.threshold(category).is_some_and(|t| **score >= t))
+            .map(|(category, score)| (category.clone(), *score))
+            .collect()
+    }
+}
+
+impl FromStr for CategoryThresholds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut thresholds = Self::none();
+
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || format!("Invalid category threshold: {}", pair);
+            let (category, threshold) = pair.split_once('=').ok_or_else(invalid)?;
+            let threshold: f64 = threshold.trim().parse().map_err(|_| invalid())?;
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(format!("Threshold must be between 0 and 1: {}", pair));
+            }
+
+            match category.trim() {
+                "*" => thresholds.default = Some(threshold),
+                category => thresholds = thresholds.with(category, threshold),
+            }
+        }
+
+        Ok(thresholds)
+    }
+}
+
+/// ローカルのルール（カテゴリごとの禁止語句）
+///
+/// 1行に `category=語句` を書く（`#` で始まる行はコメント）。
+/// 英数字の語句は単語単位で、それ以外（日本語など）は部分文字列で、大文字小文字を区別せずに照合する。
+/// 該当したカテゴリのスコアは 1.0。
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    category: String,
+    /// 小文字にした語句
+    phrase: String,
+    /// 単語に分けた語句（英数字のみの語句。それ以外は None）
+    words: Option<Vec<String>>,
+}
+
+impl RuleSet {
+    /// ルールのテキストをパース
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let mut rules = RuleSet::default();
+
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("Invalid moderation rule at line {}", i + 1);
+            let (category, phrase) = line.split_once('=').ok_or_else(invalid)?;
+            let (category, phrase) = (category.trim(), phrase.trim());
+            if category.is_empty() || phrase.is_empty() {
+                return Err(invalid());
+            }
+            rules = rules.with(category, phrase);
+        }
+
+        Ok(rules)
+    }
+
+    /// ルールのファイルを読み込む
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&data)
+    }
+
+    /// ルールを追加
+    pub fn with(mut self, category: &str, phrase: &str) -> Self {
+        let phrase = phrase.to_lowercase();
+        let words = phrase
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c.is_whitespace())
+            .then(|| words(&phrase));
+
+        self.rules.push(Rule {
+            category: category.to_string(),
+            phrase,
+            words,
+        });
+        self
+    }
+
+    /// 該当したカテゴリのスコア
+    pub fn scores(&self, text: &str) -> BTreeMap<String, f64> {
+        let text = text.to_lowercase();
+        let text_words = words(&text);
+
+        self.rules
+            .iter()
+            .filter(|rule| match &rule.words {
+                Some(phrase) => {
+                    !phrase.is_empty() && text_words.windows(phrase.len()).any(|w| w == phrase)
+                }
+                None => text.contains(&rule.phrase),
+            })
+            .map(|rule| (rule.category.clone(), 1.0))
+            .collect()
+    }
+}
+
+/// 英数字の並びで単語に分ける
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Clone, Default)]
+enum Backend {
+    #[default]
+    Disabled,
+    Api {
+        model: String,
+    },
+    Rules(Arc<RuleSet>),
+}
+
+/// 入力・出力のモデレーション
+///
+/// デフォルトは無効（何も確認しない）。
+#[derive(Clone)]
+pub struct Moderator {
+    backend: Backend,
+    /// メッセージに記録するしきい値
+    flag: CategoryThresholds,
+    /// ブロックするしきい値
+    block: CategoryThresholds,
+}
+
+impl Default for Moderator {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Disabled,
+            flag: CategoryThresholds::new(DEFAULT_FLAG_THRESHOLD),
+            block: CategoryThresholds::new(DEFAULT_BLOCK_THRESHOLD),
+        }
+    }
+}
+
+impl Moderator {
+    /// Moderations API で確認する
+    pub fn api(model: impl Into<String>) -> Self {
+        Self {
+            backend: Backend::Api {
+                model: model.into(),
+            },
+            ..Self::default()
+        }
+    }
+
+    /// ローカルのルールで確認する
+    pub fn rules(rules: RuleSet) -> Self {
+        Self {
+            backend: Backend::Rules(Arc::new(rules)),
+            ..Self::default()
+        }
+    }
+
+    /// 設定（`MODERATION` など）から作成
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let moderator = match config.moderation {
+            ModerationMode::Off => return Ok(Self::default()),
+            ModerationMode::Api => Self::api(&config.moderation_model),
+            ModerationMode::Rules => {
+                let path = config
+                    .moderation_rules
+                    .as_ref()
+                    .ok_or("MODERATION_RULES is required when MODERATION=rules")?;
+                Self::rules(RuleSet::load(path)?)
+            }
+        };
+
+        Ok(moderator.with_thresholds(
+            config.moderation_flag_thresholds.clone(),
+            config.moderation_block_thresholds.clone(),
+        ))
+    }
+
+    /// 記録・ブロックのしきい値を設定
+    pub fn with_thresholds(mut self, flag: CategoryThresholds, block: CategoryThresholds) -> Self {
+        self.flag = flag;
+        self.block = block;
+        self
+    }
+
+    /// 確認するか
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self.backend, Backend::Disabled)
+    }
+
+    /// テキストの確認で呼び出す Moderations API のモデル（API を呼ばない場合は None）
+    ///
+    /// 空のテキスト（画像のみなど）は API を呼ばない。
+    pub fn api_model(&self, text: &str) -> Option<&str> {
+        match &self.backend {
+            Backend::Api { model } if !text.trim().is_empty() => Some(model),
+            _ => None,
+        }
+    }
+
+    /// テキストを確認し、メッセージに保存する結果を返す（無効なら None）
+    ///
+    /// ブロックのしきい値を超えたカテゴリがあれば `AppError::ContentBlocked` を返す。
+    /// Moderations API の呼び出しに失敗した場合は、確認できなかったものとしてエラーを返す。
+    pub async fn check(
+        &self,
+        llm: &dyn LlmProvider,
+        stage: ModerationStage,
+        text: &str,
+    ) -> Result<Option<ModerationFlags>, AppError> {
+        let Some(scores) = self.scores(llm, text).await? else {
+            return Ok(None);
+        };
+
+        let blocked = self.block.exceeded(&scores);
+        if !blocked.is_empty() {
+            let categories: Vec<String> = blocked.into_keys().collect();
+            warn!("Moderation blocked {}: {}", stage, categories.join(", "));
+            return Err(AppError::ContentBlocked { stage, categories });
+        }
+
+        let categories = self.flag.exceeded(&scores);
+        Ok(Some(ModerationFlags {
+            flagged: !categories.is_empty(),
+            categories,
+        }))
+    }
+
+    /// カテゴリごとのスコア（無効なら None）
+    async fn scores(
+        &self,
+        llm: &dyn LlmProvider,
+        text: &str,
+    ) -> Result<Option<BTreeMap<String, f64>>, OpenAIError> {
+        match &self.backend {
+            Backend::Disabled => Ok(None),
+            Backend::Rules(rules) => Ok(Some(rules.scores(text))),
+            Backend::Api { .. } => match self.api_model(text) {
+                Some(model) => {
+                    let scores = llm.moderate(model, &[text.to_string()]).await?;
+                    Ok(Some(scores.into_iter().next().unwrap_or_default()))
+                }
+                // 空のテキスト（画像のみなど）は API を呼ばない
+                None => Ok(Some(BTreeMap::new())),
+            },
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! 料金の計算
+//!
+//! トークン使用量とモデルごとの単価から、1回の呼び出しの料金（USD）を計算する。
+//! 組み込みの単価は公開価格を元にした目安のため、変わった場合は `MODEL_PRICES` で上書きする。
+
+use std::str::FromStr;
+
+use crate::models::Usage;
+
+/// モデルの単価（100万トークンあたりの USD）
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Price {
+    /// 入力トークン（キャッシュから読んだものを除く）
+    pub input: f64,
+    /// キャッシュから読んだ入力トークン
+    pub cached_input: f64,
+    /// 出力トークン（推論を除く）
+    pub output: f64,
+    /// 推論トークン
+    pub reasoning: f64,
+}
+
+impl Price {
+    /// 推論トークンを出力トークンと同じ単価にする
+    pub const fn new(input: f64, cached_input: f64, output: f64) -> Self {
+        Self {
+            input,
+            cached_input,
+            output,
+            reasoning: output,
+        }
+    }
+
+    /// 使用量の料金（USD）
+    ///
+    /// `prompt_tokens` はキャッシュから読んだ分を、`completion_tokens` は推論の分を含む。
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cached = usage.cached_tokens.min(usage.prompt_tokens);
+        let reasoning = usage.reasoning_tokens.min(usage.completion_tokens);
+
+        let total = f64::from(usage.prompt_tokens - cached) * self.input
+            + f64::from(cached) * self.cached_input
+            + f64::from(usage.completion_tokens - reasoning) * self.output
+            + f64::from(reasoning) * self.reasoning;
+
+        total / 1_000_000.0
+    }
+}
+
+/// 組み込みの単価（一致する接頭辞のうち最も長いものを使う）
+///
+/// 接頭辞が同じ別料金のモデル（`o3` と `o3-mini` など）は、それぞれの接頭辞を並べる。
+/// キャッシュの割引がないモデルは、キャッシュの単価を入力と同じにする。
+const BUILTIN_PRICES: &[(&str, Price)] = &[
+    ("gpt-5.2-pro", Price::new(21.0, 21.0, 168.0)),
+    ("gpt-5.2", Price::new(1.75, 0.175, 14.0)),
+    ("gpt-5-pro", Price::new(15.0, 15.0, 120.0)),
+    ("gpt-5-mini", Price::new(0.25, 0.025, 2.0)),
+    ("gpt-5-nano", Price::new(0.05, 0.005, 0.4)),
+    ("gpt-5", Price::new(1.25, 0.125, 10.0)),
+    ("gpt-4.1-mini", Price::new(0.4, 0.1, 1.6)),
+    ("gpt-4.1-nano", Price::new(0.1, 0.025, 0.4)),
+    ("gpt-4.1", Price::new(2.0, 0.5, 8.0)),
+    ("gpt-4o-mini", Price::new(0.15, 0.075, 0.6)),
+    ("gpt-4o", Price::new(2.5, 1.25, 10.0)),
+    ("o4-mini-deep-research", Price::new(2.0, 0.5, 8.0)),
+    ("o4-mini", Price::new(1.1, 0.275, 4.4)),
+    ("o3-deep-research", Price::new(10.0, 2.5, 40.0)),
+    ("o3-mini", Price::new(1.1, 0.55, 4.4)),
+    ("o3-pro", Price::new(20.0, 20.0, 80.0)),
+    ("o3", Price::new(2.0, 0.5, 8.0)),
+    // 埋め込み（入力のみ）
+    ("text-embedding-3-small", Price::new(0.02, 0.02, 0.0)),
+    ("text-embedding-3-large", Price::new(0.13, 0.13, 0.0)),
+    ("text-embedding-ada-002", Price::new(0.1, 0.1, 0.0)),
+    // Moderations API は無料
+    ("omni-moderation", Price::new(0.0, 0.0, 0.0)),
+    ("text-moderation", Price::new(0.0, 0.0, 0.0)),
+];
+
+/// モデルごとの単価表
+///
+/// 設定で追加した単価を組み込みの単価より優先する。
+/// どちらもスナップショット名（`gpt-4o-2024-08-06` など）は前方一致で判定し、長い接頭辞を優先する。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PriceTable {
+    overrides: Vec<(String, Price)>,
+}
+
+impl PriceTable {
+    /// 組み込みの単価のみの単価表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// モデル（または接頭辞）の単価を設定
+    pub fn with(mut self, model: &str, price: Price) -> Self {
+        self.overrides.retain(|(m, _)| m != model);
+        self.overrides.push((model.to_string(), price));
+        self
+    }
+
+    /// モデルの単価（不明なモデルは None）
+    pub fn price(&self, model: &str) -> Option<Price> {
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|(prefix, price)| (prefix.as_str(), *price));
+
+        longest_prefix(overrides, model)
+            .or_else(|| longest_prefix(BUILTIN_PRICES.iter().copied(), model))
+    }
+
+    /// 使用量の料金（USD、単価が不明なモデルは None）
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
+
+/// モデルに一致する接頭辞のうち最も長いものの単価
+fn longest_prefix<'a>(
+    prices: impl Iterator<Item = (&'a str, Price)>,
+    model: &str,
+) -> Option<Price> {
+    prices
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| price)
+}
+
+impl FromStr for PriceTable {
+    type Err = String;
+
+    /// `"model=input:cached_input:output[:reasoning],..."`（100万トークンあたりの USD）をパース
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = Self::new();
+
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || {
+                format!(
+                    "Invalid model price '{}' (expected: model=input:cached_input:output[:reasoning])",
+                    entry
+                )
+            };
+            let (model, prices) = entry.split_once('=').ok_or_else(invalid)?;
+            let prices = prices
+                .split(':')
+                .map(|p| p.trim().parse::<f64>().ok().filter(|p| *p >= 0.0))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?;
+
+            let price = match prices[..] {
+                [input, cached_input, output] => Price::new(input, cached_input, output),
+                [input, cached_input, output, reasoning] => Price {
+                    input,
+                    cached_input,
+                    output,
+                    reasoning,
+                },
+                _ => return Err(invalid()),
+            };
+            table = table.with(model.trim(), price);
+        }
+
+        Ok(table)
+    }
+}
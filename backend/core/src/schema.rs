@@ -0,0 +1,126 @@
+//! 構造化出力用の JSON Schema
+//!
+//! Rust の型から Structured Outputs（`text.format` の `json_schema`）に渡すスキーマを作る。
+//! スキーマは `#[derive(JsonSchema)]`（schemars）で serde の属性（`rename` など）を含めて型から導出し、
+//! Strict モードの制約に合わせて変換する。
+//!
+//! ```ignore
+//! #[derive(Deserialize, JsonSchema)]
+//! #[serde(rename_all = "lowercase")]
+//! enum Label {
+//!     Positive,
+//!     Negative,
+//!     Neutral,
+//! }
+//!
+//! #[derive(Deserialize, JsonSchema)]
+//! struct Sentiment {
+//!     label: Label,
+//!     score: f64,
+//!     reason: Option<String>,
+//! }
+//!
+//! let schema = strict_schema::<Sentiment>();
+//! ```
+
+use schemars::generate::SchemaSettings;
+use serde_json::Value;
+
+/// JSON Schema を導出できる型（`#[derive(JsonSchema)]`）
+pub use schemars::JsonSchema;
+
+/// Strict モードで使える `format` の値（これ以外は数値の `int64` などのため取り除く）
+const STRICT_FORMATS: &[&str] = &[
+    "date-time",
+    "time",
+    "date",
+    "duration",
+    "email",
+    "hostname",
+    "ipv4",
+    "ipv6",
+    "uuid",
+];
+
+/// 型 `T` のスキーマを作成（Strict モードで使える形式）
+///
+/// 部分スキーマは展開し（再帰する型のみ `$defs` を参照する）、次のように変換する。
+/// - オブジェクトは全プロパティを必須にして追加プロパティを禁止する
+///   （省略可能なフィールドは `Option<T>` の null で表す）
+/// - `oneOf` は `anyOf` に、`const` は値が1つの `enum` にする（タグ付きの enum）
+/// - `$schema`・`title`・`default` と、対応していない `format` は取り除く
+pub fn strict_schema<T: JsonSchema>() -> Value {
+    let settings = SchemaSettings::draft2020_12().with(|s| {
+        s.meta_schema = None;
+        s.inline_subschemas = true;
+    });
+    let mut schema = settings
+        .into_generator()
+        .into_root_schema_for::<T>()
+        .to_value();
+    make_strict(&mut schema);
+    schema
+}
+
+/// スキーマ名（`text.format.name`。英数字・`_`・`-` のみ、64文字まで）
+///
+/// schemars の型名（`Vec<T>` なら `Array_of_T`）を使う。
+pub fn schema_name<T: JsonSchema>() -> String {
+    T::schema_name()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(64)
+        .collect()
+}
+
+/// スキーマを Strict モードの制約に合わせて変換する（部分スキーマも再帰的に変換）
+fn make_strict(schema: &mut Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+
+    object.remove("$schema");
+    object.remove("title");
+    object.remove("default");
+    if object
+        .get("format")
+        .and_then(Value::as_str)
+        .is_some_and(|format| !STRICT_FORMATS.contains(&format))
+    {
+        object.remove("format");
+    }
+    if let Some(variants) = object.remove("oneOf") {
+        object.insert("anyOf".to_string(), variants);
+    }
+    if let Some(value) = object.remove("const") {
+        object.insert("enum".to_string(), Value::Array(vec![value]));
+    }
+
+    if let Some(properties) = object.get("properties").and_then(Value::as_object) {
+        let required: Vec<Value> = properties.keys().cloned().map(Value::String).collect();
+        object.insert("required".to_string(), Value::Array(required));
+        object.insert("additionalProperties".to_string(), Value::Bool(false));
+    }
+
+    // 値がスキーマのキーワード（プロパティ名の対応表を含む）のみ辿る
+    for (keyword, value) in object.iter_mut() {
+        match keyword.as_str() {
+            "properties" | "$defs" => {
+                for subschema in value
+                    .as_object_mut()
+                    .into_iter()
+                    .flat_map(|m| m.values_mut())
+                {
+                    make_strict(subschema);
+                }
+            }
+            "anyOf" | "allOf" | "prefixItems" => {
+                for subschema in value.as_array_mut().into_iter().flatten() {
+                    make_strict(subschema);
+                }
+            }
+            "items" | "additionalProperties" => make_strict(value),
+            _ => {}
+        }
+    }
+}
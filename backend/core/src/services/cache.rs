@@ -0,0 +1,254 @@
+//! 応答キャッシュ
+//!
+//! 同じプロンプトを繰り返し送る評価・回帰テスト向けに、`LlmProvider` を包んで応答をキャッシュする。
+//! キーはモデル・instructions・入力・パラメーターの SHA-256 ハッシュ。
+//! キャッシュするのは通常の呼び出し（`chat_with_history`）のみで、ストリーミングは常に API を呼ぶ。
+//! ツールを実行するプロバイダー（副作用を飛ばさないため）と、`previous_response_id` を指定した呼び出し
+//! （サーバー側の会話の続きのため）もキャッシュせずに API を呼ぶ。
+//! キャッシュから返した応答は API を呼んでいないため、使用量を 0 にする。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use hashlink::LruCache;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::info;
+
+use super::openai_error::OpenAIError;
+use super::provider::{ChatStream, LlmProvider};
+use crate::config::Config;
+use crate::db::PostgresCache;
+use crate::models::{ChatOptions, ChatResponse, Embeddings, Message, Usage};
+
+/// キャッシュの有効期間のデフォルト
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// メモリキャッシュの最大件数のデフォルト
+pub const DEFAULT_CACHE_CAPACITY: usize = 1000;
+
+/// キャッシュの保存先（`RESPONSE_CACHE`）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheKind {
+    /// キャッシュしない
+    #[default]
+    Off,
+    /// プロセス内の LRU キャッシュ
+    Memory,
+    /// PostgreSQL の `response_cache` テーブル（プロセス間で共有する）
+    Postgres,
+}
+
+impl FromStr for CacheKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(CacheKind::Off),
+            "memory" => Ok(CacheKind::Memory),
+            "postgres" => Ok(CacheKind::Postgres),
+            _ => Err(format!(
+                "Unknown response cache '{}' (expected: off, memory, postgres)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CacheKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheKind::Off => write!(f, "off"),
+            CacheKind::Memory => write!(f, "memory"),
+            CacheKind::Postgres => write!(f, "postgres"),
+        }
+    }
+}
+
+/// 応答の保存先
+///
+/// 保存先のエラーは呼び出しを失敗させないよう、実装側でログに記録してキャッシュなしとして扱う。
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// 保存先の名前（ログ用）
+    fn name(&self) -> &'static str;
+
+    /// 有効期間内の応答を取得
+    async fn get(&self, key: &str) -> Option<ChatResponse>;
+
+    /// 応答を `ttl` の間保存する（既にあれば置き換える）
+    async fn put(&self, key: &str, response: &ChatResponse, ttl: Duration);
+}
+
+/// プロセス内の LRU キャッシュ（最大件数を超えると最も古く使われたものから捨てる）
+pub struct MemoryCache {
+    entries: Mutex<LruCache<String, (Instant, ChatResponse)>>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity.max(1))),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCache {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get(&self, key: &str) -> Option<ChatResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((expires_at, response)) if Instant::now() < *expires_at => Some(response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn put(&self, key: &str, response: &ChatResponse, ttl: Duration) {
+        let expires_at = Instant::now() + ttl;
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (expires_at, response.clone()));
+    }
+}
+
+/// 応答をキャッシュするプロバイダー
+///
+/// `ChatOptions::bypass_cache` を指定した呼び出しはキャッシュを読まずに API を呼び、結果で置き換える。
+/// キャッシュから返した応答は `ChatResponse::cached` が true になる。
+pub struct CachedProvider {
+    inner: Arc<dyn LlmProvider>,
+    store: Arc<dyn CacheStore>,
+    ttl: Duration,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, store: Arc<dyn CacheStore>, ttl: Duration) -> Self {
+        Self { inner, store, ttl }
+    }
+
+    /// キャッシュのキー（モデル・instructions・入力・パラメーターの SHA-256）
+    ///
+    /// モデル未指定の場合はデフォルトのモデルで計算する。
+    pub fn cache_key(&self, messages: &[Message], options: &ChatOptions) -> String {
+        let model = options
+            .model
+            .as_deref()
+            .unwrap_or(self.inner.default_model());
+        let key = serde_json::json!({
+            "model": model,
+            "instructions": options.instructions,
+            "input": messages,
+            "response_format": options.response_format,
+            "reasoning": options.reasoning,
+            "previous_response_id": options.previous_response_id,
+            "generation": options.generation,
+        });
+
+        format!("{:x}", Sha256::digest(key.to_string().as_bytes()))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for CachedProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn allowed_models(&self) -> &[String] {
+        self.inner.allowed_models()
+    }
+
+    fn supports_previous_response_id(&self) -> bool {
+        self.inner.supports_previous_response_id()
+    }
+
+    fn supports_prompt_cache_key(&self) -> bool {
+        self.inner.supports_prompt_cache_key()
+    }
+
+    fn has_tools(&self) -> bool {
+        self.inner.has_tools()
+    }
+
+    async fn chat_with_history(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatResponse, OpenAIError> {
+        if self.inner.has_tools() || options.previous_response_id.is_some() {
+            return self.inner.chat_with_history(messages, options).await;
+        }
+
+        let key = self.cache_key(&messages, &options);
+
+        if options.bypass_cache {
+            info!("Response cache bypassed: {}", &key[..12]);
+        } else if let Some(response) = self.store.get(&key).await {
+            info!("Response cache hit ({}): {}", self.store.name(), &key[..12]);
+            return Ok(ChatResponse {
+                usage: Usage::default(),
+                cached: true,
+                ..response
+            });
+        } else {
+            info!("Response cache miss ({}): {}", self.store.name(), &key[..12]);
+        }
+
+        let response = self.inner.chat_with_history(messages, options).await?;
+        self.store.put(&key, &response, self.ttl).await;
+
+        Ok(response)
+    }
+
+    async fn chat_with_history_stream(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError> {
+        self.inner.chat_with_history_stream(messages, options).await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        self.inner.embed(model, inputs).await
+    }
+
+    async fn moderate(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        self.inner.moderate(model, inputs).await
+    }
+}
+
+/// 設定（`RESPONSE_CACHE`）に応じてプロバイダーをキャッシュで包む（`off` ならそのまま返す）
+pub fn from_config(
+    llm: Arc<dyn LlmProvider>,
+    config: &Config,
+    pool: PgPool,
+) -> Arc<dyn LlmProvider> {
+    let store: Arc<dyn CacheStore> = match config.response_cache {
+        CacheKind::Off => return llm,
+        CacheKind::Memory => Arc::new(MemoryCache::new(config.response_cache_capacity)),
+        CacheKind::Postgres => Arc::new(PostgresCache::new(pool)),
+    };
+
+    Arc::new(CachedProvider::new(llm, store, config.response_cache_ttl))
+}
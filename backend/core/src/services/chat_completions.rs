@@ -0,0 +1,285 @@
+//! OpenAI Chat Completions 互換 API のプロバイダー
+//!
+//! Responses API に対応していないローカルサーバー（vLLM、Ollama、llama.cpp など）向け。
+//! ツール呼び出し・推論の要約・`previous_response_id` には対応していない。
+//! Responses API にしかない生成オプション（`truncation`、`store` など）と
+//! `prompt_cache_key` は、黙って捨てずに `InvalidRequest` として返す。
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use eventsource_stream::Eventsource;
+use futures::StreamExt;
+
+use super::client::{impl_provider_builders, ProviderSettings};
+use super::openai_error::OpenAIError;
+use super::provider::{ChatStream, LlmProvider};
+use crate::config::Config;
+use crate::models::completions::{
+    CompletionsChunk, CompletionsMessage, CompletionsRequest, CompletionsResponse,
+    CompletionsUsage, StreamOptions,
+};
+use crate::models::{
+    ChatOptions, ChatResponse, ChatStreamEvent, Embeddings, Message, MessageContent,
+    OpenAIErrorResponse, Usage,
+};
+
+/// ストリームの終了を表す `data`
+const DONE: &str = "[DONE]";
+
+/// OpenAI Chat Completions 互換 API クライアント
+#[derive(Clone)]
+pub struct ChatCompletionsService {
+    /// HTTP クライアントとモデルの設定（ベースURLに `/chat/completions` を付けてエンドポイントにする）
+    settings: ProviderSettings,
+}
+
+impl_provider_builders!(ChatCompletionsService, "chat/completions");
+
+impl ChatCompletionsService {
+    /// 新しいクライアントを作成
+    pub fn new(api_key: String) -> Self {
+        Self {
+            settings: ProviderSettings::new(api_key),
+        }
+    }
+
+    /// 設定からクライアントを作成
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            settings: ProviderSettings::from_config(config),
+        }
+    }
+
+    /// Chat Completions API リクエストを構築
+    ///
+    /// instructions は先頭の system メッセージとして送る。
+    /// Chat Completions API で表せないオプションは無視せず `InvalidRequest` を返す。
+    fn build_request(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+        stream: bool,
+    ) -> Result<CompletionsRequest, OpenAIError> {
+        Self::check_options(&options)?;
+
+        let system = options.instructions.map(|instructions| Message {
+            role: "system".to_string(),
+            content: MessageContent::Text(instructions),
+        });
+
+        Ok(CompletionsRequest {
+            model: options
+                .model
+                .unwrap_or_else(|| self.settings.default_model.clone()),
+            messages: system
+                .into_iter()
+                .chain(messages)
+                .map(CompletionsMessage::from)
+                .collect(),
+            response_format: options.response_format.map(Into::into),
+            reasoning_effort: options.reasoning.and_then(|reasoning| reasoning.effort),
+            temperature: options.generation.temperature,
+            top_p: options.generation.top_p,
+            max_tokens: options.generation.max_output_tokens,
+            stream: stream.then_some(true),
+            stream_options: stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+        })
+    }
+
+    /// Responses API にしかないオプションが指定されていればエラーにする
+    fn check_options(options: &ChatOptions) -> Result<(), OpenAIError> {
+        let generation = &options.generation;
+        let unsupported = [
+            ("truncation", generation.truncation.is_some()),
+            (
+                "parallel_tool_calls",
+                generation.parallel_tool_calls.is_some(),
+            ),
+            ("store", generation.store.is_some()),
+            ("metadata", generation.metadata.is_some()),
+            ("verbosity", generation.verbosity.is_some()),
+            ("prompt_cache_key", options.prompt_cache_key.is_some()),
+        ];
+
+        match unsupported.into_iter().find(|(_, specified)| *specified) {
+            Some((param, _)) => Err(OpenAIError::InvalidRequest {
+                message: format!(
+                    "'{}' is not supported by the Chat Completions provider",
+                    param
+                ),
+                param: Some(param.to_string()),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Chat Completions API のレスポンスをクライアント向けに変換
+    fn to_chat_response(response: CompletionsResponse) -> ChatResponse {
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message);
+        let (text, refusal) = message
+            .map(|message| (message.content.unwrap_or_default(), message.refusal))
+            .unwrap_or_default();
+
+        Self::build_response(
+            text,
+            refusal,
+            response.model,
+            response.id,
+            response.usage.unwrap_or_default(),
+        )
+    }
+
+    fn build_response(
+        text: String,
+        refusal: Option<String>,
+        model: String,
+        response_id: String,
+        usage: CompletionsUsage,
+    ) -> ChatResponse {
+        ChatResponse {
+            response: text,
+            model,
+            response_id,
+            usage: Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+                reasoning_tokens: usage
+                    .completion_tokens_details
+                    .map_or(0, |details| details.reasoning_tokens),
+                cached_tokens: usage
+                    .prompt_tokens_details
+                    .map_or(0, |details| details.cached_tokens),
+            },
+            parsed: None,
+            refused: refusal.is_some(),
+            refusal,
+            citations: Vec::new(),
+            reasoning_summary: Vec::new(),
+            cached: false,
+            cost_usd: None,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ChatCompletionsService {
+    fn name(&self) -> &'static str {
+        "chat_completions"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.settings.default_model
+    }
+
+    fn allowed_models(&self) -> &[String] {
+        &self.settings.allowed_models
+    }
+
+    fn supports_prompt_cache_key(&self) -> bool {
+        false
+    }
+
+    async fn chat_with_history(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatResponse, OpenAIError> {
+        let request = self.build_request(messages, options, false)?;
+        let response = self.settings.api.post("chat/completions", &request).await?;
+        let response: CompletionsResponse = response.json().await?;
+
+        Ok(Self::to_chat_response(response))
+    }
+
+    /// OpenAI 互換の `/embeddings` を呼び出す
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        self.settings.api.embed(model, inputs).await
+    }
+
+    /// OpenAI 互換の `/moderations` を呼び出す
+    async fn moderate(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        self.settings.api.moderate(model, inputs).await
+    }
+
+    /// ステータスコードのエラーはストリーム開始前に返す
+    async fn chat_with_history_stream(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError> {
+        let request = self.build_request(messages, options, true)?;
+        let response = self.settings.api.post("chat/completions", &request).await?;
+
+        let stream = async_stream::try_stream! {
+            let mut events = response.bytes_stream().eventsource();
+            let mut text = String::new();
+            let mut refusal: Option<String> = None;
+            let mut id = String::new();
+            let mut model = String::new();
+            let mut usage = None;
+            let mut done = false;
+
+            while let Some(event) = events.next().await {
+                let event = event.map_err(|e| OpenAIError::StreamError(e.to_string()))?;
+                if event.data == DONE {
+                    done = true;
+                    break;
+                }
+
+                // ストリーム中のエラーはエラーエンベロープで送られてくる
+                if let Ok(envelope) = serde_json::from_str::<OpenAIErrorResponse>(&event.data) {
+                    Err(OpenAIError::from_detail(None, envelope.error, None))?;
+                }
+
+                let chunk: CompletionsChunk = serde_json::from_str(&event.data)
+                    .map_err(|e| OpenAIError::StreamError(e.to_string()))?;
+                if !chunk.id.is_empty() {
+                    id = chunk.id;
+                }
+                if !chunk.model.is_empty() {
+                    model = chunk.model;
+                }
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+
+                for choice in chunk.choices {
+                    if let Some(delta) = choice.delta.refusal {
+                        refusal.get_or_insert_with(String::new).push_str(&delta);
+                    }
+                    if let Some(delta) = choice.delta.content.filter(|d| !d.is_empty()) {
+                        text.push_str(&delta);
+                        yield ChatStreamEvent::Delta { text: delta };
+                    }
+                }
+            }
+
+            // [DONE] を受け取る前に接続が閉じられた
+            if !done {
+                Err(OpenAIError::StreamError("stream ended before [DONE]".to_string()))?;
+            }
+
+            yield ChatStreamEvent::Completed(Self::build_response(
+                text,
+                refusal,
+                model,
+                id,
+                usage.unwrap_or_default(),
+            ));
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
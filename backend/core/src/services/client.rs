@@ -0,0 +1,299 @@
+//! OpenAI 互換 API の HTTP クライアント
+//!
+//! 認証・追加ヘッダー・リトライ・エラーの分類を各プロバイダーで共有する。
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use tracing::warn;
+
+use super::openai::{DEFAULT_BASE_URL, DEFAULT_MODEL};
+use super::openai_error::OpenAIError;
+use super::provider;
+use super::retry::{self, RetryPolicy};
+use crate::config::Config;
+use crate::models::embeddings::{Embeddings, EmbeddingsRequest, EmbeddingsResponse};
+use crate::models::Usage;
+use crate::models::moderation::{ModerationRequest, ModerationResponse};
+
+/// 接続のタイムアウト
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 読み取りのタイムアウト（レスポンスの各読み取りの間隔。ストリーミングでは差分の間隔）
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// OpenAI 互換 API の HTTP クライアント
+#[derive(Clone)]
+pub(crate) struct ApiClient {
+    client: Client,
+    /// APIキー（空の場合は Authorization を付けない）
+    pub(crate) api_key: String,
+    /// API のベースURL（エンドポイントのパスを付けて呼び出す）
+    pub(crate) base_url: String,
+    /// 全リクエストに付与する追加ヘッダー
+    pub(crate) headers: HeaderMap,
+    /// 一時的な失敗のリトライポリシー
+    pub(crate) retry_policy: RetryPolicy,
+}
+
+impl ApiClient {
+    pub(crate) fn new(api_key: String, base_url: &str) -> Self {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            client,
+            api_key,
+            base_url: base_url.to_string(),
+            headers: HeaderMap::new(),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// ベースURLを設定（末尾の `/` は除く）
+    pub(crate) fn set_base_url(&mut self, base_url: &str) {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+    }
+
+    /// `{base_url}/{path}` に POST し、ステータスコードをチェック
+    ///
+    /// サーバーが処理していないと分かる失敗（接続できなかった、429、`Retry-After` 付きの 503）だけを
+    /// リトライポリシーに従って再試行する。読み取りのタイムアウトやその他の 5xx は二重の生成を避けるため再試行しない。
+    /// 各試行はリトライポリシーの制限時間の残りで打ち切り、`OpenAIError::Timeout` とする。
+    /// エラーレスポンスは `OpenAIError` の各種別に分類して返す。
+    /// ストリーミングの場合も再試行するのはストリーム開始前まで。
+    pub(crate) async fn post<B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        let policy = &self.retry_policy;
+        let started = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            // 制限時間の残りで打ち切る（打ち切った場合は再試行しても間に合わない）
+            let sent = match policy.deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_sub(started.elapsed());
+                    match tokio::time::timeout(remaining, self.post_once(path, body)).await {
+                        Ok(sent) => sent,
+                        Err(_) => {
+                            warn!("OpenAI request timed out (deadline {:?})", deadline);
+                            return Err(OpenAIError::Timeout);
+                        }
+                    }
+                }
+                None => self.post_once(path, body).await,
+            };
+
+            let (error, server_hint, retryable) = match sent {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let server_hint = retry::server_retry_delay(response.headers());
+                    let retryable = retry::is_retryable_status(status, response.headers());
+                    let error_text = response.text().await.unwrap_or_default();
+                    let error = OpenAIError::from_response(status, &error_text, server_hint);
+                    // 429 でもクォータ超過は待っても回復しない
+                    let retryable = retryable && error.is_retryable();
+                    (error, server_hint, retryable)
+                }
+                // 接続のタイムアウトはリクエストが届いていないため再試行できる
+                Err(e) if e.is_timeout() => {
+                    let retryable = retry::is_retryable_error(&e);
+                    (OpenAIError::Timeout, None, retryable)
+                }
+                Err(e) => {
+                    let retryable = retry::is_retryable_error(&e);
+                    (OpenAIError::RequestError(e), None, retryable)
+                }
+            };
+
+            if !retryable {
+                return Err(error);
+            }
+
+            if attempt >= policy.max_attempts {
+                return Err(error);
+            }
+
+            let delay = policy.delay(attempt, server_hint);
+            if let Some(deadline) = policy.deadline
+                && started.elapsed() + delay > deadline
+            {
+                warn!(
+                    "OpenAI request failed; retry would exceed deadline ({:?}): {}",
+                    deadline, error
+                );
+                return Err(error);
+            }
+
+            warn!(
+                "OpenAI request failed (attempt {}/{}), retrying in {:?}: {}",
+                attempt, policy.max_attempts, delay, error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Embeddings API を呼び出し、入力と同じ順番でベクトルを返す（トークン使用量付き）
+    pub(crate) async fn embed(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Embeddings, OpenAIError> {
+        let request = EmbeddingsRequest {
+            model,
+            input: inputs,
+        };
+        let response = self.post("embeddings", &request).await?;
+        let mut response: EmbeddingsResponse = response.json().await?;
+
+        response.data.sort_by_key(|data| data.index);
+        Ok(Embeddings {
+            vectors: response
+                .data
+                .into_iter()
+                .map(|data| data.embedding)
+                .collect(),
+            usage: Usage {
+                prompt_tokens: response.usage.prompt_tokens,
+                total_tokens: response.usage.total_tokens,
+                ..Usage::default()
+            },
+        })
+    }
+
+    /// Moderations API を呼び出し、入力と同じ順番でカテゴリごとのスコアを返す
+    pub(crate) async fn moderate(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        let request = ModerationRequest {
+            model,
+            input: inputs,
+        };
+        let response = self.post("moderations", &request).await?;
+        let response: ModerationResponse = response.json().await?;
+
+        Ok(response
+            .results
+            .into_iter()
+            .map(|result| result.category_scores)
+            .collect())
+    }
+
+    /// API を1回呼び出す
+    async fn post_once<B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut builder = self
+            .client
+            .post(format!("{}/{}", self.base_url, path))
+            .headers(self.headers.clone());
+
+        // APIキー不要のローカルサーバー向けに、空の場合は Authorization を付けない
+        if !self.api_key.is_empty() {
+            builder = builder.bearer_auth(&self.api_key);
+        }
+
+        builder.json(body).send().await
+    }
+}
+
+/// OpenAI 互換 API のプロバイダーに共通の設定
+#[derive(Clone)]
+pub(crate) struct ProviderSettings {
+    /// HTTP クライアント（ベースURLにエンドポイントのパスを付けて呼び出す）
+    pub(crate) api: ApiClient,
+    /// モデル未指定時に使用するモデル
+    pub(crate) default_model: String,
+    /// 指定を許可するモデル（`resolve_model` で検証）
+    pub(crate) allowed_models: Vec<String>,
+}
+
+impl ProviderSettings {
+    pub(crate) fn new(api_key: String) -> Self {
+        Self {
+            api: ApiClient::new(api_key, DEFAULT_BASE_URL),
+            default_model: DEFAULT_MODEL.to_string(),
+            allowed_models: vec![DEFAULT_MODEL.to_string()],
+        }
+    }
+
+    /// 設定（`OPENAI_*`）から作成
+    pub(crate) fn from_config(config: &Config) -> Self {
+        let mut settings = Self::new(config.openai_api_key.clone());
+        settings.api.set_base_url(&config.openai_base_url);
+        settings.api.headers = config.openai_extra_headers.clone();
+        settings.api.retry_policy = config.openai_retry_policy.clone();
+        settings.set_models(
+            config.openai_model.clone(),
+            config.openai_allowed_models.clone(),
+        );
+        settings
+    }
+
+    /// デフォルトモデルと許可リストを設定（デフォルトモデルは常に許可する）
+    pub(crate) fn set_models(&mut self, default_model: String, allowed_models: Vec<String>) {
+        self.allowed_models = provider::with_default_model(&default_model, allowed_models);
+        self.default_model = default_model;
+    }
+}
+
+/// `settings: ProviderSettings` を持つプロバイダーに共通のビルダーを実装する
+///
+/// `$path` はチャットのエンドポイントのパス（`with_base_url` の説明に使う）。
+macro_rules! impl_provider_builders {
+    ($provider:ty, $path:literal) => {
+        impl $provider {
+            /// ベースURLを設定（Azure OpenAI、OpenAI互換サーバー、テスト用モックなど）
+            ///
+            #[doc = concat!("例: `http://localhost:8000/v1` → `http://localhost:8000/v1/", $path, "` を呼び出す。")]
+            pub fn with_base_url(mut self, base_url: String) -> Self {
+                self.settings.api.set_base_url(&base_url);
+                self
+            }
+
+            /// 全リクエストに付与する追加ヘッダーを設定（Azure の `api-key` など）
+            pub fn with_headers(mut self, headers: reqwest::header::HeaderMap) -> Self {
+                self.settings.api.headers = headers;
+                self
+            }
+
+            /// リトライポリシーを設定
+            pub fn with_retry_policy(
+                mut self,
+                retry_policy: $crate::services::RetryPolicy,
+            ) -> Self {
+                self.settings.api.retry_policy = retry_policy;
+                self
+            }
+
+            /// デフォルトモデルと許可リストを設定
+            ///
+            /// デフォルトモデルは許可リストに含まれていなくても常に許可する。
+            pub fn with_models(
+                mut self,
+                default_model: String,
+                allowed_models: Vec<String>,
+            ) -> Self {
+                self.settings.set_models(default_model, allowed_models);
+                self
+            }
+        }
+    };
+}
+
+pub(crate) use impl_provider_builders;
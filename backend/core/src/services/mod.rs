@@ -1,5 +1,16 @@
 // ビジネスロジック層
 
+pub mod cache;
+pub mod chat_completions;
+mod client;
 pub mod openai;
+pub mod openai_error;
+pub mod provider;
+pub mod retry;
 
-pub use openai::{OpenAIError, OpenAIService};
+pub use cache::{CacheKind, CacheStore, CachedProvider, MemoryCache};
+pub use chat_completions::ChatCompletionsService;
+pub use openai::{OpenAIService, DEFAULT_BASE_URL, DEFAULT_MAX_TOOL_STEPS, DEFAULT_MODEL};
+pub use openai_error::OpenAIError;
+pub use provider::{ChatStream, LlmProvider, LlmProviderExt, ProviderKind};
+pub use retry::RetryPolicy;
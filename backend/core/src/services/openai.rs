@@ -1,109 +1,418 @@
-use reqwest::Client;
-use thiserror::Error;
+use std::collections::BTreeMap;
 
-use crate::models::{ChatRequest, ChatResponse, Message, OpenAIRequest, OpenAIResponse, Usage};
+use async_trait::async_trait;
+use eventsource_stream::Eventsource;
+use futures::future::join_all;
+use futures::StreamExt;
 
-/// OpenAI Responses API のエンドポイント
-const OPENAI_API_URL: &str = "https://api.openai.com/v1/responses";
-/// 使用するモデル（GPT-5.2 Instant）
-const MODEL: &str = "gpt-5.2-chat-latest";
+use super::client::{impl_provider_builders, ProviderSettings};
+use super::openai_error::OpenAIError;
+use super::provider::{ChatStream, LlmProvider};
+use crate::config::Config;
+use crate::models::{
+    Annotation, ChatOptions, ChatResponse, ChatStreamEvent, Embeddings, InputItem, Message,
+    OpenAIRequest, OpenAIResponse, OpenAIStreamEvent, OpenAIUsage, TextOptions, Usage,
+};
+use crate::tools::ToolRegistry;
 
-/// OpenAI サービスのエラー型
-#[derive(Error, Debug)]
-pub enum OpenAIError {
-    #[error("HTTP request failed: {0}")]
-    RequestError(#[from] reqwest::Error),
+/// OpenAI API のデフォルトのベースURL
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+/// デフォルトのモデル（GPT-5.2 Instant）
+pub const DEFAULT_MODEL: &str = "gpt-5.2-chat-latest";
+/// ツール呼び出しループの最大ステップ数のデフォルト
+pub const DEFAULT_MAX_TOOL_STEPS: usize = 8;
+/// 複数のメッセージアイテムを連結するときの区切り
+const MESSAGE_SEPARATOR: &str = "\n\n";
 
-    #[error("OpenAI API error: {0}")]
-    ApiError(String),
-}
-
-/// OpenAI API クライアント
+/// OpenAI Responses API クライアント
 #[derive(Clone)]
 pub struct OpenAIService {
-    client: Client,
-    api_key: String,
+    /// HTTP クライアントとモデルの設定（ベースURLに `/responses` を付けてエンドポイントにする）
+    settings: ProviderSettings,
+    /// モデルが呼び出せるツール
+    tools: ToolRegistry,
+    /// 1回のチャットでツールを実行できる最大ステップ数
+    max_tool_steps: usize,
 }
 
+impl_provider_builders!(OpenAIService, "responses");
+
 impl OpenAIService {
     /// 新しいクライアントを作成
     pub fn new(api_key: String) -> Self {
+        Self::with_settings(ProviderSettings::new(api_key))
+    }
+
+    /// 設定からクライアントを作成
+    pub fn from_config(config: &Config) -> Self {
+        Self::with_settings(ProviderSettings::from_config(config))
+            .with_max_tool_steps(config.openai_max_tool_steps)
+    }
+
+    fn with_settings(settings: ProviderSettings) -> Self {
         Self {
-            client: Client::new(),
-            api_key,
+            settings,
+            tools: ToolRegistry::new(),
+            max_tool_steps: DEFAULT_MAX_TOOL_STEPS,
         }
     }
 
-    /// Responses API を呼び出す（単発チャット）
-    pub async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIError> {
-        let input = vec![Message {
-            role: "user".to_string(),
-            content: request.message,
-        }];
+    /// モデルが呼び出せるツールを設定
+    ///
+    /// ツールが登録されていれば、モデルの要求に応じて実行し、
+    /// 最終的な返答が得られるまで（最大 `max_tool_steps` 回）リクエストを繰り返す。
+    pub fn with_tools(mut self, tools: ToolRegistry) -> Self {
+        self.tools = tools;
+        self
+    }
 
-        self.call_responses_api(input, request.system_prompt).await
+    /// ツール呼び出しループの最大ステップ数を設定
+    pub fn with_max_tool_steps(mut self, max_tool_steps: usize) -> Self {
+        self.max_tool_steps = max_tool_steps;
+        self
     }
 
-    /// 履歴を含めた Responses API を呼び出す
-    pub async fn chat_with_history(
+    /// Embeddings API でテキストの埋め込みベクトル（入力と同じ順番）とトークン使用量を取得
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        self.settings.api.embed(model, inputs).await
+    }
+
+    /// Moderations API でテキストのカテゴリごとのスコアを取得（入力と同じ順番）
+    pub async fn moderate(
         &self,
-        messages: Vec<Message>,
-        instructions: Option<String>,
-    ) -> Result<ChatResponse, OpenAIError> {
-        self.call_responses_api(messages, instructions).await
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        self.settings.api.moderate(model, inputs).await
     }
 
     /// Responses API を呼び出す（内部メソッド）
     async fn call_responses_api(
         &self,
         input: Vec<Message>,
-        instructions: Option<String>,
+        options: ChatOptions,
     ) -> Result<ChatResponse, OpenAIError> {
-        // Responses API リクエストを構築
-        let openai_request = OpenAIRequest {
-            model: MODEL.to_string(),
-            input,
-            instructions,
-        };
+        let mut input: Vec<InputItem> = input.into_iter().map(InputItem::from).collect();
+        let mut steps = 0;
+        let mut usage = Usage::default();
+
+        loop {
+            let openai_request = self.build_request(input.clone(), &options, None);
+
+            let response = self.settings.api.post("responses", &openai_request).await?;
+
+            // レスポンスをパース
+            let openai_response: OpenAIResponse = response.json().await?;
+            Self::add_usage(&mut usage, &openai_response.usage);
 
-        // API を呼び出し
-        let response = self
-            .client
-            .post(OPENAI_API_URL)
-            .bearer_auth(&self.api_key)
-            .json(&openai_request)
-            .send()
-            .await?;
-
-        // ステータスコードをチェック
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(OpenAIError::ApiError(error_text));
+            // ツール呼び出しがあれば実行して再度リクエストする
+            if !self
+                .run_tool_calls(&mut input, &openai_response, &mut steps)
+                .await?
+            {
+                return Ok(ChatResponse {
+                    usage,
+                    ..Self::to_chat_response(openai_response)
+                });
+            }
         }
+    }
+
+    /// Responses API をストリーミングで呼び出す（内部メソッド）
+    ///
+    /// ステータスコードのエラーはストリーム開始前に返す（ツール実行後の再リクエストはストリーム中のエラー）。
+    async fn call_responses_api_stream(
+        &self,
+        input: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError> {
+        let mut input: Vec<InputItem> = input.into_iter().map(InputItem::from).collect();
+        let openai_request = self.build_request(input.clone(), &options, Some(true));
+
+        let mut response = self.settings.api.post("responses", &openai_request).await?;
+        let service = self.clone();
+        let stream = async_stream::try_stream! {
+            let mut steps = 0;
+            let mut usage = Usage::default();
 
-        // レスポンスをパース
-        let openai_response: OpenAIResponse = response.json().await?;
+            loop {
+                let mut events = response.bytes_stream().eventsource();
+                let mut completed = None;
 
-        // outputから"message"タイプのテキストを抽出
-        // 注意: "reasoning"（内部思考）は使用しない - "message"（公開出力）のみを使用
-        let response_text = openai_response
+                while let Some(event) = events.next().await {
+                    let event = event.map_err(|e| OpenAIError::StreamError(e.to_string()))?;
+                    let parsed: OpenAIStreamEvent = serde_json::from_str(&event.data)
+                        .map_err(|e| OpenAIError::StreamError(e.to_string()))?;
+
+                    match parsed {
+                        OpenAIStreamEvent::OutputTextDelta { delta } => {
+                            yield ChatStreamEvent::Delta { text: delta };
+                        }
+                        OpenAIStreamEvent::Completed { response } => {
+                            completed = Some(response);
+                            break;
+                        }
+                        OpenAIStreamEvent::Failed { response }
+                        | OpenAIStreamEvent::Incomplete { response } => {
+                            let error = match (response.error, response.incomplete_details) {
+                                (Some(detail), _) => OpenAIError::from_detail(None, detail, None),
+                                (None, Some(details)) => OpenAIError::ApiError(details.reason),
+                                (None, None) => {
+                                    OpenAIError::ApiError("response did not complete".to_string())
+                                }
+                            };
+                            Err(error)?;
+                        }
+                        OpenAIStreamEvent::Error { error } => {
+                            Err(OpenAIError::from_detail(None, error, None))?;
+                        }
+                        OpenAIStreamEvent::Other => {}
+                    }
+                }
+
+                // response.completed を受け取る前に接続が閉じられた
+                let completed = completed.ok_or_else(|| {
+                    OpenAIError::StreamError("stream ended before response.completed".to_string())
+                })?;
+                Self::add_usage(&mut usage, &completed.usage);
+
+                // ツール呼び出しがあれば実行し、続きを新しいストリームで受け取る
+                if !service.run_tool_calls(&mut input, &completed, &mut steps).await? {
+                    yield ChatStreamEvent::Completed(ChatResponse {
+                        usage,
+                        ..Self::to_chat_response(completed)
+                    });
+                    return;
+                }
+
+                let openai_request = service.build_request(input.clone(), &options, Some(true));
+                response = service.settings.api.post("responses", &openai_request).await?;
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+
+    /// レスポンスにツール呼び出しがあれば実行し、呼び出しと結果を `input` に追加
+    ///
+    /// ツールを実行した場合は true を返す（結果を付けて再度リクエストする）。
+    async fn run_tool_calls(
+        &self,
+        input: &mut Vec<InputItem>,
+        response: &OpenAIResponse,
+        steps: &mut usize,
+    ) -> Result<bool, OpenAIError> {
+        let calls: Vec<_> = response
             .output
             .iter()
-            .filter(|item| item.item_type == "message")
-            .find_map(|item| {
-                item.content.first().and_then(|c| c.text.clone())
+            .filter(|item| item.item_type == "function_call")
+            .map(|item| {
+                (
+                    item.call_id.clone().unwrap_or_default(),
+                    item.name.clone().unwrap_or_default(),
+                    item.arguments.clone().unwrap_or_default(),
+                )
             })
-            .unwrap_or_default();
+            .collect();
 
-        // レスポンスを変換
-        Ok(ChatResponse {
+        if calls.is_empty() {
+            return Ok(false);
+        }
+        if *steps >= self.max_tool_steps {
+            return Err(OpenAIError::ToolStepLimitExceeded(self.max_tool_steps));
+        }
+        *steps += 1;
+
+        // 同じステップの呼び出しは並行して実行する
+        let outputs = join_all(
+            calls
+                .iter()
+                .map(|(_, name, arguments)| self.tools.call(name, arguments)),
+        )
+        .await;
+
+        for ((call_id, name, arguments), output) in calls.into_iter().zip(outputs) {
+            input.push(InputItem::FunctionCall {
+                call_id: call_id.clone(),
+                name,
+                arguments,
+            });
+            input.push(InputItem::FunctionCallOutput { call_id, output });
+        }
+
+        Ok(true)
+    }
+
+    /// Responses API リクエストを構築
+    fn build_request(
+        &self,
+        input: Vec<InputItem>,
+        options: &ChatOptions,
+        stream: Option<bool>,
+    ) -> OpenAIRequest {
+        let generation = &options.generation;
+        let format = options.response_format.clone();
+        let verbosity = generation.verbosity;
+        let text =
+            (format.is_some() || verbosity.is_some()).then_some(TextOptions { format, verbosity });
+
+        OpenAIRequest {
+            model: options
+                .model
+                .clone()
+                .unwrap_or_else(|| self.settings.default_model.clone()),
+            input,
+            instructions: options.instructions.clone(),
+            tools: self.tools.definitions(),
+            text,
+            reasoning: options.reasoning.clone(),
+            previous_response_id: options.previous_response_id.clone(),
+            temperature: generation.temperature,
+            top_p: generation.top_p,
+            max_output_tokens: generation.max_output_tokens,
+            truncation: generation.truncation,
+            parallel_tool_calls: generation.parallel_tool_calls,
+            store: generation.store,
+            metadata: generation.metadata.clone(),
+            prompt_cache_key: options.prompt_cache_key.clone(),
+            stream,
+        }
+    }
+
+    /// Responses API のレスポンスをクライアント向けに変換
+    ///
+    /// 全メッセージの output_text を連結し、引用の位置を連結後のテキスト上の位置にずらす。
+    /// メッセージ内のパーツはそのまま、メッセージ同士は空行で区切る。
+    fn to_chat_response(openai_response: OpenAIResponse) -> ChatResponse {
+        let mut response_text = String::new();
+        let mut text_chars = 0;
+        let mut refusals = Vec::new();
+        let mut citations = Vec::new();
+
+        // 注意: "reasoning"（内部思考）は本文に含めない - 要約のみ reasoning_summary として返す
+        let messages = openai_response
+            .output
+            .iter()
+            .filter(|item| item.item_type == "message");
+
+        for (i, item) in messages.enumerate() {
+            if i > 0 && !response_text.is_empty() {
+                response_text.push_str(MESSAGE_SEPARATOR);
+                text_chars += MESSAGE_SEPARATOR.chars().count();
+            }
+
+            for content in &item.content {
+                match content.content_type.as_deref() {
+                    Some("refusal") => refusals.extend(content.refusal.clone()),
+                    Some("output_text") | None => {
+                        let Some(text) = &content.text else { continue };
+                        citations.extend(
+                            content
+                                .annotations
+                                .iter()
+                                .cloned()
+                                .filter_map(Annotation::into_citation)
+                                .map(|citation| citation.shifted(text_chars)),
+                        );
+                        response_text.push_str(text);
+                        text_chars += text.chars().count();
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        let refusal = (!refusals.is_empty()).then(|| refusals.join("\n"));
+
+        let reasoning_summary = openai_response
+            .output
+            .iter()
+            .filter(|item| item.item_type == "reasoning")
+            .flat_map(|item| &item.summary)
+            .map(|summary| summary.text.clone())
+            .collect();
+
+        let mut usage = Usage::default();
+        Self::add_usage(&mut usage, &openai_response.usage);
+
+        ChatResponse {
             response: response_text,
             model: openai_response.model,
-            usage: Usage {
-                prompt_tokens: openai_response.usage.input_tokens,
-                completion_tokens: openai_response.usage.output_tokens,
-                total_tokens: openai_response.usage.total_tokens,
-            },
-        })
+            response_id: openai_response.id,
+            usage,
+            parsed: None,
+            refused: refusal.is_some(),
+            refusal,
+            citations,
+            reasoning_summary,
+            cached: false,
+            cost_usd: None,
+        }
+    }
+
+    /// トークン使用量を加算（ツール呼び出しループの全リクエスト分を合計する）
+    fn add_usage(total: &mut Usage, usage: &OpenAIUsage) {
+        total.prompt_tokens += usage.input_tokens;
+        total.completion_tokens += usage.output_tokens;
+        total.total_tokens += usage.total_tokens;
+        total.reasoning_tokens += usage
+            .output_tokens_details
+            .as_ref()
+            .map_or(0, |details| details.reasoning_tokens);
+        total.cached_tokens += usage
+            .input_tokens_details
+            .as_ref()
+            .map_or(0, |details| details.cached_tokens);
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAIService {
+    fn name(&self) -> &'static str {
+        "responses"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.settings.default_model
+    }
+
+    fn allowed_models(&self) -> &[String] {
+        &self.settings.allowed_models
+    }
+
+    fn supports_previous_response_id(&self) -> bool {
+        true
+    }
+
+    fn has_tools(&self) -> bool {
+        !self.tools.is_empty()
+    }
+
+    async fn chat_with_history(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatResponse, OpenAIError> {
+        self.call_responses_api(messages, options).await
+    }
+
+    async fn chat_with_history_stream(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError> {
+        self.call_responses_api_stream(messages, options).await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        OpenAIService::embed(self, model, inputs).await
+    }
+
+    async fn moderate(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        OpenAIService::moderate(self, model, inputs).await
     }
 }
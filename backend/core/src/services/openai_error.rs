@@ -0,0 +1,168 @@
+//! OpenAI API のエラー型
+//!
+//! OpenAI のエラーエンベロープ（`error.type` / `error.code` / `error.param` / `error.message`）と
+//! HTTPステータスから、呼び出し側が扱いやすい種類に分類する。
+
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+use super::retry;
+use crate::models::{OpenAIErrorDetail, OpenAIErrorResponse};
+
+/// OpenAI サービスのエラー型
+#[derive(Error, Debug)]
+pub enum OpenAIError {
+    #[error("HTTP request failed: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    /// レート制限（時間を置けば回復する）
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        /// サーバーが指定した待機時間
+        retry_after: Option<Duration>,
+    },
+
+    /// クォータ超過（請求設定を見直すまで回復しない）
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// 認証・権限エラー（APIキーの誤りなど）
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+
+    /// リクエスト内容の誤り
+    #[error("Invalid request: {message}")]
+    InvalidRequest {
+        message: String,
+        /// 問題のあるパラメータ名
+        param: Option<String>,
+    },
+
+    /// コンテキスト長の超過
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+
+    /// OpenAI 側のサーバーエラー
+    #[error("OpenAI server error: {0}")]
+    ServerError(String),
+
+    /// タイムアウト
+    #[error("Request timed out")]
+    Timeout,
+
+    /// 上記に分類できないエラー
+    #[error("OpenAI API error: {0}")]
+    ApiError(String),
+
+    #[error("Stream error: {0}")]
+    StreamError(String),
+
+    /// モデルが応答を拒否した（構造化出力）
+    #[error("Model refused: {0}")]
+    Refusal(String),
+
+    /// 出力が要求した形式（JSON Schema）に合わない
+    #[error("Output does not match schema: {0}")]
+    SchemaMismatch(String),
+
+    /// ツール呼び出しループが最大ステップ数に達しても最終的な返答が得られなかった
+    #[error("Tool call limit exceeded ({0} steps)")]
+    ToolStepLimitExceeded(usize),
+}
+
+impl OpenAIError {
+    /// エラーレスポンス（ステータスコードとボディ）から分類する
+    pub fn from_response(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        match serde_json::from_str::<OpenAIErrorResponse>(body) {
+            Ok(envelope) => Self::from_detail(Some(status), envelope.error, retry_after),
+            // エンベロープ形式でない場合（プロキシのエラーページなど）はステータスのみで分類
+            Err(_) => Self::from_detail(
+                Some(status),
+                OpenAIErrorDetail {
+                    message: body.to_string(),
+                    ..OpenAIErrorDetail::default()
+                },
+                retry_after,
+            ),
+        }
+    }
+
+    /// エラー詳細から分類する（ストリーム中のエラーなどステータスがない場合は `status` が None）
+    pub fn from_detail(
+        status: Option<StatusCode>,
+        detail: OpenAIErrorDetail,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        let code = detail.code.as_deref().unwrap_or_default();
+        let error_type = detail.error_type.as_deref().unwrap_or_default();
+        let message = detail.message;
+
+        if code == "insufficient_quota" || error_type == "insufficient_quota" {
+            return OpenAIError::QuotaExceeded(message);
+        }
+        if code == "context_length_exceeded" {
+            return OpenAIError::ContextLengthExceeded(message);
+        }
+        if code == "rate_limit_exceeded" {
+            return OpenAIError::RateLimited {
+                message,
+                retry_after,
+            };
+        }
+        if code == "server_error" || error_type == "server_error" {
+            return OpenAIError::ServerError(message);
+        }
+
+        match status {
+            Some(StatusCode::TOO_MANY_REQUESTS) => OpenAIError::RateLimited {
+                message,
+                retry_after,
+            },
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                OpenAIError::AuthFailed(message)
+            }
+            Some(StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT) => OpenAIError::Timeout,
+            Some(s) if s.is_server_error() => OpenAIError::ServerError(message),
+            Some(s) if s.is_client_error() => OpenAIError::InvalidRequest {
+                message,
+                param: detail.param,
+            },
+            _ if error_type == "invalid_request_error" => OpenAIError::InvalidRequest {
+                message,
+                param: detail.param,
+            },
+            _ => OpenAIError::ApiError(message),
+        }
+    }
+
+    /// 再試行で回復しうるエラーか
+    ///
+    /// タイムアウトはサーバー側で処理済みの可能性があるため含めない。
+    /// 5xx はステータスコードでも判定する（`retry::is_retryable_status`）。
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OpenAIError::RequestError(e) => retry::is_retryable_error(e),
+            OpenAIError::RateLimited { .. } | OpenAIError::ServerError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// `previous_response_id` のレスポンスが見つからない（削除済み・期限切れなど）
+    pub fn is_previous_response_not_found(&self) -> bool {
+        matches!(
+            self,
+            OpenAIError::InvalidRequest { param: Some(param), .. } if param == "previous_response_id"
+        )
+    }
+
+    /// サーバーが指定した待機時間（レート制限時のみ）
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OpenAIError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
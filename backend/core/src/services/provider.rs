@@ -0,0 +1,249 @@
+//! LLM プロバイダー
+//!
+//! ハンドラーや CLI は `LlmProvider` を通して生成を呼び出し、
+//! 実装（Responses API / Chat Completions 互換 API）は設定で切り替える。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::Stream;
+use serde::de::DeserializeOwned;
+
+use super::chat_completions::ChatCompletionsService;
+use super::openai::OpenAIService;
+use super::openai_error::OpenAIError;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::{
+    ChatOptions, ChatRequest, ChatResponse, ChatStreamEvent, Embeddings, Message, ResponseFormat,
+    StructuredResponse,
+};
+use crate::schema::{self, JsonSchema};
+
+/// ストリーミングレスポンス（`ChatStreamEvent` の非同期ストリーム）
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatStreamEvent, OpenAIError>> + Send>>;
+
+/// LLM プロバイダー
+///
+/// 実装は履歴付きの2つのメソッドを持ち、単発チャットはデフォルト実装で履歴付きに変換する。
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// プロバイダー名（ログ用）
+    fn name(&self) -> &'static str;
+
+    /// デフォルトのモデル
+    fn default_model(&self) -> &str;
+
+    /// 許可されたモデル一覧
+    fn allowed_models(&self) -> &[String];
+
+    /// `ChatOptions::previous_response_id` でサーバー側の会話を続けられるか
+    fn supports_previous_response_id(&self) -> bool {
+        false
+    }
+
+    /// `ChatOptions::prompt_cache_key` を受け付けるか
+    fn supports_prompt_cache_key(&self) -> bool {
+        true
+    }
+
+    /// 呼び出しの中でツールを実行するか（実行する場合は応答キャッシュを使わない）
+    fn has_tools(&self) -> bool {
+        false
+    }
+
+    /// 指定されたモデルを検証し、使用するモデル名を返す（未指定ならデフォルト）
+    fn resolve_model(&self, requested: Option<&str>) -> Result<String, AppError> {
+        let allowed = self.allowed_models();
+        match requested {
+            None => Ok(self.default_model().to_string()),
+            Some(model) if allowed.iter().any(|m| m == model) => Ok(model.to_string()),
+            Some(model) => Err(AppError::Validation(format!(
+                "Model '{}' is not allowed (allowed: {})",
+                model,
+                allowed.join(", ")
+            ))),
+        }
+    }
+
+    /// 履歴を含めて呼び出す（`options.model` が None ならデフォルト）
+    async fn chat_with_history(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatResponse, OpenAIError>;
+
+    /// 履歴を含めてストリーミングで呼び出す（`options.model` が None ならデフォルト）
+    async fn chat_with_history_stream(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError>;
+
+    /// テキストの埋め込みベクトル（入力と同じ順番）とトークン使用量を取得
+    ///
+    /// 対応していないプロバイダーはエラーを返す。
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        let _ = (model, inputs);
+        Err(OpenAIError::ApiError(format!(
+            "Provider '{}' does not support embeddings",
+            self.name()
+        )))
+    }
+
+    /// テキストのカテゴリごとのモデレーションスコアを取得（入力と同じ順番）
+    ///
+    /// 対応していないプロバイダーはエラーを返す。
+    async fn moderate(
+        &self,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        let _ = (model, inputs);
+        Err(OpenAIError::ApiError(format!(
+            "Provider '{}' does not support moderation",
+            self.name()
+        )))
+    }
+
+    /// 単発チャット
+    ///
+    /// モデルは検証しない。許可リストでの検証は呼び出し側で `resolve_model` を使う。
+    /// JSON の出力形式を指定した場合は、パース結果を `parsed` に入れて返す。
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIError> {
+        let (messages, options) = split_request(request);
+        let is_json = options
+            .response_format
+            .as_ref()
+            .is_some_and(ResponseFormat::is_json);
+
+        let mut response = self.chat_with_history(messages, options).await?;
+
+        if is_json {
+            response.parsed = Some(parse_output(&response)?);
+        }
+        Ok(response)
+    }
+
+    /// 単発チャット（ストリーミング）
+    async fn chat_stream(&self, request: ChatRequest) -> Result<ChatStream, OpenAIError> {
+        let (messages, options) = split_request(request);
+        self.chat_with_history_stream(messages, options).await
+    }
+}
+
+/// `LlmProvider` の拡張メソッド（ジェネリックなため `dyn LlmProvider` とは別に定義する）
+#[async_trait]
+pub trait LlmProviderExt: LlmProvider {
+    /// 出力を型 `T` の JSON Schema（`#[derive(JsonSchema)]`）に従わせて呼び出し、
+    /// `T` にデシリアライズする（単発チャット）
+    ///
+    /// `request.response_format` は `T` のスキーマで上書きする。
+    /// モデルが拒否した場合は `Refusal`、出力が `T` に合わない場合は `SchemaMismatch` を返す。
+    async fn chat_structured<T: JsonSchema + DeserializeOwned + Send>(
+        &self,
+        request: ChatRequest,
+    ) -> Result<StructuredResponse<T>, OpenAIError> {
+        let format = ResponseFormat::JsonSchema {
+            name: schema::schema_name::<T>(),
+            schema: schema::strict_schema::<T>(),
+            description: None,
+            strict: true,
+        };
+
+        let response = self
+            .chat(ChatRequest {
+                response_format: Some(format),
+                ..request
+            })
+            .await?;
+        let data = parse_output(&response)?;
+
+        Ok(StructuredResponse { data, response })
+    }
+}
+
+impl<P: LlmProvider + ?Sized> LlmProviderExt for P {}
+
+/// プロバイダーの種類（`LLM_PROVIDER`）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProviderKind {
+    /// OpenAI Responses API
+    #[default]
+    Responses,
+    /// OpenAI Chat Completions 互換 API（ローカルサーバーなど）
+    ChatCompletions,
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "responses" => Ok(ProviderKind::Responses),
+            "chat_completions" => Ok(ProviderKind::ChatCompletions),
+            _ => Err(format!(
+                "Unknown provider '{}' (expected: responses, chat_completions)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderKind::Responses => write!(f, "responses"),
+            ProviderKind::ChatCompletions => write!(f, "chat_completions"),
+        }
+    }
+}
+
+/// 設定からプロバイダーを作成
+pub fn from_config(config: &Config) -> Arc<dyn LlmProvider> {
+    match config.llm_provider {
+        ProviderKind::Responses => Arc::new(OpenAIService::from_config(config)),
+        ProviderKind::ChatCompletions => Arc::new(ChatCompletionsService::from_config(config)),
+    }
+}
+
+/// 許可リストにデフォルトモデルを加える（デフォルトモデルは常に許可する）
+pub(crate) fn with_default_model(default_model: &str, mut allowed: Vec<String>) -> Vec<String> {
+    if !allowed.iter().any(|m| m == default_model) {
+        allowed.push(default_model.to_string());
+    }
+    allowed
+}
+
+/// 単発チャットのリクエストを履歴とオプションに分ける
+fn split_request(request: ChatRequest) -> (Vec<Message>, ChatOptions) {
+    let messages = vec![Message {
+        role: "user".to_string(),
+        content: request.message,
+    }];
+    let options = ChatOptions {
+        instructions: request.system_prompt,
+        model: request.model,
+        response_format: request.response_format,
+        reasoning: request.reasoning,
+        previous_response_id: None,
+        bypass_cache: request.bypass_cache,
+        prompt_cache_key: None,
+        generation: request.generation,
+    };
+
+    (messages, options)
+}
+
+/// JSON 形式の出力をパース
+fn parse_output<T: DeserializeOwned>(response: &ChatResponse) -> Result<T, OpenAIError> {
+    if let Some(refusal) = &response.refusal {
+        return Err(OpenAIError::Refusal(refusal.clone()));
+    }
+
+    serde_json::from_str(&response.response).map_err(|e| OpenAIError::SchemaMismatch(e.to_string()))
+}
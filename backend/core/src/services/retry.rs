@@ -0,0 +1,143 @@
+//! リトライポリシー
+//!
+//! 生成のリクエスト（POST）は冪等ではないため、サーバーが処理していないと分かる失敗だけを指数バックオフで再試行する。
+//! 再試行するのは、接続できなかった場合と、ステータスコードが再試行を求める場合（429、`Retry-After` 付きの 503）。
+//! 読み取りのタイムアウトやその他の 5xx は、サーバー側で生成・課金済みの可能性があるため再試行しない。
+//! `Retry-After` / `x-ratelimit-reset-*` ヘッダーがあればその待機時間（`max_delay` まで）を優先する。
+
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+/// リトライポリシー
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// 最大試行回数（初回を含む。1ならリトライしない）
+    pub max_attempts: u32,
+    /// バックオフの基準待機時間（試行ごとに2倍）
+    pub base_delay: Duration,
+    /// バックオフ1回あたりの待機時間の上限
+    pub max_delay: Duration,
+    /// 待機時間にランダムな揺らぎを加えるか（同時リトライの集中を避ける）
+    pub jitter: bool,
+    /// 初回リクエストからの全体の制限時間（これを超える待機はしない）
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            deadline: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// リトライしないポリシー
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// `attempt` 回目（1始まり）の失敗後の待機時間
+    ///
+    /// サーバーが待機時間を指定していればそれを使い（`max_delay` まで）、なければ指数バックオフで計算する。
+    pub fn delay(&self, attempt: u32, server_hint: Option<Duration>) -> Duration {
+        if let Some(hint) = server_hint {
+            return hint.min(self.max_delay);
+        }
+
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        if self.jitter && !delay.is_zero() {
+            // 50%〜100% の範囲でランダムにする
+            let millis = delay.as_millis() as u64;
+            Duration::from_millis(rand::rng().random_range(millis / 2..=millis))
+        } else {
+            delay
+        }
+    }
+}
+
+/// 再試行してよい通信エラーか（接続できず、リクエストがサーバーに届いていない）
+pub fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_connect()
+}
+
+/// ステータスコードが再試行を求めているか（429、または `Retry-After` 付きの 503）
+pub fn is_retryable_status(status: StatusCode, headers: &HeaderMap) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::SERVICE_UNAVAILABLE => {
+            headers.contains_key(RETRY_AFTER) || headers.contains_key("retry-after-ms")
+        }
+        _ => false,
+    }
+}
+
+/// レスポンスヘッダーからサーバー指定の待機時間を取得
+///
+/// `retry-after-ms` → `retry-after`（秒）→ `x-ratelimit-reset-*`（長い方）の順に参照する。
+pub fn server_retry_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+
+    if let Some(secs) = header("retry-after").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max()
+}
+
+/// "1s", "6m0s", "20ms", "1h2m3.5s" 形式の期間をパース
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+
+        let (unit_secs, unit_len) = if rest.starts_with("ms") {
+            (0.001, 2)
+        } else if rest.starts_with('h') {
+            (3600.0, 1)
+        } else if rest.starts_with('m') {
+            (60.0, 1)
+        } else if rest.starts_with('s') {
+            (1.0, 1)
+        } else {
+            return None;
+        };
+
+        total += number * unit_secs;
+        rest = &rest[unit_len..];
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
@@ -0,0 +1,352 @@
+//! テスト用のモック LLM プロバイダー（`testing` フィーチャー）
+//!
+//! 実際の API を呼ばずに、あらかじめ登録した応答・ストリーム・エラーを順番に返す。
+//! 受け取ったリクエスト（履歴・instructions など）を記録するので、テストで内容を検証できる。
+//! 埋め込みベクトルは単語のハッシュから決定的に作る（同じ単語を含むテキストほど似る）。
+//! モデレーションは受け取ったテキストを記録し、どのカテゴリにも該当しないスコアを返す。
+//!
+//! ```ignore
+//! let mock = Arc::new(
+//!     MockProvider::new()
+//!         .reply("Hello!")
+//!         .reply_stream(["Hel", "lo"])
+//!         .with_latency(Duration::from_millis(50))
+//!         .fail(OpenAIError::Timeout),
+//! );
+//! state.llm = mock.clone();
+//!
+//! // ...
+//! assert_eq!(mock.requests()[0].options.instructions.as_deref(), Some("Be brief."));
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::models::{ChatOptions, ChatResponse, ChatStreamEvent, Embeddings, Message, Usage};
+use crate::services::provider::{self, ChatStream, LlmProvider};
+use crate::services::{OpenAIError, DEFAULT_MODEL};
+
+/// モックが返す応答
+pub enum MockReply {
+    /// テキストの返答（ストリーミングでは1回の差分で返す）
+    Text(String),
+    /// 差分に分けた返答（ストリーミング以外では連結して返す）
+    Stream {
+        deltas: Vec<String>,
+        /// 全ての差分を返した後に発生させるエラー（ストリーミングのみ）
+        error: Option<OpenAIError>,
+    },
+    /// レスポンス全体（トークン使用量や推論の要約などを指定する場合）
+    Response(ChatResponse),
+    /// エラー（ストリーミングでは開始前に返す）
+    Error(OpenAIError),
+}
+
+/// モックが受け取ったリクエスト
+#[derive(Clone)]
+pub struct RecordedRequest {
+    pub messages: Vec<Message>,
+    pub options: ChatOptions,
+    /// ストリーミングで呼び出されたか
+    pub stream: bool,
+}
+
+/// モックの埋め込みベクトルの次元数
+pub const MOCK_EMBEDDING_DIMENSIONS: usize = 64;
+
+/// 登録された応答と、応答までの待機時間
+struct Step {
+    reply: MockReply,
+    latency: Duration,
+    /// テキストの返答のトークン使用量
+    usage: Usage,
+}
+
+/// 登録した応答を順番に返すモック LLM プロバイダー
+pub struct MockProvider {
+    steps: Mutex<VecDeque<Step>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+    /// モデレーションで受け取ったテキスト
+    moderations: Mutex<Vec<String>>,
+    default_model: String,
+    allowed_models: Vec<String>,
+    /// `previous_response_id` に対応しているように振る舞うか
+    response_chaining: bool,
+    /// ツールを実行するように振る舞うか
+    tools: bool,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockProvider {
+    /// 応答が登録されていないモックを作成（デフォルトモデルは本物と同じ）
+    pub fn new() -> Self {
+        Self {
+            steps: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+            moderations: Mutex::new(Vec::new()),
+            default_model: DEFAULT_MODEL.to_string(),
+            allowed_models: vec![DEFAULT_MODEL.to_string()],
+            response_chaining: false,
+            tools: false,
+        }
+    }
+
+    /// 応答を登録
+    pub fn then(self, reply: MockReply) -> Self {
+        self.steps.lock().unwrap().push_back(Step {
+            reply,
+            latency: Duration::ZERO,
+            usage: Usage::default(),
+        });
+        self
+    }
+
+    /// テキストの返答を登録
+    pub fn reply(self, text: impl Into<String>) -> Self {
+        self.then(MockReply::Text(text.into()))
+    }
+
+    /// 差分に分けた返答を登録
+    pub fn reply_stream<I, S>(self, deltas: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.then(MockReply::Stream {
+            deltas: deltas.into_iter().map(Into::into).collect(),
+            error: None,
+        })
+    }
+
+    /// エラーを登録
+    pub fn fail(self, error: OpenAIError) -> Self {
+        self.then(MockReply::Error(error))
+    }
+
+    /// 最後に登録した応答の待機時間を設定
+    ///
+    /// 応答（ストリーミングでは各イベント）を返す前に待機する。
+    pub fn with_latency(self, latency: Duration) -> Self {
+        if let Some(step) = self.steps.lock().unwrap().back_mut() {
+            step.latency = latency;
+        }
+        self
+    }
+
+    /// 最後に登録した返答のトークン使用量を設定（`MockReply::Response` では無視する）
+    pub fn with_usage(self, usage: Usage) -> Self {
+        if let Some(step) = self.steps.lock().unwrap().back_mut() {
+            step.usage = usage;
+        }
+        self
+    }
+
+    /// デフォルトモデルと許可リストを設定
+    pub fn with_models(mut self, default_model: String, allowed_models: Vec<String>) -> Self {
+        self.allowed_models = provider::with_default_model(&default_model, allowed_models);
+        self.default_model = default_model;
+        self
+    }
+
+    /// `previous_response_id` に対応しているように振る舞う
+    pub fn with_response_chaining(mut self) -> Self {
+        self.response_chaining = true;
+        self
+    }
+
+    /// ツールを登録したプロバイダーのように振る舞う（`has_tools` が true になる）
+    pub fn with_tools(mut self) -> Self {
+        self.tools = true;
+        self
+    }
+
+    /// 受け取ったリクエスト（古い順）
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// モデレーションで受け取ったテキスト（古い順）
+    pub fn moderations(&self) -> Vec<String> {
+        self.moderations.lock().unwrap().clone()
+    }
+
+    /// まだ返していない応答の数
+    pub fn remaining(&self) -> usize {
+        self.steps.lock().unwrap().len()
+    }
+
+    /// リクエストを記録し、次の応答を取り出す
+    fn next_step(&self, messages: Vec<Message>, options: ChatOptions, stream: bool) -> Step {
+        let mut requests = self.requests.lock().unwrap();
+        requests.push(RecordedRequest {
+            messages,
+            options,
+            stream,
+        });
+
+        self.steps.lock().unwrap().pop_front().unwrap_or_else(|| Step {
+            reply: MockReply::Error(OpenAIError::ApiError(format!(
+                "mock provider has no reply for request #{}",
+                requests.len()
+            ))),
+            latency: Duration::ZERO,
+            usage: Usage::default(),
+        })
+    }
+
+    /// テキストからレスポンスを作成（レスポンスIDはリクエストの通し番号）
+    fn text_response(&self, text: String, options: &ChatOptions, usage: Usage) -> ChatResponse {
+        ChatResponse {
+            response: text,
+            model: options
+                .model
+                .clone()
+                .unwrap_or_else(|| self.default_model.clone()),
+            response_id: format!("mock_resp_{}", self.requests.lock().unwrap().len()),
+            usage,
+            parsed: None,
+            refused: false,
+            refusal: None,
+            citations: Vec::new(),
+            reasoning_summary: Vec::new(),
+            cached: false,
+            cost_usd: None,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.default_model
+    }
+
+    fn allowed_models(&self) -> &[String] {
+        &self.allowed_models
+    }
+
+    fn supports_previous_response_id(&self) -> bool {
+        self.response_chaining
+    }
+
+    fn has_tools(&self) -> bool {
+        self.tools
+    }
+
+    async fn chat_with_history(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatResponse, OpenAIError> {
+        let step = self.next_step(messages, options.clone(), false);
+        tokio::time::sleep(step.latency).await;
+
+        match step.reply {
+            MockReply::Text(text) => Ok(self.text_response(text, &options, step.usage)),
+            MockReply::Stream { deltas, .. } => {
+                Ok(self.text_response(deltas.concat(), &options, step.usage))
+            }
+            MockReply::Response(response) => Ok(response),
+            MockReply::Error(error) => Err(error),
+        }
+    }
+
+    async fn chat_with_history_stream(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatStream, OpenAIError> {
+        let step = self.next_step(messages, options.clone(), true);
+        let latency = step.latency;
+        let usage = step.usage;
+
+        let (deltas, error, response) = match step.reply {
+            MockReply::Text(text) => {
+                let response = self.text_response(text.clone(), &options, usage);
+                (vec![text], None, response)
+            }
+            MockReply::Stream { deltas, error } => {
+                let response = self.text_response(deltas.concat(), &options, usage);
+                (deltas, error, response)
+            }
+            MockReply::Response(response) => (vec![response.response.clone()], None, response),
+            MockReply::Error(error) => {
+                tokio::time::sleep(latency).await;
+                return Err(error);
+            }
+        };
+
+        let stream = async_stream::try_stream! {
+            for text in deltas {
+                tokio::time::sleep(latency).await;
+                yield ChatStreamEvent::Delta { text };
+            }
+            if let Some(error) = error {
+                Err(error)?;
+            }
+            tokio::time::sleep(latency).await;
+            yield ChatStreamEvent::Completed(response);
+        };
+
+        Ok(Box::pin(stream))
+    }
+
+    /// 単語（英数字の並び、大文字小文字は区別しない）ごとにハッシュした次元を数える
+    ///
+    /// トークン使用量は単語数とする。
+    async fn embed(&self, _model: &str, inputs: &[String]) -> Result<Embeddings, OpenAIError> {
+        let embed = |text: &str| {
+            let mut vector = vec![0.0; MOCK_EMBEDDING_DIMENSIONS];
+            for word in text
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+            {
+                let hash = word
+                    .to_lowercase()
+                    .bytes()
+                    .fold(0xcbf29ce484222325_u64, |h, b| {
+                        (h ^ b as u64).wrapping_mul(0x100000001b3)
+                    });
+                vector[(hash % MOCK_EMBEDDING_DIMENSIONS as u64) as usize] += 1.0;
+            }
+            vector
+        };
+
+        let words = inputs
+            .iter()
+            .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+            .filter(|w| !w.is_empty())
+            .count() as u32;
+
+        Ok(Embeddings {
+            vectors: inputs.iter().map(|text| embed(text)).collect(),
+            usage: Usage {
+                prompt_tokens: words,
+                total_tokens: words,
+                ..Usage::default()
+            },
+        })
+    }
+
+    /// 受け取ったテキストを記録し、空のスコアを返す
+    async fn moderate(
+        &self,
+        _model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<BTreeMap<String, f64>>, OpenAIError> {
+        self.moderations.lock().unwrap().extend_from_slice(inputs);
+        Ok(vec![BTreeMap::new(); inputs.len()])
+    }
+}
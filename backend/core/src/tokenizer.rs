@@ -0,0 +1,443 @@
+//! トークン数の計算とコンテキストウィンドウの確認
+//!
+//! API を呼ばずに、送信する履歴のトークン数を o200k_base / cl100k_base の BPE で数える。
+//! BPE のランク表（tiktoken 形式の `*.tiktoken` ファイル）は `TOKENIZER_DIR` から読み込む。
+//! ランク表がないエンコーディングは、事前分割の結果から文字数で近似する
+//! （`TOKENIZER=bpe` の場合は起動時に警告を出す）。
+//!
+//! ```ignore
+//! let tokenizer = Tokenizer::from_config(&config)?;
+//! let tokens = tokenizer.count_tokens("gpt-4o", &messages, Some("Be brief."));
+//!
+//! // コンテキストウィンドウを超える場合は Validation エラー
+//! tokenizer.check_context_window("gpt-4o", &messages, Some("Be brief."))?;
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use fancy_regex::Regex;
+use tracing::warn;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::{ContentPart, Message, MessageContent};
+
+/// メッセージごとに加わるトークン数（ロールや区切り）
+const TOKENS_PER_MESSAGE: usize = 3;
+/// 返答の開始（`<|start|>assistant<|message|>`）に加わるトークン数
+const TOKENS_PER_REPLY: usize = 3;
+/// 画像1枚のトークン数（detail: low）
+const IMAGE_TOKENS_LOW: usize = 85;
+/// 画像1枚のトークン数（detail: high / auto、1024x1024 相当の目安）
+const IMAGE_TOKENS_HIGH: usize = 765;
+
+/// BPE のエンコーディング
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// GPT-4o 以降（gpt-4o、gpt-4.1、gpt-5、o シリーズ）
+    O200kBase,
+    /// GPT-4 / GPT-3.5
+    Cl100kBase,
+}
+
+impl Encoding {
+    /// モデルのエンコーディング（不明なモデルは o200k_base）
+    pub fn for_model(model: &str) -> Self {
+        if model.starts_with("gpt-4o") || model.starts_with("gpt-4.") {
+            Encoding::O200kBase
+        } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+            Encoding::Cl100kBase
+        } else {
+            Encoding::O200kBase
+        }
+    }
+
+    /// tiktoken のランク表のファイル名
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Encoding::O200kBase => "o200k_base.tiktoken",
+            Encoding::Cl100kBase => "cl100k_base.tiktoken",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::O200kBase => write!(f, "o200k_base"),
+            Encoding::Cl100kBase => write!(f, "cl100k_base"),
+        }
+    }
+}
+
+/// トークン数の数え方
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenizerMode {
+    /// BPE のランク表で数える（ランク表がないエンコーディングは警告を出して近似値で数える）
+    Bpe,
+    /// ランク表があるエンコーディングは BPE、ないものは近似値で数える
+    #[default]
+    Estimate,
+}
+
+impl FromStr for TokenizerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bpe" => Ok(TokenizerMode::Bpe),
+            "estimate" => Ok(TokenizerMode::Estimate),
+            _ => Err(format!(
+                "Unknown tokenizer mode '{}' (expected: bpe, estimate)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TokenizerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizerMode::Bpe => write!(f, "bpe"),
+            TokenizerMode::Estimate => write!(f, "estimate"),
+        }
+    }
+}
+
+/// モデルのコンテキストウィンドウ（入力と出力の合計トークン数、不明なモデルは None）
+///
+/// スナップショット名（`gpt-4o-2024-08-06` など）は前方一致で判定する。
+pub fn context_window(model: &str) -> Option<usize> {
+    // 長い接頭辞から順に判定する
+    const WINDOWS: &[(&str, usize)] = &[
+        ("gpt-5-chat", 128_000),
+        ("gpt-5.1-chat", 128_000),
+        ("gpt-5.2-chat", 128_000),
+        ("gpt-5", 400_000),
+        ("gpt-4.1", 1_047_576),
+        ("gpt-4o", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4-32k", 32_768),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1-mini", 128_000),
+        ("o1", 200_000),
+        ("o3", 200_000),
+        ("o4-mini", 200_000),
+    ];
+
+    WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// BPE のランク表（バイト列 → ランク、ランクが小さいほど先に結合する）
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Bpe {
+    /// tiktoken 形式（1行に「base64 のトークン ランク」）のランク表を読み込む
+    pub fn from_tiktoken(data: &str) -> Result<Self, String> {
+        let mut ranks = HashMap::new();
+
+        for (i, line) in data
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+        {
+            let invalid = || format!("Invalid tiktoken rank at line {}", i + 1);
+            let (token, rank) = line.trim().split_once(' ').ok_or_else(invalid)?;
+            let token = BASE64.decode(token).map_err(|_| invalid())?;
+            let rank = rank.parse().map_err(|_| invalid())?;
+            ranks.insert(token, rank);
+        }
+
+        Ok(Self { ranks })
+    }
+
+    /// ランク表のファイルを読み込む
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_tiktoken(&data)
+    }
+
+    /// 事前分割した1片のトークン数
+    ///
+    /// 隣り合う2トークンのうち、結合後のランクが最小のもの（同じランクなら左のもの）を結合できなくなるまで繰り返す。
+    /// 結合の候補をヒープで管理し、長い片でも O(n log n) で数える。
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if piece.len() <= 1 || self.ranks.contains_key(piece) {
+            return 1;
+        }
+
+        let len = piece.len();
+        let rank = |start: usize, end: usize| self.ranks.get(&piece[start..end]).copied();
+
+        // 各トークンの開始位置からの連結リスト（結合されたトークンは alive が false）
+        let mut next: Vec<usize> = (1..=len).collect();
+        let mut prev: Vec<Option<usize>> = (0..len).map(|i| i.checked_sub(1)).collect();
+        let mut alive = vec![true; len];
+
+        // 結合の候補（ランク, 開始位置, 終了位置）。結合で古くなった候補は取り出したときに捨てる
+        let mut candidates: BinaryHeap<_> = (0..len - 1)
+            .filter_map(|i| Some(Reverse((rank(i, i + 2)?, i, i + 2))))
+            .collect();
+        let mut tokens = len;
+
+        while let Some(Reverse((_, start, end))) = candidates.pop() {
+            let mid = next[start];
+            if !alive[start] || mid >= len || next[mid] != end {
+                continue;
+            }
+
+            alive[mid] = false;
+            next[start] = end;
+            if end < len {
+                prev[end] = Some(start);
+            }
+            tokens -= 1;
+
+            // 結合したトークンと前後のトークンの組を候補に加える
+            if let Some(before) = prev[start]
+                && let Some(r) = rank(before, end)
+            {
+                candidates.push(Reverse((r, before, end)));
+            }
+            if end < len
+                && let Some(r) = rank(start, next[end])
+            {
+                candidates.push(Reverse((r, start, next[end])));
+            }
+        }
+
+        tokens
+    }
+}
+
+/// トークン数の計算
+///
+/// エンコーディングごとにランク表を持ち、ない場合は近似値を返す。
+#[derive(Clone, Default)]
+pub struct Tokenizer {
+    bpe: HashMap<Encoding, Arc<Bpe>>,
+}
+
+impl Tokenizer {
+    /// ランク表なし（全エンコーディングを近似）で作成
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設定から作成（`TOKENIZER_DIR` にあるランク表を読み込む）
+    ///
+    /// ランク表がないエンコーディングは近似値で数える（`TOKENIZER=bpe` の場合は警告を出す）。
+    /// ランク表のファイルを読み込めない場合はエラー。
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut tokenizer = Self::new();
+
+        for encoding in [Encoding::O200kBase, Encoding::Cl100kBase] {
+            let path = config
+                .tokenizer_dir
+                .as_ref()
+                .map(|dir| dir.join(encoding.file_name()));
+            match path {
+                Some(path) if path.exists() => {
+                    tokenizer = tokenizer.with_bpe(encoding, Bpe::load(&path)?);
+                }
+                _ if config.tokenizer == TokenizerMode::Bpe => warn!(
+                    "Tokenizer rank file for {} not found in TOKENIZER_DIR; estimating token counts",
+                    encoding
+                ),
+                _ => {}
+            }
+        }
+
+        Ok(tokenizer)
+    }
+
+    /// ディレクトリにある全エンコーディングのランク表を読み込む（ないものがあればエラー）
+    pub fn from_dir(dir: &Path) -> Result<Self, String> {
+        let mut tokenizer = Self::new();
+
+        for encoding in [Encoding::O200kBase, Encoding::Cl100kBase] {
+            let path = dir.join(encoding.file_name());
+            if !path.exists() {
+                return Err(format!(
+                    "Tokenizer rank file for {} not found: {}",
+                    encoding,
+                    path.display()
+                ));
+            }
+            tokenizer = tokenizer.with_bpe(encoding, Bpe::load(&path)?);
+        }
+
+        Ok(tokenizer)
+    }
+
+    /// エンコーディングのランク表を設定
+    pub fn with_bpe(mut self, encoding: Encoding, bpe: Bpe) -> Self {
+        self.bpe.insert(encoding, Arc::new(bpe));
+        self
+    }
+
+    /// ランク表を読み込んだエンコーディングか（false の場合は近似値）
+    pub fn is_exact(&self, encoding: Encoding) -> bool {
+        self.bpe.contains_key(&encoding)
+    }
+
+    /// テキストのトークン数
+    pub fn count_text(&self, encoding: Encoding, text: &str) -> usize {
+        let bpe = self.bpe.get(&encoding);
+
+        pretokenize(encoding, text)
+            .map(|piece| match bpe {
+                Some(bpe) => bpe.count_piece(piece.as_bytes()),
+                None => estimate_piece(piece),
+            })
+            .sum()
+    }
+
+    /// 履歴と instructions を送信したときの入力トークン数
+    ///
+    /// 画像は detail ごとの目安の値で数える。
+    /// ファイル（PDF など）の内容はサーバー側で抽出されるため、ファイル名のみ数える。
+    pub fn count_tokens(
+        &self,
+        model: &str,
+        messages: &[Message],
+        instructions: Option<&str>,
+    ) -> usize {
+        let encoding = Encoding::for_model(model);
+
+        let instructions = instructions.map_or(0, |instructions| {
+            TOKENS_PER_MESSAGE + self.count_text(encoding, instructions)
+        });
+        let messages: usize = messages
+            .iter()
+            .map(|message| self.count_message(model, message))
+            .sum();
+
+        instructions + messages + TOKENS_PER_REPLY
+    }
+
+    /// メッセージ1件のトークン数（ロールや区切りを含む）
+    pub fn count_message(&self, model: &str, message: &Message) -> usize {
+        let encoding = Encoding::for_model(model);
+
+        TOKENS_PER_MESSAGE
+            + self.count_text(encoding, &message.role)
+            + self.count_content(encoding, &message.content)
+    }
+
+    /// 入力がモデルのコンテキストウィンドウに収まるか確認し、入力トークン数を返す
+    ///
+    /// 収まらない場合は `Validation` エラー。コンテキストウィンドウが不明なモデルは確認しない。
+    pub fn check_context_window(
+        &self,
+        model: &str,
+        messages: &[Message],
+        instructions: Option<&str>,
+    ) -> Result<usize, AppError> {
+        let tokens = self.count_tokens(model, messages, instructions);
+
+        match context_window(model) {
+            Some(window) if tokens > window => Err(AppError::Validation(format!(
+                "Input is too long for model '{}': about {} tokens exceeds the context window of {} tokens",
+                model, tokens, window
+            ))),
+            _ => Ok(tokens),
+        }
+    }
+
+    fn count_content(&self, encoding: Encoding, content: &MessageContent) -> usize {
+        match content {
+            MessageContent::Text(text) => self.count_text(encoding, text),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::InputText { text } => self.count_text(encoding, text),
+                    ContentPart::InputImage { detail, .. } if detail == "low" => IMAGE_TOKENS_LOW,
+                    ContentPart::InputImage { .. } => IMAGE_TOKENS_HIGH,
+                    ContentPart::InputFile { filename, .. } => filename
+                        .as_deref()
+                        .map_or(0, |name| self.count_text(encoding, name)),
+                })
+                .sum(),
+        }
+    }
+}
+
+/// ランク表がない場合の1片のトークン数の近似
+///
+/// ASCII は4バイトで1トークン、それ以外（日本語など）は1文字1トークンとして数える。
+fn estimate_piece(piece: &str) -> usize {
+    let ascii = piece.bytes().filter(u8::is_ascii).count();
+    let others = piece.chars().filter(|c| !c.is_ascii()).count();
+
+    (ascii.div_ceil(4) + others).max(1)
+}
+
+/// o200k_base の事前分割の正規表現（tiktoken と同じ）
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n/]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+/// cl100k_base の事前分割の正規表現（tiktoken と同じ）
+const CL100K_PATTERN: &str = concat!(
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)",
+    r"|[^\r\n\p{L}\p{N}]?\p{L}+",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+static O200K_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(O200K_PATTERN).expect("invalid o200k_base pattern"));
+static CL100K_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(CL100K_PATTERN).expect("invalid cl100k_base pattern"));
+
+/// BPE の前にテキストを分割する（tiktoken の分割用正規表現で分ける）
+///
+/// 正規表現がバックトラックの上限に達した場合は、残りを1片として数える。
+fn pretokenize(encoding: Encoding, text: &str) -> impl Iterator<Item = &str> {
+    let regex = match encoding {
+        Encoding::O200kBase => &*O200K_REGEX,
+        Encoding::Cl100kBase => &*CL100K_REGEX,
+    };
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+
+    while pos < text.len() {
+        match regex.find_from_pos(text, pos) {
+            Ok(Some(m)) if m.end() > pos => {
+                pieces.push(m.as_str());
+                pos = m.end();
+            }
+            Ok(_) => break,
+            Err(_) => {
+                pieces.push(&text[pos..]);
+                break;
+            }
+        }
+    }
+
+    pieces.into_iter()
+}
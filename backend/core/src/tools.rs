@@ -0,0 +1,111 @@
+//! ツール呼び出し（Function calling）
+//!
+//! モデルから呼び出せる関数を `Tool` として実装し、`ToolRegistry` に登録する。
+//! 登録したレジストリを `OpenAIService::with_tools` で渡すと、
+//! モデルがツールを要求したときに実行して結果を返すループが有効になる。
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::models::ToolDefinition;
+
+/// ツール実行のエラー型
+///
+/// エラーはリクエスト全体を失敗させず、内容をモデルに返して判断させる。
+#[derive(Error, Debug)]
+pub enum ToolError {
+    /// 引数が不正
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
+
+    /// 実行時のエラー
+    #[error("Execution failed: {0}")]
+    Execution(String),
+}
+
+/// モデルから呼び出せるツール
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// ツール名（モデルが呼び出しに使う。英数字・`_`・`-` のみ）
+    fn name(&self) -> &str;
+
+    /// モデル向けの説明（いつ・何のために使うか）
+    fn description(&self) -> &str;
+
+    /// 引数の JSON Schema
+    fn parameters(&self) -> Value;
+
+    /// ツールを実行し、結果を JSON で返す
+    async fn execute(&self, arguments: Value) -> Result<Value, ToolError>;
+}
+
+/// ツールのレジストリ
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ツールを登録（同名のツールは置き換える）
+    pub fn register(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.insert(tool.name().to_string(), Arc::new(tool));
+        self
+    }
+
+    /// ツールが1つも登録されていないか
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// 登録されたツール名の一覧
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tools.keys().map(String::as_str)
+    }
+
+    /// リクエストに含めるツール定義
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .values()
+            .map(|tool| ToolDefinition::Function {
+                name: tool.name().to_string(),
+                description: tool.description().to_string(),
+                parameters: tool.parameters(),
+            })
+            .collect()
+    }
+
+    /// ツールを実行し、モデルに返す出力（JSON文字列）を作成
+    ///
+    /// 未登録のツールや実行エラーも `{"error": "..."}` として返す。
+    pub async fn call(&self, name: &str, arguments: &str) -> String {
+        let result = match self.tools.get(name) {
+            Some(tool) => match serde_json::from_str::<Value>(arguments) {
+                Ok(arguments) => tool.execute(arguments).await,
+                Err(e) => Err(ToolError::InvalidArguments(e.to_string())),
+            },
+            None => Err(ToolError::InvalidArguments(format!("Unknown tool: {}", name))),
+        };
+
+        let output = match result {
+            Ok(value) => {
+                info!("Tool call succeeded: {}", name);
+                value
+            }
+            Err(e) => {
+                warn!("Tool call failed: {}: {}", name, e);
+                json!({ "error": e.to_string() })
+            }
+        };
+
+        output.to_string()
+    }
+}
@@ -1,36 +1,115 @@
 // API types matching the Rust backend
 
+export type HistoryMode = 'replay' | 'chained'
+
+export type HistoryStrategy =
+  | { type: 'full' }
+  | { type: 'last_turns'; turns: number }
+  | { type: 'token_budget'; max_tokens: number }
+  | { type: 'drop_middle'; head_turns: number; tail_turns: number }
+  | { type: 'summarize'; max_tokens: number; keep_turns: number }
+
+// Sampling and generation parameters; omitted fields use the API defaults
+export interface GenerationOptions {
+  temperature?: number
+  top_p?: number
+  max_output_tokens?: number
+  truncation?: 'auto' | 'disabled'
+  parallel_tool_calls?: boolean
+  store?: boolean
+  metadata?: Record<string, string>
+  verbosity?: 'low' | 'medium' | 'high'
+}
+
 export interface Session {
   id: string
   system_prompt: string | null
+  model?: string | null
+  history_mode?: HistoryMode
+  history_strategy?: HistoryStrategy
+  generation?: GenerationOptions
   created_at: string
 }
 
+export type ContentPart =
+  | { type: 'input_text'; text: string }
+  | { type: 'input_image'; image_url?: string; file_id?: string; detail?: 'low' | 'high' | 'auto' }
+  | { type: 'input_file'; filename?: string; file_data?: string; file_url?: string; file_id?: string }
+
 export interface Message {
   id: string
   session_id: string
   role: 'user' | 'assistant'
   content: string
+  parts?: ContentPart[] | null
+  status?: 'completed' | 'incomplete' | 'failed'
+  reasoning_summary?: string[]
+  response_id?: string | null
+  pinned?: boolean
+  context_message_ids?: string[]
+  moderation?: ModerationFlags | null
+  // Generation metadata (assistant messages only)
+  model?: string | null
+  prompt_tokens?: number | null
+  completion_tokens?: number | null
+  cached_tokens?: number | null
+  reasoning_tokens?: number | null
+  cost_usd?: number | null
+  latency_ms?: number | null
+  error?: string | null
+  created_at: string
+}
+
+export interface ModerationFlags {
+  flagged: boolean
+  categories: Record<string, number>
+}
+
+export interface SessionSummary {
+  id: string
+  session_id: string
+  content: string
+  last_message_id: string
+  message_count: number
   created_at: string
 }
 
 export interface SessionWithMessages {
   session: Session
   messages: Message[]
+  summary?: SessionSummary
+  prompt_cache: PromptCacheStats
+}
+
+export interface PromptCacheStats {
+  prompt_tokens: number
+  cached_tokens: number
+  // cached_tokens / prompt_tokens; null until a reply with usage is saved
+  hit_ratio: number | null
 }
 
 export interface CreateSessionRequest {
   system_prompt?: string
+  model?: string
+  history_mode?: HistoryMode
+  history_strategy?: HistoryStrategy
+  generation?: GenerationOptions
 }
 
 export interface CreateSessionResponse {
   id: string
   system_prompt: string | null
+  model: string | null
+  history_mode: HistoryMode
+  history_strategy: HistoryStrategy
+  generation: GenerationOptions
   created_at: string
 }
 
-export interface SessionChatRequest {
+// Generation parameters override the session defaults for this turn only
+export interface SessionChatRequest extends GenerationOptions {
   message: string
+  bypass_cache?: boolean
 }
 
 export interface SessionChatResponse {
@@ -38,8 +117,41 @@ export interface SessionChatResponse {
   model: string
   session_id: string
   message_count: number
+  // USD; null when the model has no known price
+  cost_usd: number | null
+}
+
+export interface CostSummary {
+  calls: number
+  prompt_tokens: number
+  cached_tokens: number
+  completion_tokens: number
+  reasoning_tokens: number
+  cost_usd: number
+  // Calls whose model has no known price (not included in cost_usd)
+  unpriced_calls: number
+}
+
+export interface SessionCost {
+  session_id: string
+  total: CostSummary
+  models: ({ model: string } & CostSummary)[]
 }
 
+export interface MessageSearchResult extends Message {
+  score: number
+}
+
+export interface SearchResponse {
+  query: string
+  results: MessageSearchResult[]
+}
+
+export type SessionChatStreamEvent =
+  | { type: 'delta'; text: string }
+  | ({ type: 'completed' } & SessionChatResponse)
+  | { type: 'error'; code: string; message: string }
+
 // API client
 const API_BASE_URL = import.meta.env.VITE_API_URL || 'http://localhost:8080'
 
@@ -68,6 +180,20 @@ export const api = {
     if (!res.ok) throw new Error('Failed to delete session')
   },
 
+  async getSessionCost(id: string): Promise<SessionCost> {
+    const res = await fetch(`${API_BASE_URL}/sessions/${id}/cost`)
+    if (!res.ok) throw new Error('Failed to get session cost')
+    return res.json()
+  },
+
+  async searchMessages(q: string, limit?: number): Promise<SearchResponse> {
+    const params = new URLSearchParams({ q })
+    if (limit !== undefined) params.set('limit', String(limit))
+    const res = await fetch(`${API_BASE_URL}/search?${params}`)
+    if (!res.ok) throw new Error('Failed to search messages')
+    return res.json()
+  },
+
   async sendMessage(sessionId: string, message: string): Promise<SessionChatResponse> {
     const res = await fetch(`${API_BASE_URL}/sessions/${sessionId}/chat`, {
       method: 'POST',
@@ -78,6 +204,51 @@ export const api = {
     return res.json()
   },
 
+  // Streams assistant tokens via SSE; resolves with the final response after the turn is saved
+  async sendMessageStream(
+    sessionId: string,
+    message: string,
+    onDelta: (text: string) => void,
+    signal?: AbortSignal
+  ): Promise<SessionChatResponse> {
+    const res = await fetch(`${API_BASE_URL}/sessions/${sessionId}/chat/stream`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ message }),
+      signal,
+    })
+    if (!res.ok || !res.body) throw new Error('Failed to send message')
+
+    const reader = res.body.pipeThrough(new TextDecoderStream()).getReader()
+    let buffer = ''
+    for (;;) {
+      const { value, done } = await reader.read()
+      if (done) break
+      buffer += value
+
+      // SSE events are separated by a blank line
+      let boundary = buffer.indexOf('\n\n')
+      while (boundary !== -1) {
+        const raw = buffer.slice(0, boundary)
+        buffer = buffer.slice(boundary + 2)
+        boundary = buffer.indexOf('\n\n')
+
+        const data = raw
+          .split('\n')
+          .filter((line) => line.startsWith('data:'))
+          .map((line) => line.slice(5).trimStart())
+          .join('\n')
+        if (!data) continue
+
+        const event = JSON.parse(data) as SessionChatStreamEvent
+        if (event.type === 'delta') onDelta(event.text)
+        else if (event.type === 'completed') return event
+        else throw new Error(event.message)
+      }
+    }
+    throw new Error('Stream ended unexpectedly')
+  },
+
   // Health check
   async healthCheck(): Promise<{ status: string; version: string }> {
     const res = await fetch(`${API_BASE_URL}/health`)